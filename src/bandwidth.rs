@@ -0,0 +1,127 @@
+//! Limiting the bandwidth of downloads with the means each tool offers.
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use anyhow::{anyhow, Result};
+use directories::BaseDirs;
+use tempfile::TempDir;
+
+use crate::utils::which;
+
+/// Parse a rate such as `5MB/s`, `500K` or `1048576` into bytes per second. Units are powers of 1024.
+pub fn parse_limit(limit: &str) -> Result<u64> {
+    let trimmed = limit.trim();
+    let trimmed = trimmed.strip_suffix("/s").unwrap_or(trimmed);
+    let trimmed = trimmed.strip_suffix(|c| c == 'B' || c == 'b').unwrap_or(trimmed);
+
+    let (number, multiplier) = match trimmed.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&trimmed[..trimmed.len() - 1], 1 << 10),
+        Some('M') => (&trimmed[..trimmed.len() - 1], 1 << 20),
+        Some('G') => (&trimmed[..trimmed.len() - 1], 1 << 30),
+        _ => (trimmed, 1),
+    };
+
+    let number: f64 = number
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("Invalid bandwidth limit {}. Expected a rate such as 5MB/s", limit))?;
+    if !number.is_finite() || number <= 0.0 {
+        return Err(anyhow!("The bandwidth limit must be positive"));
+    }
+
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// The limit in kilobytes per second, as most tools take it
+pub fn kilobytes(limit: u64) -> u64 {
+    (limit / 1024).max(1)
+}
+
+/// The command prefix running a program under trickle, which limits programs which don't offer limiting themselves
+fn trickle_prefix(limit: u64) -> Option<Vec<OsString>> {
+    let trickle = which("trickle")?;
+
+    Some(vec![
+        trickle.into_os_string(),
+        OsString::from("-s"),
+        OsString::from("-d"),
+        OsString::from(kilobytes(limit).to_string()),
+    ])
+}
+
+/// Limits the downloads of the commands it creates. They run under trickle when it's installed, and curl and wget
+/// read configuration files limiting them, into which the user's own configuration is copied.
+pub struct Limiter {
+    trickle: Option<Vec<OsString>>,
+    variables: Vec<(&'static str, PathBuf)>,
+    /// Holds the configuration files, which have to exist until the commands finish
+    _directory: TempDir,
+}
+
+impl Limiter {
+    pub fn new(base_dirs: &BaseDirs, limit: u64) -> Result<Self> {
+        let directory = tempfile::Builder::new().prefix("topgrade-").tempdir()?;
+        let home = base_dirs.home_dir();
+
+        let curlrc = directory.path().join(".curlrc");
+        let mut contents = fs::read_to_string(home.join(".curlrc")).unwrap_or_default();
+        contents.push_str(&format!("\nlimit-rate = {}K\n", kilobytes(limit)));
+        fs::write(&curlrc, contents)?;
+
+        let wgetrc = directory.path().join("wgetrc");
+        let mut contents = fs::read_to_string(home.join(".wgetrc")).unwrap_or_default();
+        contents.push_str(&format!("\nlimit_rate = {}k\n", kilobytes(limit)));
+        fs::write(&wgetrc, contents)?;
+
+        Ok(Self {
+            trickle: trickle_prefix(limit),
+            variables: vec![("CURL_HOME", directory.path().to_owned()), ("WGETRC", wgetrc)],
+            _directory: directory,
+        })
+    }
+
+    /// A command running the program with the limit
+    pub fn command(&self, program: &OsStr) -> Command {
+        let mut command = match &self.trickle {
+            Some(trickle) => {
+                let mut command = Command::new(&trickle[0]);
+                command.args(&trickle[1..]).arg(program);
+                command
+            }
+            None => Command::new(program),
+        };
+        command.envs(self.variables.iter().cloned());
+        command
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_limit() {
+        assert_eq!(parse_limit("1048576").unwrap(), 1 << 20);
+        assert_eq!(parse_limit("500K").unwrap(), 500 << 10);
+        assert_eq!(parse_limit("500k").unwrap(), 500 << 10);
+        assert_eq!(parse_limit("5MB/s").unwrap(), 5 << 20);
+        assert_eq!(parse_limit("1.5M").unwrap(), 3 << 19);
+        assert_eq!(parse_limit(" 2 G ").unwrap(), 2 << 30);
+        assert_eq!(parse_limit("100b/s").unwrap(), 100);
+    }
+
+    #[test]
+    fn test_parse_invalid_limit() {
+        for invalid in ["", "fast", "0", "-5M", "5T", "inf", "NaN", "MB/s"] {
+            assert!(parse_limit(invalid).is_err(), "{:?} was accepted", invalid);
+        }
+    }
+
+    #[test]
+    fn test_kilobytes() {
+        assert_eq!(kilobytes(5 << 20), 5 << 10);
+        assert_eq!(kilobytes(100), 1);
+    }
+}
@@ -1,21 +1,33 @@
 #![allow(dead_code)]
+use std::cmp::Ordering;
 use std::collections::BTreeMap;
 use std::fs::write;
-use std::path::PathBuf;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::str::FromStr;
+use std::time::Duration;
 use std::{env, fs};
 
-use anyhow::Result;
-use clap::{ArgEnum, Parser};
+use anyhow::{anyhow, Result};
+use clap::{ArgEnum, Parser, Subcommand};
+use console::Key;
 use directories::BaseDirs;
+use lazy_static::lazy_static;
 use log::debug;
 use regex::Regex;
 use serde::Deserialize;
-use strum::{EnumIter, EnumString, EnumVariantNames, IntoEnumIterator};
+use strum::{AsRefStr, EnumIter, EnumString, EnumVariantNames, IntoEnumIterator, VariantNames};
 use sys_info::hostname;
 use which_crate::which;
 
+use super::bandwidth;
+use super::config_diff;
+use super::config_migration;
+use super::reboot_schedule::RebootWindow;
+use super::terminal::{get_key, is_dumb, print_warning, prompt_yesno};
 use super::utils::editor;
+use super::wizard;
 
 pub static EXAMPLE_CONFIG: &str = include_str!("../config.example.toml");
 
@@ -31,18 +43,31 @@ macro_rules! str_value {
     };
 }
 
-macro_rules! check_deprecated {
-    ($config:expr, $old:ident, $section:ident, $new:ident) => {
-        if $config.$old.is_some() {
-            println!(concat!(
-                "'",
-                stringify!($old),
-                "' configuration option is deprecated. Rename it to '",
-                stringify!($new),
-                "' and put it under the section [",
-                stringify!($section),
-                "]",
-            ));
+/// Declares the options replaced by other ones, as `(old, section, new)`. This defines the `DEPRECATED` table
+/// and `ConfigFile::check_deprecated`, which warns about the ones still in use.
+macro_rules! deprecated_options {
+    ($(($old:ident, $section:ident, $new:ident)),* $(,)?) => {
+        /// Options replaced by other ones, with their replacements
+        pub const DEPRECATED: &[(&str, &str)] = &[
+            $((stringify!($old), concat!(stringify!($section), ".", stringify!($new)))),*
+        ];
+
+        impl ConfigFile {
+            fn check_deprecated(&self) {
+                $(
+                    if self.$old.is_some() {
+                        println!(concat!(
+                            "'",
+                            stringify!($old),
+                            "' configuration option is deprecated. Rename it to '",
+                            stringify!($new),
+                            "' and put it under the section [",
+                            stringify!($section),
+                            "], or run `topgrade --migrate-config`",
+                        ));
+                    }
+                )*
+            }
         }
     };
 }
@@ -62,13 +87,16 @@ macro_rules! get_deprecated {
 
 type Commands = BTreeMap<String, String>;
 
-#[derive(ArgEnum, EnumString, EnumVariantNames, Debug, Clone, PartialEq, Deserialize, EnumIter, Copy)]
+#[derive(ArgEnum, EnumString, EnumVariantNames, AsRefStr, Debug, Clone, PartialEq, Deserialize, EnumIter, Copy)]
 #[clap(rename_all = "snake_case")]
 #[serde(rename_all = "snake_case")]
 #[strum(serialize_all = "snake_case")]
 pub enum Step {
     Asdf,
     Atom,
+    Aws,
+    AzureCli,
+    Bazel,
     BrewCask,
     BrewFormula,
     Bin,
@@ -76,6 +104,7 @@ pub enum Step {
     Chezmoi,
     Chocolatey,
     Choosenim,
+    Cleanup,
     Composer,
     Conda,
     ConfigUpdate,
@@ -83,10 +112,13 @@ pub enum Step {
     CustomCommands,
     DebGet,
     Deno,
+    Devbox,
+    Devenv,
     Dotnet,
     Emacs,
     Firmware,
     Flatpak,
+    Flox,
     Flutter,
     Fossil,
     Gcloud,
@@ -96,14 +128,18 @@ pub enum Step {
     Go,
     Haxelib,
     GnomeShellExtensions,
+    Hg,
     HomeManager,
     Jetpack,
     Kakoune,
     Krew,
+    LanguageServers,
     Macports,
     Mas,
     Micro,
+    MicrosoftStore,
     Myrepos,
+    NetworkDevices,
     Nix,
     Node,
     Opam,
@@ -114,8 +150,10 @@ pub enum Step {
     Pkg,
     Pkgin,
     Powershell,
+    PreCommit,
     Raco,
     Remotes,
+    Repo,
     Restarts,
     Rtcl,
     Rustup,
@@ -127,26 +165,256 @@ pub enum Step {
     Sparkle,
     Spicetify,
     Stack,
+    Svn,
     System,
+    Terminals,
+    Terraform,
     Tldr,
     Tlmgr,
     Tmux,
+    ToolchainCheck,
     Toolbx,
     Vagrant,
     Vcpkg,
     Vim,
+    Vscode,
+    West,
     Winget,
     Wsl,
     Yadm,
 }
 
+impl Step {
+    /// Whether the commands of the step may prompt the user or take over the terminal, as config-update or the
+    /// remotes do. Their output is neither recorded nor watched, so that they keep the terminal.
+    pub fn is_interactive(self) -> bool {
+        matches!(
+            self,
+            Step::ConfigUpdate | Step::Remotes | Step::Restarts | Step::Toolbx | Step::Vagrant | Step::Wsl
+        )
+    }
+}
+
+/// A git repository entry. Either a plain path (or glob) or a table with per-repository options.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum GitRepo {
+    Path(String),
+    Detailed(GitRepoDetails),
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct GitRepoDetails {
+    path: String,
+    lfs: Option<bool>,
+    annex: Option<bool>,
+    stash_dirty: Option<bool>,
+    push_after_pull: Option<bool>,
+    arguments: Option<String>,
+    remote: Option<String>,
+}
+
+impl GitRepo {
+    /// The path or glob pattern of the repository
+    pub fn path(&self) -> &str {
+        match self {
+            GitRepo::Path(path) => path,
+            GitRepo::Detailed(details) => &details.path,
+        }
+    }
+
+    fn path_mut(&mut self) -> &mut String {
+        match self {
+            GitRepo::Path(path) => path,
+            GitRepo::Detailed(details) => &mut details.path,
+        }
+    }
+
+    /// Whether to run `git lfs pull` after pulling
+    pub fn lfs(&self) -> bool {
+        match self {
+            GitRepo::Path(_) => false,
+            GitRepo::Detailed(details) => details.lfs.unwrap_or(false),
+        }
+    }
+
+    /// Whether to run `git annex sync --content` after pulling
+    pub fn annex(&self) -> bool {
+        match self {
+            GitRepo::Path(_) => false,
+            GitRepo::Detailed(details) => details.annex.unwrap_or(false),
+        }
+    }
+
+    /// Arguments for `git pull` of this repository, replacing `--ff-only` and the global arguments
+    pub fn arguments(&self) -> Option<&str> {
+        match self {
+            GitRepo::Path(_) => None,
+            GitRepo::Detailed(details) => details.arguments.as_deref(),
+        }
+    }
+
+    /// The remote to pull from instead of the upstream of the current branch
+    pub fn remote(&self) -> Option<&str> {
+        match self {
+            GitRepo::Path(_) => None,
+            GitRepo::Detailed(details) => details.remote.as_deref(),
+        }
+    }
+
+    /// Whether to stash local changes and rebase instead of failing to pull, if set for this repository
+    pub fn stash_dirty(&self) -> Option<bool> {
+        match self {
+            GitRepo::Path(_) => None,
+            GitRepo::Detailed(details) => details.stash_dirty,
+        }
+    }
+
+    /// Whether to push local commits after pulling, if set for this repository
+    pub fn push_after_pull(&self) -> Option<bool> {
+        match self {
+            GitRepo::Path(_) => None,
+            GitRepo::Detailed(details) => details.push_after_pull,
+        }
+    }
+}
+
+/// A remote machine with Topgrade installed on it
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Remote {
+    host: String,
+    tags: Option<Vec<String>>,
+    preset: Option<RemotePreset>,
+    kubernetes_node: Option<String>,
+}
+
+/// Maintenance procedures for common types of remote machines
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum RemotePreset {
+    /// A Kubernetes node, such as a k3s one, which is drained before the upgrade and uncordoned after it
+    K3sNode,
+}
+
+impl Remote {
+    fn untagged(host: &str) -> Self {
+        Self {
+            host: host.to_string(),
+            tags: None,
+            preset: None,
+            kubernetes_node: None,
+        }
+    }
+
+    /// The host to connect to
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// The tags of the remote
+    pub fn tags(&self) -> &[String] {
+        self.tags.as_deref().unwrap_or_default()
+    }
+
+    /// The maintenance procedure of the remote
+    pub fn preset(&self) -> Option<RemotePreset> {
+        self.preset
+    }
+
+    /// The name of the remote in the Kubernetes cluster. Defaults to the host.
+    pub fn kubernetes_node(&self) -> &str {
+        self.kubernetes_node.as_deref().unwrap_or(&self.host)
+    }
+}
+
+/// A router, a firewall or a homelab service whose updates are checked through its HTTP API
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkDevice {
+    kind: NetworkDeviceKind,
+    url: String,
+    credentials_file: Option<String>,
+    credentials_env: Option<String>,
+    insecure: Option<bool>,
+    install: Option<bool>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkDeviceKind {
+    Opnsense,
+    Pfsense,
+    Routeros,
+    Homeassistant,
+    Pihole,
+}
+
+impl NetworkDevice {
+    pub fn kind(&self) -> NetworkDeviceKind {
+        self.kind
+    }
+
+    /// The base URL of the web interface
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// A file holding the credentials: `user:password`, a Home Assistant access token or a Pi-hole password
+    pub fn credentials_file(&self) -> Option<&str> {
+        self.credentials_file.as_deref()
+    }
+
+    /// An environment variable holding the credentials, in the same format as `credentials_file`
+    pub fn credentials_env(&self) -> Option<&str> {
+        self.credentials_env.as_deref()
+    }
+
+    /// Whether to accept self-signed certificates
+    pub fn insecure(&self) -> bool {
+        self.insecure.unwrap_or(false)
+    }
+
+    /// Whether to install available updates. Only Home Assistant supports it.
+    pub fn install(&self) -> bool {
+        self.install.unwrap_or(false)
+    }
+}
+
+/// Commands to run immediately before and after a step
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct StepHooks {
+    pre: Option<String>,
+    post: Option<String>,
+}
+
+impl StepHooks {
+    /// Run before the first command of the step. The step isn't run if it fails.
+    pub fn pre(&self) -> Option<&str> {
+        self.pre.as_deref()
+    }
+
+    /// Run after the step, even if the step failed
+    pub fn post(&self) -> Option<&str> {
+        self.post.as_deref()
+    }
+}
+
 #[derive(Deserialize, Default, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct Git {
     max_concurrency: Option<usize>,
     arguments: Option<String>,
-    repos: Option<Vec<String>>,
+    repos: Option<Vec<GitRepo>>,
     pull_predefined: Option<bool>,
+    ghq: Option<bool>,
+    ghq_exclude: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+    stash_dirty: Option<bool>,
+    push_after_pull: Option<bool>,
+    pull_timeout: Option<String>,
 }
 
 #[derive(Deserialize, Default, Debug)]
@@ -157,6 +425,55 @@ pub struct Vagrant {
     always_suspend: Option<bool>,
 }
 
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Repo {
+    directories: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Devenv {
+    directories: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Terraform {
+    tfenv_install_latest: Option<bool>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Bazel {
+    disk_cache: Option<String>,
+    disk_cache_max_size_mb: Option<u64>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Hg {
+    directories: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Svn {
+    directories: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct West {
+    directories: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct PreCommit {
+    directories: Option<Vec<String>>,
+}
+
 #[derive(Deserialize, Default, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct Windows {
@@ -164,6 +481,9 @@ pub struct Windows {
     self_rename: Option<bool>,
     open_remotes_in_new_terminal: Option<bool>,
     enable_winget: Option<bool>,
+    chocolatey_arguments: Option<String>,
+    chocolatey_use_sudo: Option<bool>,
+    wsl_distributions: Option<Vec<String>>,
 }
 
 #[derive(Deserialize, Default, Debug)]
@@ -178,6 +498,7 @@ pub struct NPM {
 #[allow(clippy::upper_case_acronyms)]
 pub struct Firmware {
     upgrade: Option<bool>,
+    devices: Option<Vec<String>>,
 }
 
 #[derive(Deserialize, Default, Debug)]
@@ -185,12 +506,186 @@ pub struct Firmware {
 #[allow(clippy::upper_case_acronyms)]
 pub struct Flatpak {
     use_sudo: Option<bool>,
+    exclude: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct HomeManager {
+    flake: Option<String>,
+    show_news: Option<bool>,
+    expire_generations: Option<String>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Containers {
+    runtime: Option<ContainerRuntime>,
+    exclude: Option<Vec<String>>,
+    compose_files: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Network {
+    limit: Option<String>,
+    unlimited_steps: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Notifications {
+    on: Option<NotifyOn>,
+    webhook: Option<String>,
+    webhook_format: Option<WebhookFormat>,
+    email: Option<String>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Secrets {
+    file: Option<String>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Terminals {
+    repos: Option<Vec<GitRepo>>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Snap {
+    arguments: Option<String>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Nix {
+    keep_generations: Option<NonZeroU32>,
+    keep_days: Option<u32>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Krew {
+    arguments: Option<String>,
+    indexes: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct LanguageServers {
+    rust_analyzer: Option<bool>,
+    clangd: Option<bool>,
+    lua_language_server: Option<bool>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Vscode {
+    binaries: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ToolchainCheck {
+    enable: Option<bool>,
+    tools: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Python {
+    upgrade_user_packages: Option<bool>,
+    exclude: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Conda {
+    environments: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Emacs {
+    update_function: Option<String>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Asdf {
+    bump_global_versions: Option<bool>,
 }
 
 #[derive(Deserialize, Default, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct Brew {
     greedy_cask: Option<bool>,
+    recent_update_minutes: Option<u64>,
+    restart_services: Option<bool>,
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq)]
+#[clap(rename_all = "snake_case")]
+pub enum ReportFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ContainerRuntime {
+    Docker,
+    Podman,
+}
+
+/// When to reboot at the end of the run
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RebootPolicy {
+    /// Only when chosen at the end of the run
+    Never,
+    /// When an upgrade requires it and no step failed
+    IfRequired,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Reboot {
+    policy: Option<RebootPolicy>,
+    window: Option<String>,
+}
+
+/// When to send a desktop notification at the end of the run
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifyOn {
+    Always,
+    Failure,
+    Never,
+}
+
+impl NotifyOn {
+    /// Whether to notify about a run which failed or succeeded
+    pub fn applies(self, failed: bool) -> bool {
+        match self {
+            NotifyOn::Always => true,
+            NotifyOn::Failure => failed,
+            NotifyOn::Never => false,
+        }
+    }
+}
+
+/// The payload posted to the webhook of `[notifications]`
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookFormat {
+    /// The JSON report with the host, the outcome and the duration of the run
+    Json,
+    Slack,
+    Discord,
 }
 
 #[derive(Debug, Deserialize, Clone, Copy)]
@@ -202,25 +697,53 @@ pub enum ArchPackageManager {
     Yay,
     Pacman,
     Pikaur,
+    Aura,
     Pamac,
 }
 
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AptTool {
+    Autodetect,
+    Apt,
+    AptGet,
+    AptFast,
+    Nala,
+}
+
 #[derive(Deserialize, Default, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct Linux {
     yay_arguments: Option<String>,
+    paru_arguments: Option<String>,
+    aur_devel: Option<bool>,
     arch_package_manager: Option<ArchPackageManager>,
     show_arch_news: Option<bool>,
     trizen_arguments: Option<String>,
     pikaur_arguments: Option<String>,
+    aura_arguments: Option<String>,
     pamac_arguments: Option<String>,
+    pamac_aur: Option<bool>,
     dnf_arguments: Option<String>,
+    dnf_refresh: Option<bool>,
     apt_arguments: Option<String>,
+    apt_tool: Option<AptTool>,
+    apt_dist_upgrade: Option<bool>,
+    apt_phased_updates: Option<bool>,
+    xbps_arguments: Option<String>,
     enable_tlmgr: Option<bool>,
     redhat_distro_sync: Option<bool>,
     rpm_ostree: Option<bool>,
+    transactional_update: Option<bool>,
     emerge_sync_flags: Option<String>,
     emerge_update_flags: Option<String>,
+    emerge_preserved_rebuild: Option<bool>,
+    emerge_depclean: Option<bool>,
+    eclean: Option<bool>,
+    nixos_flake: Option<String>,
+    nixos_use_remote_sudo: Option<bool>,
+    journalctl_vacuum_time: Option<String>,
+    restart_user_units: Option<BTreeMap<String, Vec<String>>>,
 }
 
 #[derive(Deserialize, Default, Debug)]
@@ -233,21 +756,30 @@ pub struct Composer {
 #[serde(deny_unknown_fields)]
 pub struct Vim {
     force_plug_update: Option<bool>,
+    update_mason: Option<bool>,
+    appnames: Option<Vec<String>>,
 }
 
 #[derive(Deserialize, Default, Debug)]
 #[serde(deny_unknown_fields)]
 /// Configuration file
 pub struct ConfigFile {
+    include: Option<Vec<String>>,
     pre_commands: Option<Commands>,
     post_commands: Option<Commands>,
+    post_change_commands: Option<Commands>,
     commands: Option<Commands>,
-    git_repos: Option<Vec<String>>,
+    hooks: Option<BTreeMap<String, StepHooks>>,
+    git_repos: Option<Vec<GitRepo>>,
     predefined_git_repos: Option<bool>,
-    disable: Option<Vec<Step>>,
-    ignore_failures: Option<Vec<Step>>,
+    disable: Option<Vec<String>>,
+    ignore_failures: Option<Vec<String>>,
+    inhibit_sleep: Option<Vec<String>>,
     remote_topgrades: Option<Vec<String>>,
+    remotes: Option<Vec<Remote>>,
+    network_devices: Option<Vec<NetworkDevice>>,
     remote_topgrade_path: Option<String>,
+    remote_summary: Option<bool>,
     ssh_arguments: Option<String>,
     git_arguments: Option<String>,
     tmux_arguments: Option<String>,
@@ -256,12 +788,23 @@ pub struct ConfigFile {
     assume_yes: Option<bool>,
     yay_arguments: Option<String>,
     no_retry: Option<bool>,
+    auto_retry: Option<u32>,
+    no_sudo: Option<bool>,
+    elevation_command: Option<String>,
+    auto_reboot: Option<bool>,
     run_in_tmux: Option<bool>,
     cleanup: Option<bool>,
+    package_lock_timeout: Option<u64>,
+    stall_timeout: Option<String>,
+    failure_output_lines: Option<usize>,
+    state_file: Option<bool>,
+    metrics_file: Option<String>,
     notify_each_step: Option<bool>,
+    notify_on: Option<NotifyOn>,
+    notification_command: Option<String>,
     accept_all_windows_updates: Option<bool>,
     bashit_branch: Option<String>,
-    only: Option<Vec<Step>>,
+    only: Option<Vec<String>>,
     composer: Option<Composer>,
     brew: Option<Brew>,
     linux: Option<Linux>,
@@ -272,8 +815,43 @@ pub struct ConfigFile {
     firmware: Option<Firmware>,
     vagrant: Option<Vagrant>,
     flatpak: Option<Flatpak>,
+    containers: Option<Containers>,
+    snap: Option<Snap>,
+    terminals: Option<Terminals>,
+    network: Option<Network>,
+    notifications: Option<Notifications>,
+    reboot: Option<Reboot>,
+    changelogs: Option<BTreeMap<String, String>>,
+    priorities: Option<BTreeMap<String, i32>>,
+    secrets: Option<Secrets>,
+    nix: Option<Nix>,
+    asdf: Option<Asdf>,
+    krew: Option<Krew>,
+    language_servers: Option<LanguageServers>,
+    python: Option<Python>,
+    conda: Option<Conda>,
+    emacs: Option<Emacs>,
+    home_manager: Option<HomeManager>,
+    repo: Option<Repo>,
+    west: Option<West>,
+    hg: Option<Hg>,
+    svn: Option<Svn>,
+    devenv: Option<Devenv>,
+    bazel: Option<Bazel>,
+    terraform: Option<Terraform>,
+    pre_commit: Option<PreCommit>,
+    toolchain_check: Option<ToolchainCheck>,
+    vscode: Option<Vscode>,
 }
 
+deprecated_options!(
+    (git_arguments, git, arguments),
+    (git_repos, git, repos),
+    (predefined_git_repos, git, pull_predefined),
+    (yay_arguments, linux, yay_arguments),
+    (accept_all_windows_updates, windows, accept_all_updates),
+);
+
 fn config_directory(base_dirs: &BaseDirs) -> PathBuf {
     #[cfg(not(target_os = "macos"))]
     return base_dirs.config_dir().to_owned();
@@ -282,72 +860,471 @@ fn config_directory(base_dirs: &BaseDirs) -> PathBuf {
     return base_dirs.home_dir().join(".config");
 }
 
-impl ConfigFile {
-    fn ensure(base_dirs: &BaseDirs) -> Result<PathBuf> {
-        let config_directory = config_directory(base_dirs);
-
-        let config_path = config_directory.join("topgrade.toml");
-
-        if !config_path.exists() {
-            debug!("No configuration exists");
-            write(&config_path, EXAMPLE_CONFIG).map_err(|e| {
-                debug!(
-                    "Unable to write the example configuration file to {}: {}. Using blank config.",
-                    config_path.display(),
-                    e
-                );
-                e
-            })?;
-        } else {
-            debug!("Configuration at {}", config_path.display());
-        }
-
-        Ok(config_path)
+/// Replace `${env:VAR}` by the value of the environment variable and `${hostname}` by the name of this host.
+/// Other `${...}` are left alone, since commands use them for shell variables.
+fn interpolate(value: &str) -> Result<String> {
+    lazy_static! {
+        static ref PLACEHOLDER: Regex = Regex::new(r"\$\{(env:([A-Za-z_][A-Za-z0-9_]*)|hostname)\}").unwrap();
     }
 
-    /// Read the configuration file.
-    ///
-    /// If the configuration file does not exist the function returns the default ConfigFile.
-    fn read(base_dirs: &BaseDirs, config_path: Option<PathBuf>) -> Result<ConfigFile> {
-        let config_path = if let Some(path) = config_path {
-            path
-        } else {
-            Self::ensure(base_dirs)?
+    let mut error = None;
+    let interpolated = PLACEHOLDER.replace_all(value, |captures: &regex::Captures| {
+        let result = match captures.get(2) {
+            Some(variable) => env::var(variable.as_str()).map_err(|_| {
+                anyhow!(
+                    "The environment variable {} in the configuration isn't set",
+                    variable.as_str()
+                )
+            }),
+            None => hostname().map_err(|e| anyhow!("Failed getting the hostname: {}", e)),
         };
+        result.unwrap_or_else(|e| {
+            error.get_or_insert(e);
+            String::new()
+        })
+    });
 
-        let contents = fs::read_to_string(&config_path).map_err(|e| {
-            log::error!("Unable to read {}", config_path.display());
-            e
-        })?;
-
-        let mut result: Self = toml::from_str(&contents).map_err(|e| {
-            log::error!("Failed to deserialize {}", config_path.display());
-            e
-        })?;
+    match error {
+        Some(e) => Err(e),
+        None => Ok(interpolated.into_owned()),
+    }
+}
 
-        if let Some(ref mut paths) = &mut result.git_repos {
-            for path in paths.iter_mut() {
-                let expanded = shellexpand::tilde::<&str>(&path.as_ref()).into_owned();
-                debug!("Path {} expanded to {}", path, expanded);
-                *path = expanded;
+/// Interpolate every string of the configuration
+fn interpolate_toml(value: &mut toml::Value) -> Result<()> {
+    match value {
+        toml::Value::String(string) => *string = interpolate(string)?,
+        toml::Value::Array(values) => {
+            for value in values {
+                interpolate_toml(value)?;
             }
         }
-
-        if let Some(paths) = result.git.as_mut().and_then(|git| git.repos.as_mut()) {
-            for path in paths.iter_mut() {
-                let expanded = shellexpand::tilde::<&str>(&path.as_ref()).into_owned();
-                debug!("Path {} expanded to {}", path, expanded);
-                *path = expanded;
+        toml::Value::Table(table) => {
+            for (_, value) in table.iter_mut() {
+                interpolate_toml(value)?;
             }
         }
+        _ => (),
+    }
 
-        debug!("Loaded configuration: {:?}", result);
+    Ok(())
+}
+
+/// Merge `fragment` over `base`
+fn merge_toml(base: &mut toml::Value, fragment: toml::Value) {
+    match (base, fragment) {
+        (toml::Value::Table(base), toml::Value::Table(fragment)) => {
+            for (key, value) in fragment {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_toml(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (toml::Value::Array(base), toml::Value::Array(fragment)) => base.extend(fragment),
+        (base, fragment) => *base = fragment,
+    }
+}
+
+/// Expand the tilde in every path of the list.
+fn expand_paths(paths: Option<&mut Vec<String>>) {
+    for path in paths.into_iter().flatten() {
+        expand_path(Some(path));
+    }
+}
+
+fn expand_path(path: Option<&mut String>) {
+    if let Some(path) = path {
+        let expanded = shellexpand::tilde::<&str>(&path.as_ref()).into_owned();
+        debug!("Path {} expanded to {}", path, expanded);
+        *path = expanded;
+    }
+}
+
+/// Expand the tilde in the path of every git repository in the list.
+fn expand_repo_paths(repos: Option<&mut Vec<GitRepo>>) {
+    for repo in repos.into_iter().flatten() {
+        let path = repo.path_mut();
+        let expanded = shellexpand::tilde::<&str>(&path.as_ref()).into_owned();
+        debug!("Path {} expanded to {}", path, expanded);
+        *path = expanded;
+    }
+}
+
+/// Parse a duration such as `90`, `60s`, `5m`, `1h` or `7d`. Plain numbers are seconds.
+fn parse_duration(duration: &str) -> Result<Duration> {
+    let trimmed = duration.trim();
+    let (number, multiplier) = match trimmed.chars().last() {
+        Some('s') => (&trimmed[..trimmed.len() - 1], 1),
+        Some('m') => (&trimmed[..trimmed.len() - 1], 60),
+        Some('h') => (&trimmed[..trimmed.len() - 1], 60 * 60),
+        Some('d') => (&trimmed[..trimmed.len() - 1], 24 * 60 * 60),
+        _ => (trimmed, 1),
+    };
+
+    let number: u64 = number
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("Invalid duration {}. Expected a duration such as 60s or 5m", duration))?;
+    if number == 0 {
+        return Err(anyhow!("The duration must be positive"));
+    }
+    let seconds = number
+        .checked_mul(multiplier)
+        .ok_or_else(|| anyhow!("The duration {} is too long", duration))?;
+
+    Ok(Duration::from_secs(seconds))
+}
+
+/// The steps which take the arguments of `--step-arg`
+const STEPS_WITH_ARGUMENTS: &[&str] = &[
+    "system",
+    "git_repos",
+    "krew",
+    "snap",
+    "chocolatey",
+    "cargo",
+    "brew_formula",
+];
+
+/// Parse the `step=arguments` value of `--step-arg`
+fn parse_step_arguments(value: &str) -> Result<(Step, String)> {
+    let (step, arguments) = value
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Expected step=arguments, got {}", value))?;
+    let step = <Step as FromStr>::from_str(step).map_err(|_| anyhow!("Unknown step {}", step))?;
+
+    Ok((step, arguments.to_string()))
+}
+
+/// Append arguments to a passthrough arguments option, which takes `default` when it isn't set
+fn append_arguments(option: &mut Option<String>, default: &str, arguments: &str) {
+    let mut merged = option.take().unwrap_or_else(|| default.to_string());
+    if !merged.is_empty() {
+        merged.push(' ');
+    }
+    merged.push_str(arguments);
+    *option = Some(merged);
+}
+
+impl ConfigFile {
+    /// Append arguments given on the command line to the passthrough arguments of the step
+    fn append_step_arguments(&mut self, step: Step, arguments: &str) -> Result<()> {
+        match step {
+            Step::System => {
+                if self.yay_arguments.is_some() {
+                    append_arguments(&mut self.yay_arguments, "", arguments);
+                }
+
+                let linux = self.linux.get_or_insert_with(Linux::default);
+                if self.yay_arguments.is_none() {
+                    append_arguments(&mut linux.yay_arguments, "", arguments);
+                }
+                // Without their own arguments, paru uses the ones of yay
+                if linux.paru_arguments.is_some() {
+                    append_arguments(&mut linux.paru_arguments, "", arguments);
+                }
+                append_arguments(&mut linux.trizen_arguments, "", arguments);
+                append_arguments(&mut linux.pikaur_arguments, "", arguments);
+                append_arguments(&mut linux.aura_arguments, "", arguments);
+                append_arguments(&mut linux.pamac_arguments, "", arguments);
+                append_arguments(&mut linux.dnf_arguments, "", arguments);
+                append_arguments(&mut linux.apt_arguments, "", arguments);
+                append_arguments(&mut linux.xbps_arguments, "", arguments);
+                append_arguments(&mut linux.emerge_update_flags, "-uDNa --with-bdeps=y world", arguments);
+            }
+            Step::GitRepos => {
+                if self.git_arguments.is_some() {
+                    append_arguments(&mut self.git_arguments, "", arguments);
+                } else {
+                    let git = self.git.get_or_insert_with(Git::default);
+                    append_arguments(&mut git.arguments, "", arguments);
+                }
+            }
+            Step::Krew => {
+                let krew = self.krew.get_or_insert_with(Krew::default);
+                append_arguments(&mut krew.arguments, "", arguments);
+            }
+            Step::Snap => {
+                let snap = self.snap.get_or_insert_with(Snap::default);
+                append_arguments(&mut snap.arguments, "", arguments);
+            }
+            Step::Chocolatey => {
+                let windows = self.windows.get_or_insert_with(Windows::default);
+                append_arguments(&mut windows.chocolatey_arguments, "", arguments);
+            }
+            // Steps without passthrough arguments in the configuration read them with `Config::step_arguments`
+            Step::Cargo | Step::BrewFormula => (),
+            _ => {
+                return Err(anyhow!(
+                    "The {} step doesn't take arguments. Arguments can be given to {}",
+                    step.as_ref(),
+                    STEPS_WITH_ARGUMENTS.join(", ")
+                ))
+            }
+        }
+
+        Ok(())
+    }
+
+    fn ensure(base_dirs: &BaseDirs, opt: &CommandLineArgs) -> Result<PathBuf> {
+        let config_directory = config_directory(base_dirs);
+
+        let config_path = config_directory.join("topgrade.toml");
+
+        if !config_path.exists() {
+            debug!("No configuration exists");
+            let contents = Self::first_run_contents(opt);
+            write(&config_path, contents).map_err(|e| {
+                debug!(
+                    "Unable to write the example configuration file to {}: {}. Using blank config.",
+                    config_path.display(),
+                    e
+                );
+                e
+            })?;
+        } else {
+            debug!("Configuration at {}", config_path.display());
+        }
+
+        Ok(config_path)
+    }
+
+    /// The contents of the configuration file written on the first run. The setup wizard is offered when there's
+    /// a terminal to run it in and the run is attended, and the example configuration is written otherwise. Runs
+    /// with `--yes`, dry runs and runs on remotes, which are started by another Topgrade, aren't considered
+    /// attended.
+    fn first_run_contents(opt: &CommandLineArgs) -> String {
+        let unattended = opt.yes.is_some() || opt.dry_run || env::var_os("TOPGRADE_PREFIX").is_some();
+        if is_dumb() || unattended {
+            return String::from(EXAMPLE_CONFIG);
+        }
+
+        match prompt_yesno("No configuration file was found. Run the setup wizard?") {
+            Ok(true) => (),
+            _ => {
+                println!();
+                return String::from(EXAMPLE_CONFIG);
+            }
+        }
+        println!();
+
+        wizard::run().unwrap_or_else(|_| {
+            print_warning("The setup wizard was aborted. Writing the example configuration");
+            String::from(EXAMPLE_CONFIG)
+        })
+    }
+
+    /// Merge the files matching the include patterns over the configuration, in alphabetical order. Tables are
+    /// merged, arrays are extended and other values are replaced.
+    fn merge_included(config_path: &Path, merged: &mut toml::Value, include: &[String]) -> Result<()> {
+        for pattern in include {
+            let pattern = interpolate(pattern)?;
+            let pattern = shellexpand::tilde(&pattern);
+            // Relative patterns are relative to the directory of the configuration file
+            let pattern = match config_path.parent() {
+                Some(directory) if Path::new(pattern.as_ref()).is_relative() => directory.join(pattern.as_ref()),
+                _ => PathBuf::from(pattern.as_ref()),
+            };
+
+            for path in glob::glob(&pattern.to_string_lossy())? {
+                let path = path?;
+                debug!("Including {}", path.display());
+                let contents = fs::read_to_string(&path)?;
+
+                // Parsing the file on its own points at the line of errors
+                let fragment: ConfigFile =
+                    toml::from_str(&contents).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+                if fragment.include.is_some() {
+                    return Err(anyhow!("{} can't include other files", path.display()));
+                }
+
+                let mut fragment = toml::from_str(&contents)?;
+                interpolate_toml(&mut fragment)?;
+                merge_toml(merged, fragment);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read the configuration file.
+    ///
+    /// If the configuration file does not exist the function returns the default ConfigFile.
+    fn read(base_dirs: &BaseDirs, opt: &CommandLineArgs, config_path: Option<PathBuf>) -> Result<ConfigFile> {
+        let config_path = if let Some(path) = config_path {
+            path
+        } else {
+            Self::ensure(base_dirs, opt)?
+        };
+
+        let contents = fs::read_to_string(&config_path).map_err(|e| {
+            log::error!("Unable to read {}", config_path.display());
+            e
+        })?;
+
+        let mut result: Self = toml::from_str(&contents).map_err(|e| {
+            log::error!("Failed to deserialize {}", config_path.display());
+            e
+        })?;
+
+        // Interpolation and merging work on the TOML values, which are converted again afterwards
+        let mut merged: toml::Value = toml::from_str(&contents)?;
+        interpolate_toml(&mut merged)?;
+        if let Some(include) = result.include.take() {
+            Self::merge_included(&config_path, &mut merged, &include)?;
+        }
+        result = merged.try_into()?;
+        result.check_step_names()?;
+        if let Some(limit) = result.network.as_ref().and_then(|network| network.limit.as_ref()) {
+            bandwidth::parse_limit(limit)?;
+        }
+        if let Some(timeout) = result.git.as_ref().and_then(|git| git.pull_timeout.as_ref()) {
+            parse_duration(timeout)?;
+        }
+        if let Some(timeout) = result.stall_timeout.as_ref() {
+            parse_duration(timeout)?;
+        }
+        if let Some(window) = result.reboot.as_ref().and_then(|reboot| reboot.window.as_ref()) {
+            window.parse::<RebootWindow>()?;
+        }
+
+        expand_repo_paths(result.git_repos.as_mut());
+        expand_repo_paths(result.git.as_mut().and_then(|git| git.repos.as_mut()));
+        expand_repo_paths(result.terminals.as_mut().and_then(|terminals| terminals.repos.as_mut()));
+        expand_paths(result.git.as_mut().and_then(|git| git.ghq_exclude.as_mut()));
+        expand_paths(result.git.as_mut().and_then(|git| git.exclude.as_mut()));
+        expand_paths(result.repo.as_mut().and_then(|repo| repo.directories.as_mut()));
+        expand_paths(result.west.as_mut().and_then(|west| west.directories.as_mut()));
+        expand_paths(result.hg.as_mut().and_then(|hg| hg.directories.as_mut()));
+        expand_paths(result.svn.as_mut().and_then(|svn| svn.directories.as_mut()));
+        expand_paths(result.devenv.as_mut().and_then(|devenv| devenv.directories.as_mut()));
+        expand_paths(
+            result
+                .pre_commit
+                .as_mut()
+                .and_then(|pre_commit| pre_commit.directories.as_mut()),
+        );
+        expand_paths(
+            result
+                .containers
+                .as_mut()
+                .and_then(|containers| containers.compose_files.as_mut()),
+        );
+        expand_path(result.bazel.as_mut().and_then(|bazel| bazel.disk_cache.as_mut()));
+        expand_path(result.metrics_file.as_mut());
+        expand_path(result.secrets.as_mut().and_then(|secrets| secrets.file.as_mut()));
+        for device in result.network_devices.iter_mut().flatten() {
+            expand_path(device.credentials_file.as_mut());
+        }
+
+        debug!("Loaded configuration: {:?}", result);
 
         Ok(result)
     }
 
-    fn edit(base_dirs: &BaseDirs) -> Result<()> {
-        let config_path = Self::ensure(base_dirs)?;
+    fn custom_command_names(&self) -> impl Iterator<Item = &str> {
+        self.commands
+            .iter()
+            .flat_map(|commands| commands.keys())
+            .map(String::as_str)
+    }
+
+    /// Make sure that `only`, `disable` and `ignore_failures` only name existing steps, suggesting the closest name
+    /// otherwise. `only` and `disable` may also name custom commands.
+    fn check_step_names(&self) -> Result<()> {
+        let hooked_steps = self.hooks.as_ref().map(|hooks| hooks.keys().cloned().collect());
+        let restarting_steps = self
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.restart_user_units.as_ref())
+            .map(|units| units.keys().cloned().collect());
+        // Topgrade updates itself outside of the steps
+        let changelog_steps = self.changelogs.as_ref().map(|changelogs| {
+            changelogs
+                .keys()
+                .filter(|step| step.as_str() != "self_update")
+                .cloned()
+                .collect()
+        });
+        let prioritized_steps = self
+            .priorities
+            .as_ref()
+            .map(|priorities| priorities.keys().cloned().collect());
+        let unlimited_steps = self
+            .network
+            .as_ref()
+            .and_then(|network| network.unlimited_steps.clone());
+        let lists = [
+            ("only", &self.only, true),
+            ("disable", &self.disable, true),
+            ("ignore_failures", &self.ignore_failures, false),
+            ("inhibit_sleep", &self.inhibit_sleep, false),
+            ("hooks", &hooked_steps, false),
+            ("linux.restart_user_units", &restarting_steps, false),
+            ("changelogs", &changelog_steps, false),
+            ("priorities", &prioritized_steps, false),
+            ("network.unlimited_steps", &unlimited_steps, false),
+        ];
+
+        for (option, names, allow_custom_commands) in lists {
+            for name in names.iter().flatten() {
+                if <Step as FromStr>::from_str(name).is_ok()
+                    || (allow_custom_commands && self.custom_command_names().any(|command| command == name))
+                {
+                    continue;
+                }
+
+                let custom_commands = self.custom_command_names().filter(|_| allow_custom_commands);
+                let suggestion = closest_match(name, Step::VARIANTS.iter().copied().chain(custom_commands));
+
+                return Err(match suggestion {
+                    Some(candidate) => {
+                        anyhow!("Unknown step `{}` in `{}`. Did you mean `{}`?", name, option, candidate)
+                    }
+                    None => anyhow!("Unknown step `{}` in `{}`", name, option),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The glob patterns which don't parse, with their options
+    fn invalid_patterns(&self) -> Vec<String> {
+        let git = self.git.as_ref();
+        let repos: Vec<String> = self
+            .git_repos
+            .iter()
+            .chain(git.and_then(|git| git.repos.as_ref()))
+            .flatten()
+            .map(|repo| repo.path().to_string())
+            .collect();
+        let lists = [
+            ("git.repos", Some(&repos)),
+            ("git.exclude", git.and_then(|git| git.exclude.as_ref())),
+            ("git.ghq_exclude", git.and_then(|git| git.ghq_exclude.as_ref())),
+        ];
+
+        let mut problems = Vec::new();
+        for (option, patterns) in lists {
+            for pattern in patterns.into_iter().flatten() {
+                if let Err(e) = glob::Pattern::new(pattern) {
+                    problems.push(format!("Invalid pattern `{}` in `{}`: {}", pattern, option, e));
+                }
+            }
+        }
+
+        problems
+    }
+
+    fn edit(base_dirs: &BaseDirs, opt: &CommandLineArgs) -> Result<()> {
+        Self::open_in_editor(&Self::ensure(base_dirs, opt)?)
+    }
+
+    fn open_in_editor(config_path: &Path) -> Result<()> {
         let editor = editor();
         debug!("Editor: {:?}", editor);
 
@@ -375,6 +1352,28 @@ pub struct CommandLineArgs {
     #[clap(long = "config-reference")]
     show_config_reference: bool,
 
+    /// Compare the configuration file with the config reference: options set to non-default values, unused
+    /// options and deprecated ones
+    #[clap(long = "diff-config")]
+    diff_config: bool,
+
+    /// Validate the configuration file without running any step. Exits with code 1 if it's invalid
+    #[clap(long = "check-config")]
+    check_config: bool,
+
+    /// Move deprecated options of the configuration file into their sections, keeping a backup of the file
+    #[clap(long = "migrate-config")]
+    migrate_config: bool,
+
+    /// Install a service running Topgrade periodically with the rest of the given arguments
+    #[clap(long = "install-service")]
+    install_service: bool,
+
+    /// How often the installed service runs. A systemd calendar expression on Linux, `hourly`, `daily` or `weekly`
+    /// elsewhere
+    #[clap(long = "schedule", default_value = "daily")]
+    schedule: String,
+
     /// Run inside tmux
     #[clap(short = 't', long = "tmux")]
     run_in_tmux: bool,
@@ -411,7 +1410,14 @@ pub struct CommandLineArgs {
     #[clap(short = 'v', long = "verbose")]
     pub verbose: bool,
 
-    /// Prompt for a key before exiting
+    /// Ask before running each step. Steps which don't apply, e.g. because their package manager isn't installed,
+    /// are skipped without asking
+    #[clap(long = "ask-each")]
+    ask_each: bool,
+
+    /// Prompt for a key before exiting, offering to show the output of failed steps. On Windows, the output is
+    /// piped through Topgrade to record it, so some commands lose their colors or progress bars. Steps which may
+    /// prompt, such as config-update or the remotes, aren't recorded
     #[clap(short = 'k', long = "keep")]
     keep_at_end: bool,
 
@@ -427,13 +1433,112 @@ pub struct CommandLineArgs {
     #[clap(long = "config")]
     config: Option<PathBuf>,
 
+    /// Extra arguments for a step in this run, as `step=arguments`. They are appended to the arguments of the step in
+    /// the configuration file. Supported by system, git_repos, krew, snap, chocolatey, cargo and brew_formula
+    #[clap(long = "step-arg", parse(try_from_str = parse_step_arguments), multiple_occurrences(true))]
+    step_arguments: Vec<(Step, String)>,
+
+    /// Abort if the configuration file is invalid instead of offering to fix it
+    #[clap(long = "strict-config")]
+    strict_config: bool,
+
     /// A regular expression for restricting remote host execution
     #[clap(long = "remote-host-limit")]
     remote_host_limit: Option<Regex>,
 
+    /// Only upgrade remote hosts having one of the given tags
+    #[clap(long = "remote-tag", multiple_occurrences = true)]
+    remote_tags: Vec<String>,
+
+    /// List the configured remote hosts and whether they are reachable
+    #[clap(long = "list-remotes")]
+    list_remotes: bool,
+
     /// Show the reason for skipped steps
     #[clap(long = "show-skipped")]
     show_skipped: bool,
+
+    /// Skip steps which require elevation
+    #[clap(long = "no-sudo")]
+    no_sudo: bool,
+
+    /// Exit with code 2 if any step reported changes
+    #[clap(long = "exit-code-on-changes")]
+    exit_code_on_changes: bool,
+
+    /// The format of the summary. `json` prints it as a single line of JSON
+    #[clap(long = "report-format", arg_enum, default_value = "text")]
+    report_format: ReportFormat,
+
+    /// Choose the steps to run from a list before starting
+    #[clap(long = "interactive")]
+    interactive: bool,
+
+    /// Continue an interrupted run, skipping the steps which finished in it
+    #[clap(long = "resume")]
+    resume: bool,
+
+    /// Skip the remaining steps once the run took longer, such as `20m` or `1h`
+    #[clap(long = "max-duration", parse(try_from_str = parse_duration))]
+    max_duration: Option<Duration>,
+
+    /// Show the progress in a status icon, which shows the outcome until it's clicked
+    #[cfg(all(target_os = "linux", feature = "tray"))]
+    #[clap(long = "tray")]
+    tray: bool,
+
+    #[clap(subcommand)]
+    command: Option<SubCommand>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SubCommand {
+    /// Run a single step without the rest of the run
+    Run {
+        /// A step name (e.g. `cargo`), a title from the summary (e.g. `Remote (server)`) or `custom:<name>`
+        step: String,
+    },
+    /// Print the days since the last successful run for shell prompts. Requires `state_file` in the configuration
+    PromptStatus,
+    /// Exit successfully only if the last successful run is older than the given age, or there was none, e.g.
+    /// `topgrade gate --max-age 7d && topgrade` in a shell profile. Requires `state_file` in the configuration
+    Gate {
+        /// The age, such as `12h` or `7d`
+        #[clap(long = "max-age", parse(try_from_str = parse_duration))]
+        max_age: Duration,
+    },
+}
+
+/// The candidate closest to a misspelled name, if any is close enough to suggest
+pub fn closest_match<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    candidates
+        .map(|candidate| (strsim::jaro_winkler(name, candidate), candidate))
+        .filter(|(similarity, _)| *similarity > 0.8)
+        .max_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(Ordering::Equal))
+        .map(|(_, candidate)| candidate)
+}
+
+/// The step `topgrade run` should run
+#[derive(Debug, Clone, PartialEq)]
+pub enum RunTarget {
+    /// All the instances of a step
+    Step(Step),
+    /// A custom command by name
+    Custom(String),
+    /// A step by the title shown in the summary
+    Key(String),
+}
+
+impl RunTarget {
+    fn parse(target: &str) -> Self {
+        if let Some(name) = target.strip_prefix("custom:") {
+            RunTarget::Custom(name.to_string())
+        } else if let Ok(step) = <Step as FromStr>::from_str(target) {
+            RunTarget::Step(step)
+        } else {
+            RunTarget::Key(target.to_string())
+        }
+    }
 }
 
 impl CommandLineArgs {
@@ -445,6 +1550,37 @@ impl CommandLineArgs {
         self.show_config_reference
     }
 
+    pub fn diff_config(&self) -> bool {
+        self.diff_config
+    }
+
+    pub fn check_config(&self) -> bool {
+        self.check_config
+    }
+
+    pub fn migrate_config(&self) -> bool {
+        self.migrate_config
+    }
+
+    pub fn prompt_status(&self) -> bool {
+        matches!(self.command, Some(SubCommand::PromptStatus))
+    }
+
+    pub fn gate(&self) -> Option<Duration> {
+        match self.command {
+            Some(SubCommand::Gate { max_age }) => Some(max_age),
+            _ => None,
+        }
+    }
+
+    pub fn install_service(&self) -> Option<&str> {
+        if self.install_service {
+            Some(&self.schedule)
+        } else {
+            None
+        }
+    }
+
     pub fn env_variables(&self) -> &Vec<String> {
         &self.env
     }
@@ -459,6 +1595,7 @@ pub struct Config {
     opt: CommandLineArgs,
     config_file: ConfigFile,
     allowed_steps: Vec<Step>,
+    run_target: Option<RunTarget>,
 }
 
 impl Config {
@@ -467,58 +1604,219 @@ impl Config {
     /// The function parses the command line arguments and reading the configuration file.
     pub fn load(base_dirs: &BaseDirs, opt: CommandLineArgs) -> Result<Self> {
         let config_directory = config_directory(base_dirs);
-        let config_file = if config_directory.is_dir() {
-            ConfigFile::read(base_dirs, opt.config.clone()).unwrap_or_else(|e| {
-                // Inform the user about errors when loading the configuration,
-                // but fallback to the default config to at least attempt to do something
-                log::error!("failed to load configuration: {}", e);
-                ConfigFile::default()
-            })
+        let mut config_file = if config_directory.is_dir() {
+            Self::read_config_file(base_dirs, &opt)?
         } else {
             log::debug!("Configuration directory {} does not exist", config_directory.display());
             ConfigFile::default()
         };
 
-        check_deprecated!(config_file, git_arguments, git, arguments);
-        check_deprecated!(config_file, git_repos, git, repos);
-        check_deprecated!(config_file, predefined_git_repos, git, pull_predefined);
-        check_deprecated!(config_file, yay_arguments, linux, yay_arguments);
-        check_deprecated!(config_file, accept_all_windows_updates, windows, accept_all_updates);
+        for (step, arguments) in &opt.step_arguments {
+            config_file.append_step_arguments(*step, arguments)?;
+        }
+
+        config_file.check_deprecated();
+
+        let run_target = opt.command.as_ref().and_then(|command| match command {
+            SubCommand::Run { step } => Some(RunTarget::parse(step)),
+            SubCommand::PromptStatus | SubCommand::Gate { .. } => None,
+        });
+        if let Some(RunTarget::Custom(name)) = &run_target {
+            if !config_file.custom_command_names().any(|command| command == name) {
+                return Err(match closest_match(name, config_file.custom_command_names()) {
+                    Some(candidate) => anyhow!("Unknown custom command `{}`. Did you mean `{}`?", name, candidate),
+                    None => anyhow!("Unknown custom command `{}`", name),
+                });
+            }
+        }
 
-        let allowed_steps = Self::allowed_steps(&opt, &config_file);
+        let allowed_steps = match &run_target {
+            Some(RunTarget::Step(step)) => vec![*step],
+            Some(RunTarget::Custom(_)) => vec![Step::CustomCommands],
+            _ => Self::allowed_steps(&opt, &config_file),
+        };
 
         Ok(Self {
             opt,
             config_file,
             allowed_steps,
+            run_target,
         })
     }
 
+    /// Read the configuration file. If it's invalid, offer to edit it or to run with the default configuration,
+    /// which is also used when there's no terminal to ask in.
+    fn read_config_file(base_dirs: &BaseDirs, opt: &CommandLineArgs) -> Result<ConfigFile> {
+        loop {
+            let error = match ConfigFile::read(base_dirs, opt, opt.config.clone()) {
+                Ok(config_file) => return Ok(config_file),
+                Err(error) => error,
+            };
+
+            if opt.strict_config {
+                return Err(error.context("Failed to load the configuration"));
+            }
+
+            print_warning(format!("Failed to load the configuration: {}", error));
+            if is_dumb() {
+                print_warning("Running with the default configuration");
+                return Ok(ConfigFile::default());
+            }
+
+            println!("(E)dit the configuration/(c)ontinue with the default configuration/(q)uit");
+            match get_key()? {
+                Key::Char('e') | Key::Char('E') => {
+                    let config_path = match &opt.config {
+                        Some(path) => path.clone(),
+                        None => ConfigFile::ensure(base_dirs, opt)?,
+                    };
+                    ConfigFile::open_in_editor(&config_path)?;
+                }
+                Key::Char('c') | Key::Char('C') => return Ok(ConfigFile::default()),
+                Key::Char('q') | Key::Char('Q') => return Err(anyhow!("The configuration is invalid")),
+                _ => (),
+            }
+        }
+    }
+
     /// Launch an editor to edit the configuration
-    pub fn edit(base_dirs: &BaseDirs) -> Result<()> {
-        ConfigFile::edit(base_dirs)
+    pub fn edit(base_dirs: &BaseDirs, opt: &CommandLineArgs) -> Result<()> {
+        ConfigFile::edit(base_dirs, opt)
+    }
+
+    /// Validate the configuration file without running anything, printing the problems. Returns whether it's valid.
+    pub fn check(base_dirs: &BaseDirs, opt: &CommandLineArgs) -> Result<bool> {
+        let config_path = match &opt.config {
+            Some(path) => path.clone(),
+            None => config_directory(base_dirs).join("topgrade.toml"),
+        };
+        if !config_path.exists() && opt.config.is_none() {
+            println!("There's no configuration file. The default configuration is used");
+            return Ok(true);
+        }
+
+        let mut problems = Vec::new();
+        match ConfigFile::read(base_dirs, opt, Some(config_path.clone())) {
+            Ok(config_file) => problems.extend(config_file.invalid_patterns()),
+            Err(e) => problems.push(config_diff::explain_error(&e.to_string())),
+        }
+
+        for problem in &problems {
+            print_warning(problem);
+        }
+        if problems.is_empty() {
+            println!("{} is valid", config_path.display());
+        }
+
+        Ok(problems.is_empty())
+    }
+
+    /// Move the deprecated options of the configuration file into their sections, keeping the original file as a
+    /// backup. Dry runs print the migrated configuration instead.
+    pub fn migrate(base_dirs: &BaseDirs, opt: &CommandLineArgs) -> Result<()> {
+        let config_path = match &opt.config {
+            Some(path) => path.clone(),
+            None => ConfigFile::ensure(base_dirs, opt)?,
+        };
+
+        let (migrated, moves) = match config_migration::migrate(&fs::read_to_string(&config_path)?)? {
+            Some(migration) => migration,
+            None => {
+                println!("The configuration has no deprecated options");
+                return Ok(());
+            }
+        };
+
+        // The backup of an earlier migration may be the only copy of the original configuration
+        let backup = config_path.with_extension("toml.bak");
+        if backup.exists() && !opt.dry_run {
+            return Err(anyhow!(
+                "{} already exists. Move it away to keep it before migrating again",
+                backup.display()
+            ));
+        }
+
+        for option in &moves {
+            println!("Moving {}", option);
+        }
+        if opt.dry_run {
+            println!();
+            print!("{}", migrated);
+            return Ok(());
+        }
+
+        fs::copy(&config_path, &backup)?;
+        fs::write(&config_path, migrated)?;
+        println!(
+            "Migrated {}. The original is kept in {}",
+            config_path.display(),
+            backup.display()
+        );
+
+        Ok(())
+    }
+
+    /// Compare the configuration file with the example configuration
+    pub fn diff(base_dirs: &BaseDirs, opt: &CommandLineArgs) -> Result<()> {
+        let config_path = match &opt.config {
+            Some(path) => path.clone(),
+            None => ConfigFile::ensure(base_dirs, opt)?,
+        };
+
+        config_diff::print_diff(&fs::read_to_string(config_path)?)
     }
 
-    /// The list of commands to run before performing any step.
+    /// The list of commands to run before performing any step. Not run by `topgrade run`.
     pub fn pre_commands(&self) -> &Option<Commands> {
+        if self.single_step() {
+            return &None;
+        }
         &self.config_file.pre_commands
     }
 
-    /// The list of commands to run at the end of all steps
+    /// The list of commands to run at the end of all steps. Not run by `topgrade run`.
     pub fn post_commands(&self) -> &Option<Commands> {
+        if self.single_step() {
+            return &None;
+        }
         &self.config_file.post_commands
     }
 
+    /// The list of commands to run at the end of all steps, only if any step changed something.
+    /// Not run by `topgrade run`.
+    pub fn post_change_commands(&self) -> &Option<Commands> {
+        if self.single_step() {
+            return &None;
+        }
+        &self.config_file.post_change_commands
+    }
+
+    /// The commands to run before and after the step
+    pub fn step_hooks(&self, step: Step) -> Option<&StepHooks> {
+        self.config_file
+            .hooks
+            .as_ref()
+            .and_then(|hooks| hooks.get(step.as_ref()))
+    }
+
     /// The list of custom steps.
     pub fn commands(&self) -> &Option<Commands> {
         &self.config_file.commands
     }
 
     /// The list of additional git repositories to pull.
-    pub fn git_repos(&self) -> &Option<Vec<String>> {
+    pub fn git_repos(&self) -> &Option<Vec<GitRepo>> {
         get_deprecated!(self.config_file, git_repos, git, repos)
     }
 
+    /// Theme and plugin repositories of terminal emulators to pull along with the predefined ones
+    pub fn terminal_repos(&self) -> Option<&Vec<GitRepo>> {
+        self.config_file
+            .terminals
+            .as_ref()
+            .and_then(|terminals| terminals.repos.as_ref())
+    }
+
     /// Tell whether the specified step should run.
     ///
     /// If the step appears either in the `--disable` command line argument
@@ -527,12 +1825,23 @@ impl Config {
         self.allowed_steps.contains(&step)
     }
 
+    /// The steps which are allowed to run.
+    pub fn enabled_steps(&self) -> &[Step] {
+        &self.allowed_steps
+    }
+
+    /// Restrict the allowed steps to the given selection.
+    pub fn select_steps(&mut self, steps: Vec<Step>) {
+        self.allowed_steps = steps;
+    }
+
     fn allowed_steps(opt: &CommandLineArgs, config_file: &ConfigFile) -> Vec<Step> {
         let mut enabled_steps: Vec<Step> = Vec::new();
         enabled_steps.extend(&opt.only);
 
-        if let Some(only) = config_file.only.as_ref() {
-            enabled_steps.extend(only)
+        for name in config_file.only.iter().flatten() {
+            // Names which aren't steps are custom commands, which were validated when loading the configuration
+            enabled_steps.push(<Step as FromStr>::from_str(name).unwrap_or(Step::CustomCommands));
         }
 
         if enabled_steps.is_empty() {
@@ -541,14 +1850,39 @@ impl Config {
 
         let mut disabled_steps: Vec<Step> = Vec::new();
         disabled_steps.extend(&opt.disable);
-        if let Some(disabled) = config_file.disable.as_ref() {
-            disabled_steps.extend(disabled);
-        }
+        disabled_steps.extend(
+            config_file
+                .disable
+                .iter()
+                .flatten()
+                .filter_map(|name| <Step as FromStr>::from_str(name).ok()),
+        );
 
         enabled_steps.retain(|e| !disabled_steps.contains(e) || opt.only.contains(e));
         enabled_steps
     }
 
+    /// Tell whether only a single step was requested with `topgrade run`
+    pub fn single_step(&self) -> bool {
+        self.run_target.is_some()
+    }
+
+    /// The title of the step selected with `topgrade run`, if it was selected by its title
+    pub fn run_key(&self) -> Option<&str> {
+        match &self.run_target {
+            Some(RunTarget::Key(target)) => Some(target),
+            _ => None,
+        }
+    }
+
+    /// Tell whether a step with the given title should run, when `topgrade run` selected one by its title
+    pub fn should_run_key(&self, key: &str) -> bool {
+        match &self.run_target {
+            Some(RunTarget::Key(target)) => target == key,
+            _ => true,
+        }
+    }
+
     /// Tell whether we should run in tmux.
     pub fn run_in_tmux(&self) -> bool {
         self.opt.run_in_tmux || self.config_file.run_in_tmux.unwrap_or(false)
@@ -559,6 +1893,34 @@ impl Config {
         self.opt.cleanup || self.config_file.cleanup.unwrap_or(false)
     }
 
+    /// How long to wait for package managers used by other processes
+    pub fn package_lock_timeout(&self) -> Duration {
+        Duration::from_secs(self.config_file.package_lock_timeout.unwrap_or(120))
+    }
+
+    /// How many of the last lines of the standard error of failed steps to show in the summary
+    pub fn failure_output_lines(&self) -> Option<usize> {
+        self.config_file.failure_output_lines.filter(|lines| *lines > 0)
+    }
+
+    /// How long a command may run without producing output before it's considered stalled
+    pub fn stall_timeout(&self) -> Option<Duration> {
+        self.config_file
+            .stall_timeout
+            .as_ref()
+            .and_then(|timeout| parse_duration(timeout).ok())
+    }
+
+    /// Whether to record the run in the state file read by `topgrade prompt-status`. Writing metrics requires it.
+    pub fn state_file(&self) -> bool {
+        self.config_file.state_file.unwrap_or(false) || self.metrics_file().is_some()
+    }
+
+    /// The file the results of the run are written to as Prometheus metrics
+    pub fn metrics_file(&self) -> Option<&Path> {
+        self.config_file.metrics_file.as_deref().map(Path::new)
+    }
+
     /// Tell whether we are dry-running.
     pub fn dry_run(&self) -> bool {
         self.opt.dry_run
@@ -569,20 +1931,107 @@ impl Config {
         self.opt.no_retry || self.config_file.no_retry.unwrap_or(false)
     }
 
-    /// List of remote hosts to run Topgrade in
-    pub fn remote_topgrades(&self) -> &Option<Vec<String>> {
-        &self.config_file.remote_topgrades
+    /// How many times to automatically retry a failed step before giving up or asking the user.
+    pub fn auto_retry(&self) -> u32 {
+        self.config_file.auto_retry.unwrap_or(0)
     }
 
-    /// Path to Topgrade executable used for all remote hosts
-    pub fn remote_topgrade_path(&self) -> &str {
-        self.config_file.remote_topgrade_path.as_deref().unwrap_or("topgrade")
+    /// When to reboot at the end of the run. `auto_reboot` reboots when required.
+    pub fn reboot_policy(&self) -> RebootPolicy {
+        self.config_file
+            .reboot
+            .as_ref()
+            .and_then(|reboot| reboot.policy)
+            .unwrap_or(if self.config_file.auto_reboot.unwrap_or(false) {
+                RebootPolicy::IfRequired
+            } else {
+                RebootPolicy::Never
+            })
     }
 
-    /// Extra SSH arguments
-    pub fn ssh_arguments(&self) -> &Option<String> {
-        &self.config_file.ssh_arguments
-    }
+    /// The priority of the step. Steps with higher priorities run first, and steps without one have 0.
+    pub fn step_priority(&self, step: Step) -> i32 {
+        self.config_file
+            .priorities
+            .as_ref()
+            .and_then(|priorities| priorities.get(step.as_ref()))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// The priorities steps have, from the highest
+    pub fn priority_levels(&self) -> Vec<i32> {
+        let mut levels: Vec<i32> = self
+            .config_file
+            .priorities
+            .iter()
+            .flat_map(|priorities| priorities.values().copied())
+            .chain(std::iter::once(0))
+            .collect();
+        levels.sort_unstable_by(|a, b| b.cmp(a));
+        levels.dedup();
+        levels
+    }
+
+    /// The URL template of the changelogs of the packages upgraded by the step, with `{name}` and `{version}`
+    pub fn changelog_url(&self, step: &str) -> Option<&str> {
+        self.config_file
+            .changelogs
+            .as_ref()
+            .and_then(|changelogs| changelogs.get(step))
+            .map(String::as_str)
+    }
+
+    /// The window required reboots are scheduled in, instead of rebooting at the end of the run
+    pub fn reboot_window(&self) -> Option<RebootWindow> {
+        self.config_file
+            .reboot
+            .as_ref()
+            .and_then(|reboot| reboot.window.as_ref())
+            .and_then(|window| window.parse().ok())
+    }
+
+    /// Tell whether steps which require elevation should be skipped.
+    pub fn no_sudo(&self) -> bool {
+        self.opt.no_sudo || self.config_file.no_sudo.unwrap_or(false)
+    }
+
+    /// The program used for elevation instead of the detected one
+    pub fn elevation_command(&self) -> Option<&str> {
+        self.config_file.elevation_command.as_deref()
+    }
+
+    /// List of remote hosts to run Topgrade in
+    /// All remote hosts, both from `remote_topgrades` and from `remotes`
+    pub fn remotes(&self) -> Vec<Remote> {
+        self.config_file
+            .remote_topgrades
+            .iter()
+            .flatten()
+            .map(|host| Remote::untagged(host))
+            .chain(self.config_file.remotes.iter().flatten().cloned())
+            .collect()
+    }
+
+    /// Routers, firewalls and homelab services to check for updates
+    pub fn network_devices(&self) -> &[NetworkDevice] {
+        self.config_file.network_devices.as_deref().unwrap_or_default()
+    }
+
+    /// Whether to list the remote hosts instead of upgrading
+    pub fn list_remotes(&self) -> bool {
+        self.opt.list_remotes
+    }
+
+    /// Path to Topgrade executable used for all remote hosts
+    pub fn remote_topgrade_path(&self) -> &str {
+        self.config_file.remote_topgrade_path.as_deref().unwrap_or("topgrade")
+    }
+
+    /// Extra SSH arguments
+    pub fn ssh_arguments(&self) -> &Option<String> {
+        &self.config_file.ssh_arguments
+    }
 
     /// Extra Git arguments
     pub fn git_arguments(&self) -> &Option<String> {
@@ -595,6 +2044,21 @@ impl Config {
         &self.config_file.tmux_arguments
     }
 
+    /// Whether to ask before running each step
+    pub fn ask_each(&self) -> bool {
+        self.opt.ask_each
+    }
+
+    /// Whether to continue the interrupted run
+    pub fn resume(&self) -> bool {
+        self.opt.resume
+    }
+
+    /// The time after which the remaining steps are skipped
+    pub fn max_duration(&self) -> Option<Duration> {
+        self.opt.max_duration
+    }
+
     /// Prompt for a key before exiting
     pub fn keep_at_end(&self) -> bool {
         self.opt.keep_at_end || env::var("TOPGRADE_KEEP_END").is_ok()
@@ -656,6 +2120,24 @@ impl Config {
             .unwrap_or(false)
     }
 
+    /// Whether to restart the started services of upgraded formulas
+    pub fn brew_restart_services(&self) -> bool {
+        self.config_file
+            .brew
+            .as_ref()
+            .and_then(|brew| brew.restart_services)
+            .unwrap_or(false)
+    }
+
+    /// Skip `brew update` when Homebrew updated itself within this duration
+    pub fn brew_recent_update_window(&self) -> Option<Duration> {
+        self.config_file
+            .brew
+            .as_ref()
+            .and_then(|c| c.recent_update_minutes)
+            .map(|minutes| Duration::from_secs(minutes * 60))
+    }
+
     /// Whether Composer should update itself
     pub fn composer_self_update(&self) -> bool {
         self.config_file
@@ -674,11 +2156,40 @@ impl Config {
             .unwrap_or_default()
     }
 
+    /// The `NVIM_APPNAME`s of the Neovim configurations to update
+    pub fn nvim_appnames(&self) -> Vec<&str> {
+        self.config_file
+            .vim
+            .as_ref()
+            .and_then(|vim| vim.appnames.as_ref())
+            .map(|appnames| appnames.iter().map(String::as_str).collect())
+            .unwrap_or_else(|| vec!["nvim"])
+    }
+
+    /// Whether to update the tools installed with mason.nvim
+    pub fn update_mason(&self) -> bool {
+        self.config_file
+            .vim
+            .as_ref()
+            .and_then(|c| c.update_mason)
+            .unwrap_or(true)
+    }
+
     /// Whether to send a desktop notification at the beginning of every step
     pub fn notify_each_step(&self) -> bool {
         self.config_file.notify_each_step.unwrap_or(false)
     }
 
+    /// When to send desktop notifications about the outcome of the run and failed steps
+    pub fn notify_on(&self) -> NotifyOn {
+        self.config_file.notify_on.unwrap_or(NotifyOn::Always)
+    }
+
+    /// A command sending desktop notifications instead of the one of the system. The message is its last argument.
+    pub fn notification_command(&self) -> Option<&str> {
+        self.config_file.notification_command.as_deref()
+    }
+
     /// Extra trizen arguments
     pub fn trizen_arguments(&self) -> &str {
         self.config_file
@@ -689,7 +2200,6 @@ impl Config {
     }
 
     /// Extra Pikaur arguments
-    #[allow(dead_code)]
     pub fn pikaur_arguments(&self) -> &str {
         self.config_file
             .linux
@@ -698,6 +2208,15 @@ impl Config {
             .unwrap_or("")
     }
 
+    /// Extra Aura arguments, passed when upgrading packages of the AUR
+    pub fn aura_arguments(&self) -> &str {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|s| s.aura_arguments.as_deref())
+            .unwrap_or("")
+    }
+
     /// Extra Pamac arguments
     pub fn pamac_arguments(&self) -> &str {
         self.config_file
@@ -707,6 +2226,15 @@ impl Config {
             .unwrap_or("")
     }
 
+    /// Whether pamac upgrades packages of the AUR
+    pub fn pamac_aur(&self) -> bool {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|s| s.pamac_aur)
+            .unwrap_or(false)
+    }
+
     /// Show news on Arch Linux
     pub fn show_arch_news(&self) -> bool {
         self.config_file
@@ -729,7 +2257,55 @@ impl Config {
     pub fn yay_arguments(&self) -> &str {
         get_deprecated!(self.config_file, yay_arguments, linux, yay_arguments)
             .as_deref()
-            .unwrap_or("--devel")
+            .unwrap_or("")
+    }
+
+    /// Extra paru arguments. The yay arguments, which used to apply to paru as well, are used if they're not set.
+    pub fn paru_arguments(&self) -> &str {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|s| s.paru_arguments.as_deref())
+            .unwrap_or_else(|| self.yay_arguments())
+    }
+
+    /// Whether AUR helpers rebuild development packages, such as `-git` ones
+    pub fn aur_devel(&self) -> bool {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|s| s.aur_devel)
+            .unwrap_or(false)
+    }
+
+    /// Whether yay or paru rebuild development packages. They do unless their arguments are set, since `--devel`
+    /// used to be the default arguments of yay, which paru used as well.
+    pub fn yay_devel(&self, paru: bool) -> bool {
+        let arguments_set = get_deprecated!(self.config_file, yay_arguments, linux, yay_arguments).is_some()
+            || (paru
+                && self
+                    .config_file
+                    .linux
+                    .as_ref()
+                    .and_then(|linux| linux.paru_arguments.as_ref())
+                    .is_some());
+
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.aur_devel)
+            .unwrap_or(!arguments_set)
+    }
+
+    /// Extra arguments given to the step with `--step-arg`, for the steps which have no passthrough arguments in the
+    /// configuration file
+    pub fn step_arguments(&self, step: Step) -> Vec<&str> {
+        self.opt
+            .step_arguments
+            .iter()
+            .filter(|(argument_step, _)| *argument_step == step)
+            .flat_map(|(_, arguments)| arguments.split_whitespace())
+            .collect()
     }
 
     /// Extra apt arguments
@@ -740,6 +2316,41 @@ impl Config {
             .and_then(|linux| linux.apt_arguments.as_deref())
     }
 
+    /// The frontend of apt to upgrade Debian based systems with
+    pub fn apt_tool(&self) -> AptTool {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.apt_tool)
+            .unwrap_or(AptTool::Autodetect)
+    }
+
+    /// Install the upgrades which change or remove installed packages, with `dist-upgrade`, instead of `upgrade`
+    pub fn apt_dist_upgrade(&self) -> bool {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.apt_dist_upgrade)
+            .unwrap_or(true)
+    }
+
+    /// Install phased updates of Ubuntu right away, instead of when the phase reaches this machine
+    pub fn apt_phased_updates(&self) -> bool {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.apt_phased_updates)
+            .unwrap_or(false)
+    }
+
+    /// Extra arguments for xbps-install on Void Linux
+    pub fn xbps_arguments(&self) -> Option<&str> {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.xbps_arguments.as_deref())
+    }
+
     /// Extra dnf arguments
     pub fn dnf_arguments(&self) -> Option<&str> {
         self.config_file
@@ -748,11 +2359,62 @@ impl Config {
             .and_then(|linux| linux.dnf_arguments.as_deref())
     }
 
+    /// Refresh the metadata of the repositories before upgrading with dnf, even if it hasn't expired
+    pub fn dnf_refresh(&self) -> bool {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.dnf_refresh)
+            .unwrap_or(false)
+    }
+
+    /// Whether to pull the repositories managed by ghq
+    pub fn git_ghq(&self) -> bool {
+        self.config_file.git.as_ref().and_then(|git| git.ghq).unwrap_or(false)
+    }
+
+    /// Glob patterns of ghq repositories that should not be pulled
+    pub fn git_ghq_exclude(&self) -> Option<&Vec<String>> {
+        self.config_file.git.as_ref().and_then(|git| git.ghq_exclude.as_ref())
+    }
+
+    /// Glob patterns of repositories which should never be pulled
+    pub fn git_exclude(&self) -> Option<&Vec<String>> {
+        self.config_file.git.as_ref().and_then(|git| git.exclude.as_ref())
+    }
+
+    /// Whether to stash local changes of repositories and pull with a rebase, instead of failing to pull them
+    pub fn git_stash_dirty(&self) -> bool {
+        self.config_file
+            .git
+            .as_ref()
+            .and_then(|git| git.stash_dirty)
+            .unwrap_or(false)
+    }
+
+    /// Whether to push the local commits of repositories after pulling them
+    pub fn git_push_after_pull(&self) -> bool {
+        self.config_file
+            .git
+            .as_ref()
+            .and_then(|git| git.push_after_pull)
+            .unwrap_or(false)
+    }
+
     /// Concurrency limit for git
     pub fn git_concurrency_limit(&self) -> Option<usize> {
         self.config_file.git.as_ref().and_then(|git| git.max_concurrency)
     }
 
+    /// How long pulling a single repository may take before it is aborted
+    pub fn git_pull_timeout(&self) -> Option<Duration> {
+        self.config_file
+            .git
+            .as_ref()
+            .and_then(|git| git.pull_timeout.as_ref())
+            .and_then(|timeout| parse_duration(timeout).ok())
+    }
+
     /// Should we power on vagrant boxes if needed
     pub fn vagrant_power_on(&self) -> Option<bool> {
         self.config_file.vagrant.as_ref().and_then(|vagrant| vagrant.power_on)
@@ -774,6 +2436,206 @@ impl Config {
             .and_then(|vagrant| vagrant.always_suspend)
     }
 
+    /// The container runtime to use instead of detecting it
+    pub fn container_runtime(&self) -> Option<ContainerRuntime> {
+        self.config_file
+            .containers
+            .as_ref()
+            .and_then(|containers| containers.runtime)
+    }
+
+    /// Glob patterns of container images which should not be pulled
+    pub fn containers_exclude(&self) -> Option<&Vec<String>> {
+        self.config_file
+            .containers
+            .as_ref()
+            .and_then(|containers| containers.exclude.as_ref())
+    }
+
+    /// Docker Compose files of the stacks to update
+    pub fn compose_files(&self) -> Option<&Vec<String>> {
+        self.config_file
+            .containers
+            .as_ref()
+            .and_then(|containers| containers.compose_files.as_ref())
+    }
+
+    /// Workspaces synced by Android's repo tool
+    pub fn repo_directories(&self) -> Option<&Vec<String>> {
+        self.config_file
+            .repo
+            .as_ref()
+            .and_then(|repo| repo.directories.as_ref())
+    }
+
+    /// Workspaces updated by Zephyr's west tool
+    pub fn west_directories(&self) -> Option<&Vec<String>> {
+        self.config_file
+            .west
+            .as_ref()
+            .and_then(|west| west.directories.as_ref())
+    }
+
+    /// Mercurial working copies to pull and update
+    pub fn hg_directories(&self) -> Option<&Vec<String>> {
+        self.config_file.hg.as_ref().and_then(|hg| hg.directories.as_ref())
+    }
+
+    /// Subversion working copies to update
+    pub fn svn_directories(&self) -> Option<&Vec<String>> {
+        self.config_file.svn.as_ref().and_then(|svn| svn.directories.as_ref())
+    }
+
+    /// Repositories whose pre-commit hooks should be updated. The hook environments of pulled git repositories are
+    /// installed as well, without updating their hooks.
+    pub fn pre_commit_directories(&self) -> Option<&Vec<String>> {
+        self.config_file
+            .pre_commit
+            .as_ref()
+            .and_then(|pre_commit| pre_commit.directories.as_ref())
+    }
+
+    /// Projects updated by devenv
+    #[cfg(unix)]
+    pub fn devenv_directories(&self) -> Option<&Vec<String>> {
+        self.config_file
+            .devenv
+            .as_ref()
+            .and_then(|devenv| devenv.directories.as_ref())
+    }
+
+    /// The Emacs function upgrading the packages, called after loading init.el
+    pub fn emacs_update_function(&self) -> Option<&str> {
+        self.config_file
+            .emacs
+            .as_ref()
+            .and_then(|emacs| emacs.update_function.as_deref())
+    }
+
+    /// Conda environments to update instead of the base one
+    pub fn conda_environments(&self) -> Option<&Vec<String>> {
+        self.config_file
+            .conda
+            .as_ref()
+            .and_then(|conda| conda.environments.as_ref())
+    }
+
+    /// Whether the pip3 step should upgrade the outdated packages in the user site
+    pub fn pip3_upgrade_user_packages(&self) -> bool {
+        self.config_file
+            .python
+            .as_ref()
+            .and_then(|python| python.upgrade_user_packages)
+            .unwrap_or(false)
+    }
+
+    /// Python packages the pip3 step shouldn't upgrade
+    pub fn pip3_exclude(&self) -> Option<&Vec<String>> {
+        self.config_file
+            .python
+            .as_ref()
+            .and_then(|python| python.exclude.as_ref())
+    }
+
+    /// The VS Code builds whose extensions are updated
+    pub fn vscode_binaries(&self) -> Vec<&str> {
+        self.config_file
+            .vscode
+            .as_ref()
+            .and_then(|vscode| vscode.binaries.as_ref())
+            .map(|binaries| binaries.iter().map(String::as_str).collect())
+            .unwrap_or_else(|| vec!["code", "codium"])
+    }
+
+    /// Whether to check that the default toolchains still work after upgrading
+    pub fn toolchain_check(&self) -> bool {
+        self.config_file
+            .toolchain_check
+            .as_ref()
+            .and_then(|toolchain_check| toolchain_check.enable)
+            .unwrap_or(false)
+    }
+
+    /// The commands whose defaults are checked
+    pub fn toolchain_check_tools(&self) -> Vec<String> {
+        self.config_file
+            .toolchain_check
+            .as_ref()
+            .and_then(|toolchain_check| toolchain_check.tools.clone())
+            .unwrap_or_else(|| {
+                ["java", "python3", "node"]
+                    .iter()
+                    .map(|tool| tool.to_string())
+                    .collect()
+            })
+    }
+
+    /// Extra arguments for `kubectl krew upgrade`
+    pub fn krew_arguments(&self) -> Option<&str> {
+        self.config_file
+            .krew
+            .as_ref()
+            .and_then(|krew| krew.arguments.as_deref())
+    }
+
+    /// Custom krew indexes by name
+    pub fn krew_indexes(&self) -> Option<&BTreeMap<String, String>> {
+        self.config_file.krew.as_ref().and_then(|krew| krew.indexes.as_ref())
+    }
+
+    /// Whether to make sure that the rust-analyzer component of rustup is installed
+    pub fn update_rust_analyzer(&self) -> bool {
+        self.config_file
+            .language_servers
+            .as_ref()
+            .and_then(|language_servers| language_servers.rust_analyzer)
+            .unwrap_or(true)
+    }
+
+    /// Whether to update clangd installed from its releases
+    pub fn update_clangd(&self) -> bool {
+        self.config_file
+            .language_servers
+            .as_ref()
+            .and_then(|language_servers| language_servers.clangd)
+            .unwrap_or(true)
+    }
+
+    /// Whether to update lua-language-server installed from its releases
+    pub fn update_lua_language_server(&self) -> bool {
+        self.config_file
+            .language_servers
+            .as_ref()
+            .and_then(|language_servers| language_servers.lua_language_server)
+            .unwrap_or(true)
+    }
+
+    /// The Bazel disk cache to trim when cleaning up
+    pub fn bazel_disk_cache(&self) -> Option<&str> {
+        self.config_file
+            .bazel
+            .as_ref()
+            .and_then(|bazel| bazel.disk_cache.as_deref())
+    }
+
+    /// The size in megabytes the Bazel disk cache is trimmed to
+    pub fn bazel_disk_cache_max_size_mb(&self) -> u64 {
+        self.config_file
+            .bazel
+            .as_ref()
+            .and_then(|bazel| bazel.disk_cache_max_size_mb)
+            .unwrap_or(5 * 1024)
+    }
+
+    /// Whether to install the latest Terraform release with tfenv
+    pub fn tfenv_install_latest(&self) -> bool {
+        self.config_file
+            .terraform
+            .as_ref()
+            .and_then(|terraform| terraform.tfenv_install_latest)
+            .unwrap_or(false)
+    }
+
     /// Enable tlmgr on Linux
     pub fn enable_tlmgr_linux(&self) -> bool {
         self.config_file
@@ -801,13 +2663,69 @@ impl Config {
             .unwrap_or(true)
     }
 
+    /// Use transactional-update on openSUSE systems with a read-only root, such as MicroOS, when it's detected
+    pub fn transactional_update(&self) -> bool {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.transactional_update)
+            .unwrap_or(true)
+    }
+
+    /// Remove journal entries older than this when cleaning up
+    #[cfg(target_os = "linux")]
+    pub fn journalctl_vacuum_time(&self) -> Option<&str> {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.journalctl_vacuum_time.as_deref())
+    }
+
+    /// The systemd user units to restart after the step upgraded something
+    #[cfg(target_os = "linux")]
+    pub fn restart_user_units(&self, step: Step) -> Option<&Vec<String>> {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.restart_user_units.as_ref())
+            .and_then(|units| units.get(step.as_ref()))
+    }
+
+    /// The flake NixOS should be rebuilt from
+    #[cfg(target_os = "linux")]
+    pub fn nixos_flake(&self) -> Option<&str> {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.nixos_flake.as_deref())
+    }
+
+    /// Let nixos-rebuild elevate by itself instead of running it through sudo
+    #[cfg(target_os = "linux")]
+    pub fn nixos_use_remote_sudo(&self) -> bool {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.nixos_use_remote_sudo)
+            .unwrap_or(false)
+    }
+
     /// Should we ignore failures for this step
     pub fn ignore_failure(&self, step: Step) -> bool {
         self.config_file
             .ignore_failures
-            .as_ref()
-            .map(|v| v.contains(&step))
-            .unwrap_or(false)
+            .iter()
+            .flatten()
+            .any(|name| name == step.as_ref())
+    }
+
+    /// Should the machine be kept from sleeping or shutting down while this step runs
+    pub fn inhibit_sleep(&self, step: Step) -> bool {
+        self.config_file
+            .inhibit_sleep
+            .iter()
+            .flatten()
+            .any(|name| name == step.as_ref())
     }
 
     pub fn use_predefined_git_repos(&self) -> bool {
@@ -819,6 +2737,30 @@ impl Config {
         self.opt.verbose
     }
 
+    pub fn exit_code_on_changes(&self) -> bool {
+        self.opt.exit_code_on_changes
+    }
+
+    /// The format of the summary
+    pub fn report_format(&self) -> ReportFormat {
+        self.opt.report_format
+    }
+
+    /// Whether to show the step results of remote Topgrades in the summary instead of a single result per remote.
+    /// Requires the remote Topgrades to support `--report-format json`.
+    pub fn remote_summary(&self) -> bool {
+        self.config_file.remote_summary.unwrap_or(false)
+    }
+
+    pub fn interactive(&self) -> bool {
+        self.opt.interactive
+    }
+
+    #[cfg(all(target_os = "linux", feature = "tray"))]
+    pub fn tray(&self) -> bool {
+        self.opt.tray
+    }
+
     pub fn show_skipped(&self) -> bool {
         self.opt.show_skipped
     }
@@ -840,7 +2782,7 @@ impl Config {
             .unwrap_or(false)
     }
 
-    #[cfg(target_os = "linux")]
+    #[cfg(any(target_os = "linux", target_os = "freebsd"))]
     pub fn firmware_upgrade(&self) -> bool {
         self.config_file
             .firmware
@@ -849,6 +2791,15 @@ impl Config {
             .unwrap_or(false)
     }
 
+    /// GUIDs of the devices whose firmware should be upgraded. `None` means all devices.
+    #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+    pub fn firmware_devices(&self) -> Option<&Vec<String>> {
+        self.config_file
+            .firmware
+            .as_ref()
+            .and_then(|firmware| firmware.devices.as_ref())
+    }
+
     #[cfg(target_os = "linux")]
     pub fn flatpak_use_sudo(&self) -> bool {
         self.config_file
@@ -858,24 +2809,193 @@ impl Config {
             .unwrap_or(false)
     }
 
+    /// When to send the results of the run to the webhook and the email address
+    pub fn notifications_on(&self) -> NotifyOn {
+        self.config_file
+            .notifications
+            .as_ref()
+            .and_then(|notifications| notifications.on)
+            .unwrap_or(NotifyOn::Always)
+    }
+
+    /// The URL the results of the run are posted to
+    pub fn notification_webhook(&self) -> Option<&str> {
+        self.config_file
+            .notifications
+            .as_ref()
+            .and_then(|notifications| notifications.webhook.as_deref())
+    }
+
+    pub fn notification_webhook_format(&self) -> WebhookFormat {
+        self.config_file
+            .notifications
+            .as_ref()
+            .and_then(|notifications| notifications.webhook_format)
+            .unwrap_or(WebhookFormat::Json)
+    }
+
+    /// The address the results of the run are emailed to with sendmail
+    pub fn notification_email(&self) -> Option<&str> {
+        self.config_file
+            .notifications
+            .as_ref()
+            .and_then(|notifications| notifications.email.as_deref())
+    }
+
+    /// The sops-encrypted file secrets are looked up in instead of the keychain
+    pub fn secrets_file(&self) -> Option<&str> {
+        self.config_file
+            .secrets
+            .as_ref()
+            .and_then(|secrets| secrets.file.as_deref())
+    }
+
+    /// The bandwidth limit of the downloads of the step in bytes per second. `None` if the step is exempted from the
+    /// limit.
+    pub fn network_limit(&self, step: Step) -> Option<u64> {
+        let network = self.config_file.network.as_ref()?;
+        if network
+            .unlimited_steps
+            .iter()
+            .flatten()
+            .any(|name| name == step.as_ref())
+        {
+            return None;
+        }
+
+        network
+            .limit
+            .as_ref()
+            .and_then(|limit| bandwidth::parse_limit(limit).ok())
+    }
+
+    /// Extra arguments for `snap refresh`
+    #[cfg(target_os = "linux")]
+    pub fn snap_arguments(&self) -> Option<&str> {
+        self.config_file
+            .snap
+            .as_ref()
+            .and_then(|snap| snap.arguments.as_deref())
+    }
+
+    /// IDs of flatpak applications and runtimes which should not be updated
+    #[cfg(target_os = "linux")]
+    pub fn flatpak_exclude(&self) -> Option<&Vec<String>> {
+        self.config_file
+            .flatpak
+            .as_ref()
+            .and_then(|flatpak| flatpak.exclude.as_ref())
+    }
+
+    /// How many of the latest Nix generations to keep when cleaning up
+    #[cfg(unix)]
+    pub fn nix_keep_generations(&self) -> Option<u32> {
+        self.config_file
+            .nix
+            .as_ref()
+            .and_then(|nix| nix.keep_generations)
+            .map(NonZeroU32::get)
+    }
+
+    /// The flake home-manager should switch to
+    #[cfg(unix)]
+    pub fn home_manager_flake(&self) -> Option<&str> {
+        self.config_file
+            .home_manager
+            .as_ref()
+            .and_then(|home_manager| home_manager.flake.as_deref())
+    }
+
+    /// Show unread home-manager news after switching
+    #[cfg(unix)]
+    pub fn home_manager_show_news(&self) -> bool {
+        self.config_file
+            .home_manager
+            .as_ref()
+            .and_then(|home_manager| home_manager.show_news)
+            .unwrap_or(false)
+    }
+
+    /// Home-manager generations older than this are expired when cleaning up. `None` keeps every generation.
+    #[cfg(unix)]
+    pub fn home_manager_expire_generations(&self) -> Option<&str> {
+        self.config_file
+            .home_manager
+            .as_ref()
+            .and_then(|home_manager| home_manager.expire_generations.as_deref())
+    }
+
+    /// Delete Nix generations older than this many days when cleaning up
+    #[cfg(unix)]
+    pub fn nix_keep_days(&self) -> Option<u32> {
+        self.config_file.nix.as_ref().and_then(|nix| nix.keep_days)
+    }
+
+    /// Whether to install the latest versions of the tools in ~/.tool-versions and make them the global ones
+    #[cfg(unix)]
+    pub fn asdf_bump_global_versions(&self) -> bool {
+        self.config_file
+            .asdf
+            .as_ref()
+            .and_then(|asdf| asdf.bump_global_versions)
+            .unwrap_or(false)
+    }
+
     #[cfg(target_os = "linux")]
     str_value!(linux, emerge_sync_flags);
 
     #[cfg(target_os = "linux")]
     str_value!(linux, emerge_update_flags);
 
-    pub fn should_execute_remote(&self, remote: &str) -> bool {
+    /// Whether to rebuild the packages using libraries preserved by the update on Gentoo
+    #[cfg(target_os = "linux")]
+    pub fn emerge_preserved_rebuild(&self) -> bool {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.emerge_preserved_rebuild)
+            .unwrap_or(false)
+    }
+
+    /// Whether to remove the packages nothing depends on when cleaning up on Gentoo
+    #[cfg(target_os = "linux")]
+    pub fn emerge_depclean(&self) -> bool {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.emerge_depclean)
+            .unwrap_or(false)
+    }
+
+    /// Whether to remove old distfiles and binary packages with eclean when cleaning up on Gentoo
+    #[cfg(target_os = "linux")]
+    pub fn eclean(&self) -> bool {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.eclean)
+            .unwrap_or(false)
+    }
+
+    pub fn should_execute_remote(&self, remote: &Remote) -> bool {
         if let Ok(hostname) = hostname() {
-            if remote == hostname {
+            if remote.host() == hostname {
                 return false;
             }
         }
 
+        self.remote_matches_filters(remote)
+    }
+
+    /// Tell whether the remote passes the `--remote-host-limit` and `--remote-tag` filters
+    pub fn remote_matches_filters(&self, remote: &Remote) -> bool {
         if let Some(limit) = self.opt.remote_host_limit.as_ref() {
-            return limit.is_match(remote);
+            if !limit.is_match(remote.host()) {
+                return false;
+            }
         }
 
-        true
+        self.opt.remote_tags.is_empty() || remote.tags().iter().any(|tag| self.opt.remote_tags.contains(tag))
     }
 
     #[cfg(windows)]
@@ -888,15 +3008,177 @@ impl Config {
             .unwrap_or(false);
     }
 
+    /// Extra Chocolatey arguments
+    #[cfg(windows)]
+    pub fn chocolatey_arguments(&self) -> Option<&str> {
+        self.config_file
+            .windows
+            .as_ref()
+            .and_then(|w| w.chocolatey_arguments.as_deref())
+    }
+
+    /// Whether Chocolatey should be elevated when Topgrade isn't running as an administrator
+    #[cfg(windows)]
+    pub fn chocolatey_use_sudo(&self) -> bool {
+        self.config_file
+            .windows
+            .as_ref()
+            .and_then(|w| w.chocolatey_use_sudo)
+            .unwrap_or(false)
+    }
+
+    /// WSL distributions in which Topgrade should run. All distributions are used when unset.
+    #[cfg(windows)]
+    pub fn wsl_distributions(&self) -> Option<&Vec<String>> {
+        self.config_file
+            .windows
+            .as_ref()
+            .and_then(|w| w.wsl_distributions.as_ref())
+    }
+
     pub fn display_time(&self) -> bool {
         self.config_file.display_time.unwrap_or(true)
     }
 
     pub fn should_run_custom_command(&self, name: &str) -> bool {
-        if self.opt.custom_commands.is_empty() {
-            return true;
+        if let Some(RunTarget::Custom(target)) = &self.run_target {
+            return target == name;
         }
 
-        self.opt.custom_commands.iter().any(|s| s == name)
+        let disabled = self.config_file.disable.iter().flatten().any(|s| s == name);
+        if disabled && !self.opt.custom_commands.iter().any(|s| s == name) {
+            return false;
+        }
+
+        if !self.opt.custom_commands.is_empty() {
+            return self.opt.custom_commands.iter().any(|s| s == name);
+        }
+
+        // Custom commands listed in `only` restrict the custom commands to them
+        let only_commands: Vec<&String> = self
+            .config_file
+            .only
+            .iter()
+            .flatten()
+            .filter(|s| <Step as FromStr>::from_str(s).is_err())
+            .collect();
+        only_commands.is_empty() || only_commands.iter().any(|s| *s == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("60s").unwrap(), Duration::from_secs(60));
+        assert_eq!(parse_duration(" 5m ").unwrap(), Duration::from_secs(5 * 60));
+        assert_eq!(parse_duration("1h").unwrap(), Duration::from_secs(60 * 60));
+        assert_eq!(parse_duration("7d").unwrap(), Duration::from_secs(7 * 24 * 60 * 60));
+    }
+
+    #[test]
+    fn test_parse_invalid_duration() {
+        for invalid in ["", "m", "-5m", "1.5h", "5 minutes", "5w", "10ms"] {
+            assert!(parse_duration(invalid).is_err(), "{:?} was accepted", invalid);
+        }
+    }
+
+    #[test]
+    fn test_parse_zero_duration() {
+        for zero in ["0", "0s", "0d"] {
+            let error = parse_duration(zero).unwrap_err();
+            assert_eq!(error.to_string(), "The duration must be positive");
+        }
+    }
+
+    #[test]
+    fn test_parse_duration_overflow() {
+        assert!(parse_duration(&format!("{}d", u64::MAX / 1000)).is_err());
+        assert!(parse_duration(&format!("{}", u64::MAX)).is_ok());
+    }
+
+    #[test]
+    fn test_merge_toml() {
+        let mut base: toml::Value = toml::from_str(
+            r#"
+            assume_yes = false
+            disable = ["emacs"]
+            [git]
+            repos = ["~/a"]
+            max_concurrency = 5
+            "#,
+        )
+        .unwrap();
+        let fragment: toml::Value = toml::from_str(
+            r#"
+            assume_yes = true
+            disable = ["vim"]
+            [git]
+            repos = ["~/b"]
+            [linux]
+            rpm_ostree = true
+            "#,
+        )
+        .unwrap();
+        merge_toml(&mut base, fragment);
+
+        let expected: toml::Value = toml::from_str(
+            r#"
+            assume_yes = true
+            disable = ["emacs", "vim"]
+            [git]
+            repos = ["~/a", "~/b"]
+            max_concurrency = 5
+            [linux]
+            rpm_ostree = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(base, expected);
+    }
+
+    #[test]
+    fn test_interpolate() {
+        env::set_var("TOPGRADE_TEST_INTERPOLATE", "value");
+        assert_eq!(
+            interpolate("~/${env:TOPGRADE_TEST_INTERPOLATE}/${env:TOPGRADE_TEST_INTERPOLATE}").unwrap(),
+            "~/value/value"
+        );
+        assert_eq!(
+            interpolate("~/src/${hostname}").unwrap(),
+            format!("~/src/{}", hostname().unwrap())
+        );
+        // Other placeholders are left for the shell
+        assert_eq!(interpolate("${HOME} $PATH ${env:}").unwrap(), "${HOME} $PATH ${env:}");
+        assert!(interpolate("${env:TOPGRADE_TEST_UNSET}").is_err());
+    }
+
+    #[test]
+    fn test_interpolate_toml() {
+        env::set_var("TOPGRADE_TEST_INTERPOLATE_TOML", "value");
+        let mut config: toml::Value = toml::from_str(
+            r#"
+            number = 5
+            repos = ["${env:TOPGRADE_TEST_INTERPOLATE_TOML}"]
+            [commands]
+            "${env:TOPGRADE_TEST_INTERPOLATE_TOML}" = "echo ${env:TOPGRADE_TEST_INTERPOLATE_TOML}"
+            "#,
+        )
+        .unwrap();
+        interpolate_toml(&mut config).unwrap();
+
+        let expected: toml::Value = toml::from_str(
+            r#"
+            number = 5
+            repos = ["value"]
+            [commands]
+            "${env:TOPGRADE_TEST_INTERPOLATE_TOML}" = "echo value"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config, expected);
     }
 }
@@ -0,0 +1,190 @@
+//! Comparison of the configuration file with the example configuration, option by option.
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use console::style;
+use lazy_static::lazy_static;
+use regex::Regex;
+use toml::Value;
+
+use crate::config::{DEPRECATED, EXAMPLE_CONFIG};
+
+/// Sections whose keys are chosen by the user, such as the names of custom commands. They're compared as a whole.
+const FREE_FORM_SECTIONS: &[&str] = &[
+    "commands",
+    "pre_commands",
+    "post_commands",
+    "post_change_commands",
+    "hooks",
+    "remotes",
+    "network_devices",
+    "changelogs",
+    "priorities",
+];
+
+lazy_static! {
+    static ref OPTION: Regex = Regex::new(r"^#\s*([A-Za-z0-9_]+)\s*=").unwrap();
+    static ref DEFAULT: Regex = Regex::new(r"\(default: ([^)]*)\)").unwrap();
+    static ref UNKNOWN_FIELD: Regex =
+        Regex::new(r"unknown field `([^`]*)`, expected (.*?)((?: for key `[^`]*`)?(?: at line .*)?)$").unwrap();
+    static ref QUOTED: Regex = Regex::new(r"`([^`]*)`").unwrap();
+}
+
+/// Parse a default value from the comments. Values which aren't TOML are plain words, such as `always`.
+fn parse_default(default: &str) -> Value {
+    toml::from_str::<BTreeMap<String, Value>>(&format!("value = {}", default))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(default.to_string()))
+}
+
+/// The options of the example configuration, in which they're commented out, with their defaults. The defaults
+/// are taken from the `(default: ...)` notes in the comments above the options.
+fn reference_options() -> BTreeMap<String, Option<Value>> {
+    let mut options = BTreeMap::new();
+    let mut section: Option<&str> = None;
+    let mut default = None;
+
+    for line in EXAMPLE_CONFIG.lines().map(str::trim) {
+        let uncommented = line.trim_start_matches('#').trim();
+        if line.is_empty() {
+            default = None;
+        } else if uncommented.starts_with('[') {
+            let name = uncommented.trim_matches(|c| c == '[' || c == ']');
+            let name = name.split('.').next().unwrap();
+            if FREE_FORM_SECTIONS.contains(&name) {
+                options.insert(name.to_string(), None);
+            }
+            section = Some(name);
+            default = None;
+        } else if let Some(captures) = OPTION.captures(line) {
+            let key = &captures[1];
+            match section {
+                Some(section) if FREE_FORM_SECTIONS.contains(&section) => (),
+                Some(section) => {
+                    options.insert(format!("{}.{}", section, key), default.clone());
+                }
+                None => {
+                    options.insert(key.to_string(), default.clone());
+                }
+            }
+        } else if let Some(captures) = DEFAULT.captures(line) {
+            default = Some(parse_default(&captures[1]));
+        }
+    }
+
+    options
+}
+
+/// The options set in the configuration, with sections flattened into `section.option`
+fn user_options(contents: &str, reference: &BTreeMap<String, Option<Value>>) -> Result<BTreeMap<String, Value>> {
+    let table: BTreeMap<String, Value> = toml::from_str(contents)?;
+    let mut options = BTreeMap::new();
+
+    for (key, value) in table {
+        let is_section = !FREE_FORM_SECTIONS.contains(&key.as_str())
+            && reference.keys().any(|option| option.starts_with(&format!("{}.", key)));
+
+        match value {
+            Value::Table(section) if is_section => {
+                for (option, value) in section {
+                    options.insert(format!("{}.{}", key, option), value);
+                }
+            }
+            value => {
+                options.insert(key, value);
+            }
+        }
+    }
+
+    Ok(options)
+}
+
+/// Shorten the parsing errors of unknown options by replacing the list of the expected options with the most similar
+/// one, if there's one. Other errors are returned as they are.
+pub fn explain_error(error: &str) -> String {
+    let captures = match UNKNOWN_FIELD.captures(error) {
+        Some(captures) => captures,
+        None => return error.to_string(),
+    };
+    let unknown = &captures[1];
+
+    let suggestion = QUOTED
+        .captures_iter(&captures[2])
+        .map(|candidate| candidate[1].to_string())
+        .map(|candidate| (strsim::jaro_winkler(unknown, &candidate), candidate))
+        .filter(|(similarity, _)| *similarity > 0.8)
+        .max_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(Ordering::Equal))
+        .map(|(_, candidate)| candidate);
+
+    match suggestion {
+        Some(suggestion) => format!(
+            "unknown field `{}`{}. Did you mean `{}`?",
+            unknown,
+            captures.get(3).map(|location| location.as_str()).unwrap_or(""),
+            suggestion
+        ),
+        None => error.to_string(),
+    }
+}
+
+fn print_title(title: &str) {
+    println!("\n{}", style(title).yellow().bold());
+}
+
+/// Print the options set to other values than their defaults, the ones set to their defaults, deprecated options,
+/// options missing from the reference and the available options which aren't used
+pub fn print_diff(contents: &str) -> Result<()> {
+    let reference = reference_options();
+    let options = user_options(contents, &reference)?;
+
+    let mut changed = Vec::new();
+    let mut defaults = Vec::new();
+    let mut deprecated = Vec::new();
+    let mut unknown = Vec::new();
+    for (name, value) in &options {
+        if let Some((_, replacement)) = DEPRECATED.iter().find(|(old, _)| old == name) {
+            deprecated.push(format!("{} (use {})", name, replacement));
+            continue;
+        }
+
+        match reference.get(name) {
+            None => unknown.push(name.clone()),
+            Some(Some(default)) if default == value => defaults.push(format!("{} = {}", name, value)),
+            Some(_) if FREE_FORM_SECTIONS.contains(&name.as_str()) => changed.push(name.clone()),
+            Some(_) => changed.push(format!("{} = {}", name, value)),
+        }
+    }
+
+    // The unused options are grouped by section to keep the list short
+    let mut unused: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for name in reference.keys().filter(|name| !options.contains_key(*name)) {
+        let (section, option) = name.split_once('.').unwrap_or(("", name));
+        unused.entry(section).or_default().push(option);
+    }
+
+    for (title, names) in [
+        ("Options set to non-default values", &changed),
+        ("Options set to their default values, which can be removed", &defaults),
+        ("Deprecated options", &deprecated),
+        ("Options which aren't in the reference", &unknown),
+    ] {
+        if !names.is_empty() {
+            print_title(title);
+            for name in names {
+                println!("  {}", name);
+            }
+        }
+    }
+
+    if !unused.is_empty() {
+        print_title("Available options which aren't set");
+        for (section, names) in unused {
+            let section = if section.is_empty() { "(top level)" } else { section };
+            println!("  {}: {}", section, names.join(", "));
+        }
+    }
+
+    Ok(())
+}
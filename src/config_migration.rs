@@ -0,0 +1,171 @@
+//! Rewriting of the configuration file, moving deprecated options into their sections. The file is edited line by
+//! line so that comments and formatting are kept.
+use anyhow::{anyhow, Result};
+
+use crate::config::{ConfigFile, DEPRECATED};
+
+/// A header of a table, such as `[git]`, outside comments
+fn section_header(line: &str) -> Option<&str> {
+    let line = line.trim();
+    if line.starts_with('[') && line.ends_with(']') {
+        Some(line.trim_matches(|c| c == '[' || c == ']').trim())
+    } else {
+        None
+    }
+}
+
+/// Whether the line sets the option, such as `git_repos = [`
+fn sets_option(line: &str, option: &str) -> bool {
+    line.trim_start()
+        .strip_prefix(option)
+        .map(|rest| rest.trim_start().starts_with('='))
+        .unwrap_or(false)
+}
+
+/// The lines of the option starting at `start`, which may span several lines, such as arrays
+fn option_end(lines: &[String], start: usize) -> usize {
+    let mut text = String::new();
+    for (index, line) in lines.iter().enumerate().skip(start) {
+        text.push_str(line);
+        text.push('\n');
+        if toml::from_str::<toml::Value>(&text).is_ok() {
+            return index + 1;
+        }
+    }
+
+    lines.len()
+}
+
+/// Move the deprecated options of the top level into their sections. Returns the new contents and a description of
+/// every move, or `None` if there's nothing to migrate.
+pub fn migrate(contents: &str) -> Result<Option<(String, Vec<String>)>> {
+    let mut lines: Vec<String> = contents.lines().map(String::from).collect();
+    let mut moves = Vec::new();
+
+    for (old, new) in DEPRECATED {
+        let (section, option) = new.split_once('.').unwrap();
+
+        // Deprecated options only exist at the top level, before the first section
+        let top_level_end = lines
+            .iter()
+            .position(|line| section_header(line).is_some())
+            .unwrap_or(lines.len());
+        let start = match lines[..top_level_end].iter().position(|line| sets_option(line, old)) {
+            Some(start) => start,
+            None => continue,
+        };
+        let end = option_end(&lines, start);
+
+        // The comments right above the option move along with it
+        let mut first = start;
+        while first > 0 && lines[first - 1].trim_start().starts_with('#') {
+            first -= 1;
+        }
+
+        let mut moved: Vec<String> = lines.drain(first..end).collect();
+        let renamed = &mut moved[start - first];
+        *renamed = renamed.replacen(old, option, 1);
+
+        match lines.iter().position(|line| section_header(line) == Some(section)) {
+            Some(header) => {
+                let section_end = lines
+                    .iter()
+                    .skip(header + 1)
+                    .position(|line| section_header(line).is_some())
+                    .map(|position| position + header + 1)
+                    .unwrap_or(lines.len());
+                if lines[header + 1..section_end]
+                    .iter()
+                    .any(|line| sets_option(line, option))
+                {
+                    return Err(anyhow!("Both {} and {} are set. Remove one of them first", old, new));
+                }
+                lines.splice(header + 1..header + 1, moved);
+            }
+            None => {
+                if lines.last().map(|line| !line.trim().is_empty()).unwrap_or(false) {
+                    lines.push(String::new());
+                }
+                lines.push(format!("[{}]", section));
+                lines.extend(moved);
+            }
+        }
+
+        moves.push(format!("{} -> {}", old, new));
+    }
+
+    if moves.is_empty() {
+        return Ok(None);
+    }
+
+    let mut migrated = lines.join("\n");
+    migrated.push('\n');
+    toml::from_str::<ConfigFile>(&migrated)
+        .map_err(|e| anyhow!("The migrated configuration would be invalid: {}", e))?;
+
+    Ok(Some((migrated, moves)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn migrated(contents: &str) -> String {
+        migrate(contents).unwrap().unwrap().0
+    }
+
+    #[test]
+    fn test_multi_line_array() {
+        let contents = "git_repos = [\n    \"~/a\",\n    \"~/b\",\n]\nassume_yes = true\n";
+        assert_eq!(
+            migrated(contents),
+            "assume_yes = true\n\n[git]\nrepos = [\n    \"~/a\",\n    \"~/b\",\n]\n"
+        );
+    }
+
+    #[test]
+    fn test_comments_move_along() {
+        let contents = "# Pull with rebase\n# everywhere\ngit_arguments = \"--rebase\"\n";
+        assert_eq!(
+            migrated(contents),
+            "[git]\n# Pull with rebase\n# everywhere\narguments = \"--rebase\"\n"
+        );
+    }
+
+    #[test]
+    fn test_existing_section() {
+        let contents = "git_arguments = \"--rebase\"\n\n[git]\nmax_concurrency = 5\n\n[linux]\nrpm_ostree = true\n";
+        assert_eq!(
+            migrated(contents),
+            "\n[git]\narguments = \"--rebase\"\nmax_concurrency = 5\n\n[linux]\nrpm_ostree = true\n"
+        );
+    }
+
+    #[test]
+    fn test_moves() {
+        let (_, moves) = migrate("yay_arguments = \"--nodiffmenu\"\npredefined_git_repos = false\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            moves,
+            vec![
+                "predefined_git_repos -> git.pull_predefined",
+                "yay_arguments -> linux.yay_arguments"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_both_set() {
+        let contents = "git_arguments = \"--rebase\"\n\n[git]\narguments = \"--ff-only\"\n";
+        assert!(migrate(contents).is_err());
+    }
+
+    #[test]
+    fn test_nothing_to_migrate() {
+        assert!(migrate("assume_yes = true\n\n[git]\narguments = \"--rebase\"\n")
+            .unwrap()
+            .is_none());
+        assert!(migrate("").unwrap().is_none());
+    }
+}
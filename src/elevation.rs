@@ -0,0 +1,42 @@
+use std::path::Path;
+
+use crate::executor::Executor;
+
+/// The program used to run commands with elevated privileges
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Elevation {
+    Sudo,
+    Doas,
+    Pkexec,
+    Gsudo,
+}
+
+impl Elevation {
+    /// Tells which elevation program the given path points to
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_stem()?.to_str()?.to_ascii_lowercase();
+        match name.as_str() {
+            "sudo" => Some(Elevation::Sudo),
+            "doas" => Some(Elevation::Doas),
+            "pkexec" => Some(Elevation::Pkexec),
+            "gsudo" => Some(Elevation::Gsudo),
+            _ => None,
+        }
+    }
+
+    /// Add the arguments which precede the elevated command.
+    ///
+    /// `interactive` requests a login shell, which only sudo supports.
+    pub fn add_arguments(self, command: &mut Executor, interactive: bool) {
+        match self {
+            Elevation::Sudo => {
+                command.arg("--preserve-env=DIFFPROG");
+                if interactive {
+                    command.arg("-i");
+                }
+            }
+            // doas keeps the environment according to doas.conf, pkexec and gsudo don't take equivalent flags
+            Elevation::Doas | Elevation::Pkexec | Elevation::Gsudo => (),
+        }
+    }
+}
@@ -10,6 +10,9 @@ pub enum TopgradeError {
     #[error("{0}: {1}")]
     ProcessFailedWithOutput(ExitStatus, String),
 
+    #[error("Killed after producing no output for {0}")]
+    Stalled(String),
+
     #[error("Sudo is required for this step")]
     #[allow(dead_code)]
     SudoRequired,
@@ -27,6 +30,11 @@ pub enum TopgradeError {
 #[error("A step failed")]
 pub struct StepFailed;
 
+/// Returned when `--exit-code-on-changes` is set and some step reported changes.
+#[derive(Error, Debug)]
+#[error("Changes were made")]
+pub struct ChangesDetected;
+
 #[derive(Error, Debug)]
 #[error("Dry running")]
 pub struct DryRun();
@@ -35,6 +43,12 @@ pub struct DryRun();
 #[error("{0}")]
 pub struct SkipStep(pub String);
 
+/// The cause of skipping a step because the binary it requires isn't installed. It comes with a `SkipStep`, so that
+/// it's handled as any skipped step, and tells which binary is missing for the install hints.
+#[derive(Error, Debug)]
+#[error("Cannot find {0:?} in PATH")]
+pub struct MissingBinary(pub String);
+
 #[cfg(all(windows, feature = "self-update"))]
 #[derive(Error, Debug)]
 #[error("Topgrade Upgraded")]
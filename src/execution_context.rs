@@ -1,11 +1,16 @@
 #![allow(dead_code)]
+use crate::elevation::Elevation;
+use crate::error::SkipStep;
 use crate::executor::RunType;
 use crate::git::Git;
+use crate::report::{StepResult, Upgrade};
 use crate::utils::require_option;
 use crate::{config::Config, executor::Executor};
 use anyhow::Result;
 use directories::BaseDirs;
+use log::debug;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
 pub struct ExecutionContext<'a> {
     run_type: RunType,
@@ -13,6 +18,16 @@ pub struct ExecutionContext<'a> {
     git: &'a Git,
     config: &'a Config,
     base_dirs: &'a BaseDirs,
+    /// Whether the running step changed anything. `None` if the step didn't tell.
+    changes: Mutex<Option<bool>>,
+    /// Reasons for rebooting the machine after the run
+    reboot_reasons: Mutex<Vec<String>>,
+    /// Messages to show in the summary
+    summary_notes: Mutex<Vec<String>>,
+    /// Results the running step reported for its parts, which are shown instead of its own result
+    sub_results: Mutex<Option<Vec<(String, StepResult)>>>,
+    /// The packages the running step upgraded, as far as it tells
+    upgrades: Mutex<Vec<Upgrade>>,
 }
 
 impl<'a> ExecutionContext<'a> {
@@ -29,19 +44,21 @@ impl<'a> ExecutionContext<'a> {
             git,
             config,
             base_dirs,
+            changes: Mutex::new(None),
+            reboot_reasons: Mutex::new(Vec::new()),
+            summary_notes: Mutex::new(Vec::new()),
+            sub_results: Mutex::new(None),
+            upgrades: Mutex::new(Vec::new()),
         }
     }
 
     pub fn execute_elevated(&self, command: &Path, interactive: bool) -> Result<Executor> {
-        let sudo = require_option(self.sudo.clone(), "Sudo is required for this operation".into())?;
+        let sudo = self.require_sudo()?;
         let mut cmd = self.run_type.execute(&sudo);
 
-        if sudo.ends_with("sudo") {
-            cmd.arg("--preserve-env=DIFFPROG");
-        }
-
-        if interactive {
-            cmd.arg("-i");
+        match Elevation::from_path(sudo) {
+            Some(elevation) => elevation.add_arguments(&mut cmd, interactive),
+            None => debug!("Unknown elevation program {}", sudo.display()),
         }
 
         cmd.arg(command);
@@ -60,6 +77,15 @@ impl<'a> ExecutionContext<'a> {
         self.sudo
     }
 
+    /// Returns the sudo binary or skips the step if elevation isn't available
+    pub fn require_sudo(&self) -> Result<&PathBuf> {
+        if self.config.no_sudo() {
+            return Err(SkipStep(String::from("Elevation is disabled by --no-sudo")).into());
+        }
+
+        require_option(self.sudo.as_ref(), String::from("Sudo is required for this operation"))
+    }
+
     pub fn config(&self) -> &Config {
         self.config
     }
@@ -67,4 +93,59 @@ impl<'a> ExecutionContext<'a> {
     pub fn base_dirs(&self) -> &BaseDirs {
         self.base_dirs
     }
+
+    /// Report whether the running step changed anything.
+    ///
+    /// Steps may report several times. The step is considered to have changed something
+    /// if any of the reports did.
+    pub fn report_changes(&self, changed: bool) {
+        let mut changes = self.changes.lock().unwrap();
+        *changes = Some(changes.unwrap_or(false) || changed);
+    }
+
+    /// Report the results of the parts of the running step, such as the steps of a remote Topgrade
+    pub fn report_sub_results(&self, results: Vec<(String, StepResult)>) {
+        *self.sub_results.lock().unwrap() = Some(results);
+    }
+
+    /// Take the results reported by the running step for its parts, resetting them for the next one.
+    pub fn take_sub_results(&self) -> Option<Vec<(String, StepResult)>> {
+        self.sub_results.lock().unwrap().take()
+    }
+
+    /// Report the packages the running step upgraded. Reporting upgrades reports changes as well.
+    pub fn report_upgrades(&self, upgrades: Vec<Upgrade>) {
+        self.report_changes(!upgrades.is_empty());
+        self.upgrades.lock().unwrap().extend(upgrades);
+    }
+
+    /// Take the upgrades reported by the running step, resetting them for the next one.
+    pub fn take_upgrades(&self) -> Vec<Upgrade> {
+        std::mem::take(&mut *self.upgrades.lock().unwrap())
+    }
+
+    /// Take the changes reported by the running step, resetting them for the next one.
+    pub fn take_changes(&self) -> Option<bool> {
+        self.changes.lock().unwrap().take()
+    }
+
+    /// Report that a reboot is required to complete the upgrade
+    pub fn report_reboot_required(&self, reason: String) {
+        self.reboot_reasons.lock().unwrap().push(reason);
+    }
+
+    /// The reasons reported by steps for rebooting the machine
+    pub fn reboot_reasons(&self) -> Vec<String> {
+        self.reboot_reasons.lock().unwrap().clone()
+    }
+
+    /// Add a message to show in the summary
+    pub fn add_summary_note(&self, note: String) {
+        self.summary_notes.lock().unwrap().push(note);
+    }
+
+    /// The messages steps added to the summary
+    pub fn summary_notes(&self) -> Vec<String> {
+        self.summary_notes.lock().unwrap().clone()
+    }
 }
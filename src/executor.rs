@@ -1,11 +1,93 @@
 //! Utilities for command execution
+use crate::bandwidth::Limiter;
 use crate::error::{DryRun, TopgradeError};
-use crate::utils::{Check, CheckWithCodes};
+use crate::step_output;
+use crate::terminal::format_duration;
+use crate::utils::CheckWithCodes;
+use crate::watchdog::{self, Watchdog};
 use anyhow::Result;
+use console::Term;
+use lazy_static::lazy_static;
 use log::{debug, trace};
 use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::{self, Read, Write};
 use std::path::Path;
-use std::process::{Child, Command, ExitStatus};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// How often a command watched for stalls is checked for having finished or been killed
+const OUTPUT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Whether the running step may prompt the user, in which case its commands keep the terminal
+static INTERACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Tell whether the commands run from now on may prompt the user. Their output isn't recorded or watched then.
+pub fn set_interactive(interactive: bool) {
+    INTERACTIVE.store(interactive, Ordering::Relaxed);
+}
+
+/// Whether the output of commands is piped through Topgrade to record it
+fn record_output() -> bool {
+    !INTERACTIVE.load(Ordering::Relaxed) && step_output::enabled()
+}
+
+/// Start watching the command for stalls, unless it may be waiting for the user
+fn watch(command: &Command, child: Arc<Mutex<Child>>) -> Option<Watchdog> {
+    if INTERACTIVE.load(Ordering::Relaxed) {
+        return None;
+    }
+    Watchdog::start(command.get_program().to_string_lossy().into_owned(), child)
+}
+
+/// Runs before the first command of a step, which doesn't run if it fails
+pub type PreHook = Box<dyn FnOnce() -> Result<()> + Send>;
+
+/// Whether the running step ran any command
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref PRE_HOOK: Mutex<Option<PreHook>> = Mutex::new(None);
+    /// Limits the downloads of the commands of the running step
+    static ref LIMITER: Mutex<Option<Limiter>> = Mutex::new(None);
+}
+
+/// Limit the downloads of the commands created from now on, or stop limiting them
+pub fn set_limiter(limiter: Option<Limiter>) {
+    *LIMITER.lock().unwrap() = limiter;
+}
+
+/// Prepare for running a step, whose pre hook runs right before its first command. Steps which are skipped before
+/// running any command don't run it. The step counts as started once its pre hook succeeded.
+pub fn prepare_step(pre_hook: Option<PreHook>) {
+    STARTED.store(false, Ordering::Relaxed);
+    *PRE_HOOK.lock().unwrap() = pre_hook;
+}
+
+/// Record that the step runs a command, running its pre hook first. Commands run through an `Executor` do it by
+/// themselves; steps which run their commands otherwise call it before running them.
+pub fn start_step() -> Result<()> {
+    let pre_hook = PRE_HOOK.lock().unwrap().take();
+    if let Some(pre_hook) = pre_hook {
+        if let Err(e) = pre_hook() {
+            // The commands of a failed pre hook don't count as the step starting
+            STARTED.store(false, Ordering::Relaxed);
+            return Err(e);
+        }
+    }
+    STARTED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Tell whether the step prepared last ran any command. Its pre hook is dropped if it didn't.
+pub fn finish_step() -> bool {
+    PRE_HOOK.lock().unwrap().take();
+    STARTED.load(Ordering::Relaxed)
+}
 
 /// An enum telling whether Topgrade should perform dry runs or actually perform the steps.
 #[derive(Clone, Copy, Debug)]
@@ -27,14 +109,18 @@ impl RunType {
         }
     }
 
-    /// Create an instance of `Executor` that should run `program`.
+    /// Create an instance of `Executor` that should run `program`. Its downloads are limited if the step has a
+    /// bandwidth limit.
     pub fn execute<S: AsRef<OsStr>>(self, program: S) -> Executor {
         match self {
             RunType::Dry => Executor::Dry(DryCommand {
                 program: program.as_ref().into(),
                 ..Default::default()
             }),
-            RunType::Wet => Executor::Wet(Command::new(program)),
+            RunType::Wet => Executor::Wet(match LIMITER.lock().unwrap().as_ref() {
+                Some(limiter) => limiter.command(program.as_ref()),
+                None => Command::new(program),
+            }),
         }
     }
 
@@ -88,7 +174,6 @@ impl Executor {
         self
     }
 
-    #[allow(dead_code)]
     /// See `std::process::Command::current_dir`
     pub fn current_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Executor {
         match self {
@@ -117,7 +202,6 @@ impl Executor {
         self
     }
 
-    #[allow(dead_code)]
     /// See `std::process::Command::env`
     pub fn env<K, V>(&mut self, key: K, val: V) -> &mut Executor
     where
@@ -136,6 +220,7 @@ impl Executor {
 
     /// See `std::process::Command::spawn`
     pub fn spawn(&mut self) -> Result<ExecutorChild> {
+        start_step()?;
         let result = match self {
             Executor::Wet(c) => {
                 debug!("Running {:?}", c);
@@ -152,6 +237,7 @@ impl Executor {
 
     /// See `std::process::Command::output`
     pub fn output(&mut self) -> Result<ExecutorOutput> {
+        start_step()?;
         match self {
             Executor::Wet(c) => Ok(ExecutorOutput::Wet(c.output()?)),
             Executor::Dry(c) => {
@@ -165,15 +251,238 @@ impl Executor {
     /// Returns an error if something went wrong during the execution or if the
     /// process exited with failure.
     pub fn check_run(&mut self) -> Result<()> {
-        self.spawn()?.wait()?.check()
+        self.check_run_with_codes(&[])
     }
 
     /// An extension of `check_run` that allows you to set a sequence of codes
     /// that can indicate success of a script
-    #[allow(dead_code)]
     pub fn check_run_with_codes(&mut self, codes: &[i32]) -> Result<()> {
-        self.spawn()?.wait()?.check_with_codes(codes)
+        start_step()?;
+        if record_output() {
+            return self.capture(true, codes).map(|_| ());
+        }
+
+        let c = match self {
+            Executor::Wet(c) if watchdog::enabled() => c,
+            _ => return self.spawn()?.wait()?.check_with_codes(codes),
+        };
+
+        // The command keeps the terminal, so the watchdog only times it
+        debug!("Running {:?}", c);
+        let child = Arc::new(Mutex::new(c.spawn()?));
+        let watchdog = watch(c, child.clone());
+        let status = loop {
+            if let Some(status) = child.lock().unwrap().try_wait()? {
+                break status;
+            }
+            thread::sleep(OUTPUT_POLL_INTERVAL);
+        };
+        if let Some(timeout) = watchdog.and_then(|watchdog| watchdog.killed_after()) {
+            return Err(TopgradeError::Stalled(format_duration(timeout)).into());
+        }
+
+        status.check_with_codes(codes)
+    }
+
+    /// Like `check_run`, but also tells whether the command changed anything.
+    ///
+    /// The standard output is echoed as it arrives, through a pseudo-terminal on Unix so that progress bars keep
+    /// working. The command is considered to have changed something unless its output contains one of
+    /// `unchanged_markers`. Dry runs never change anything.
+    pub fn check_run_detect_changes(&mut self, unchanged_markers: &[&str]) -> Result<bool> {
+        Ok(self
+            .capture(false, &[])?
+            .map(|output| !unchanged_markers.iter().any(|marker| output.contains(marker)))
+            .unwrap_or(false))
+    }
+
+    /// Like `check_run`, but also returns the output of the command. `None` for dry runs.
+    ///
+    /// Both standard output and standard error are echoed as they arrive. The returned output holds the
+    /// standard output followed by the standard error.
+    pub fn check_run_capture(&mut self) -> Result<Option<String>> {
+        self.capture(true, &[])
+    }
+
+    /// Like `check_run_capture`, but returns the exit status along with the output instead of failing
+    /// when the command fails.
+    pub fn run_capture(&mut self) -> Result<Option<(ExitStatus, String)>> {
+        self.capture_unchecked(true)
+    }
+
+    fn capture(&mut self, capture_stderr: bool, codes: &[i32]) -> Result<Option<String>> {
+        match self.capture_unchecked(capture_stderr)? {
+            Some((status, output)) => {
+                status.check_with_codes(codes)?;
+                Ok(Some(output))
+            }
+            None => Ok(None),
+        }
     }
+
+    fn capture_unchecked(&mut self, capture_stderr: bool) -> Result<Option<(ExitStatus, String)>> {
+        start_step()?;
+        let c = match self {
+            Executor::Wet(c) => c,
+            Executor::Dry(c) => {
+                c.dry_run();
+                return Ok(None);
+            }
+        };
+
+        debug!("Running {:?}", c);
+        let stdout_master = pseudo_terminal(&Term::stdout()).map(|(master, slave)| {
+            c.stdout(slave);
+            master
+        });
+        if stdout_master.is_none() {
+            c.stdout(Stdio::piped());
+        }
+        // The standard error is captured as well when recording the output of the step, but only returned if
+        // requested
+        let stderr_captured = capture_stderr || record_output();
+        let stderr_master = if stderr_captured {
+            pseudo_terminal(&Term::stderr()).map(|(master, slave)| {
+                c.stderr(slave);
+                master
+            })
+        } else {
+            None
+        };
+        if stderr_captured && stderr_master.is_none() {
+            c.stderr(Stdio::piped());
+        }
+
+        let spawned = c.spawn();
+        // The pseudo-terminals only hang up once the command is the last one holding them open
+        c.stdout(Stdio::inherit()).stderr(Stdio::inherit());
+        let mut child = spawned?;
+        let child_stdout: Box<dyn Read + Send> = match stdout_master {
+            Some(master) => Box::new(master),
+            None => Box::new(child.stdout.take().unwrap()),
+        };
+        let child_stderr: Option<Box<dyn Read + Send>> = match stderr_master {
+            Some(master) => Some(Box::new(master)),
+            None => child
+                .stderr
+                .take()
+                .map(|stderr| Box::new(stderr) as Box<dyn Read + Send>),
+        };
+
+        let child = Arc::new(Mutex::new(child));
+        let watchdog = watch(c, child.clone());
+
+        // The streams are forwarded in threads so that a killed command isn't waited for. Processes it started may
+        // keep the streams open.
+        let (sender, receiver) = mpsc::channel();
+        let stdout_sender = sender.clone();
+        thread::spawn(move || stdout_sender.send((false, forward(child_stdout, io::stdout(), false))));
+        if let Some(child_stderr) = child_stderr {
+            thread::spawn(move || sender.send((true, forward(child_stderr, io::stderr(), true))));
+        } else {
+            // The receiver only disconnects once every sender is gone
+            drop(sender);
+        }
+
+        let killed_after = || watchdog.as_ref().and_then(|watchdog| watchdog.killed_after());
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        loop {
+            match receiver.recv_timeout(OUTPUT_POLL_INTERVAL) {
+                Ok((false, output)) => stdout = output?,
+                Ok((true, output)) => stderr = output?,
+                Err(RecvTimeoutError::Timeout) if killed_after().is_none() => (),
+                Err(_) => break,
+            }
+        }
+
+        let status = child.lock().unwrap().wait()?;
+        if let Some(timeout) = killed_after() {
+            return Err(TopgradeError::Stalled(format_duration(timeout)).into());
+        }
+
+        if capture_stderr {
+            stdout.extend(stderr);
+        }
+        Ok(Some((status, String::from_utf8_lossy(&stdout).into_owned())))
+    }
+}
+
+/// A pseudo-terminal for capturing the output of a command written to the given terminal, so that the command
+/// keeps its colors and progress bars. Returns the master side, to read the output from, and the slave side, for
+/// the command to write to. `None` if Topgrade doesn't write to a terminal, in which case the output is piped.
+#[cfg(unix)]
+fn pseudo_terminal(term: &Term) -> Option<(File, Stdio)> {
+    use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+    use nix::pty::{openpty, Winsize};
+    use nix::sys::termios::{tcgetattr, tcsetattr, OutputFlags, SetArg};
+    use std::os::unix::io::FromRawFd;
+
+    if !term.is_term() {
+        return None;
+    }
+
+    let (rows, columns) = term.size();
+    let size = Winsize {
+        ws_row: rows,
+        ws_col: columns,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    let result = openpty(&size, None).and_then(|pty| {
+        // Owned right away so that they're closed on errors
+        let (master, slave) = unsafe { (File::from_raw_fd(pty.master), File::from_raw_fd(pty.slave)) };
+        for fd in &[pty.master, pty.slave] {
+            fcntl(*fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+        }
+        // Line endings are translated by the terminal Topgrade writes to, not by this one
+        let mut attributes = tcgetattr(pty.slave)?;
+        attributes.output_flags.remove(OutputFlags::ONLCR);
+        tcsetattr(pty.slave, SetArg::TCSANOW, &attributes)?;
+        Ok((master, Stdio::from(slave)))
+    });
+
+    match result {
+        Ok(terminal) => Some(terminal),
+        Err(e) => {
+            debug!("Failed opening a pseudo-terminal: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn pseudo_terminal(_term: &Term) -> Option<(File, Stdio)> {
+    None
+}
+
+/// Copy everything from `from` to `to`, returning what was copied. The copied output is recorded as the output
+/// of the current step as well.
+///
+/// Chunks are forwarded rather than lines so that prompts without a trailing newline show up.
+fn forward<R: Read, W: Write>(mut from: R, mut to: W, is_error: bool) -> io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut buffer = [0; 4096];
+
+    loop {
+        let read = match from.read(&mut buffer) {
+            Ok(read) => read,
+            // Pseudo-terminals fail reading instead of reaching the end once the command closed them
+            #[cfg(unix)]
+            Err(e) if e.raw_os_error() == Some(nix::errno::Errno::EIO as i32) => 0,
+            Err(e) => return Err(e),
+        };
+        if read == 0 {
+            break;
+        }
+        to.write_all(&buffer[..read])?;
+        to.flush()?;
+        step_output::record(&buffer[..read], is_error);
+        watchdog::output_seen();
+        output.extend_from_slice(&buffer[..read]);
+    }
+
+    Ok(output)
 }
 
 pub enum ExecutorOutput {
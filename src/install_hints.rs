@@ -0,0 +1,71 @@
+/// Picks the hint for the current platform
+fn by_platform(macos: &'static str, windows: &'static str, other: &'static str) -> &'static str {
+    if cfg!(target_os = "macos") {
+        macos
+    } else if cfg!(windows) {
+        windows
+    } else {
+        other
+    }
+}
+
+/// Suggests how to install a binary, for steps skipped because it is missing
+pub fn install_hint(binary: &str) -> Option<&'static str> {
+    let hint = match binary {
+        "aws" => "see https://docs.aws.amazon.com/cli/latest/userguide/getting-started-install.html",
+        "az" => by_platform(
+            "install with `brew install azure-cli`",
+            "install with `winget install Microsoft.AzureCLI`",
+            "see https://learn.microsoft.com/cli/azure/install-azure-cli-linux",
+        ),
+        "bazelisk" => by_platform(
+            "install with `brew install bazelisk`",
+            "install with `winget install Bazel.Bazelisk`",
+            "install with `npm install -g @bazel/bazelisk`",
+        ),
+        "cargo" | "rustup" => "see https://rustup.rs",
+        "cargo-install-update" => "install cargo-update with `cargo install cargo-update`",
+        "chezmoi" => by_platform(
+            "install with `brew install chezmoi`",
+            "install with `winget install twpayne.chezmoi`",
+            "see https://www.chezmoi.io/install/",
+        ),
+        "choco" => "see https://chocolatey.org/install",
+        "devbox" => "install with `curl -fsSL https://get.jetpack.io/devbox | bash`",
+        "flatpak" => "install flatpak with the package manager of your distribution",
+        "gh" => by_platform(
+            "install with `brew install gh`",
+            "install with `winget install GitHub.cli`",
+            "see https://github.com/cli/cli#installation",
+        ),
+        "go-global-update" => "install go-global-update with `go install github.com/Gelio/go-global-update@latest`",
+        "kubectl-krew" => "see https://krew.sigs.k8s.io/docs/user-guide/setup/install/",
+        "mas" => "install with `brew install mas`",
+        "micro" => by_platform(
+            "install with `brew install micro`",
+            "install with `scoop install micro`",
+            "install with `curl https://getmic.ro | bash`",
+        ),
+        "pipx" => by_platform(
+            "install with `brew install pipx`",
+            "install with `scoop install pipx`",
+            "install with `python3 -m pip install --user pipx`",
+        ),
+        "scoop" => "install with `irm get.scoop.sh | iex` in PowerShell",
+        "sheldon" => by_platform(
+            "install with `brew install sheldon`",
+            "install with `cargo install sheldon`",
+            "install with `cargo install sheldon`",
+        ),
+        "snap" => "install snapd with the package manager of your distribution",
+        "tldr" => by_platform(
+            "install with `brew install tldr`",
+            "install with `scoop install tldr`",
+            "install with `npm install -g tldr`",
+        ),
+        "winget" => "install App Installer from the Microsoft Store",
+        _ => return None,
+    };
+
+    Some(hint)
+}
@@ -2,7 +2,9 @@
 
 use std::env;
 use std::io;
+use std::path::Path;
 use std::process::exit;
+use std::time::Instant;
 
 use anyhow::{anyhow, Result};
 use clap::{crate_version, Parser};
@@ -11,29 +13,47 @@ use log::debug;
 use log::LevelFilter;
 use pretty_env_logger::formatted_timed_builder;
 
-use self::config::{CommandLineArgs, Config, Step};
-use self::error::StepFailed;
+use self::config::{CommandLineArgs, Config, RebootPolicy, ReportFormat, Step};
 #[cfg(all(windows, feature = "self-update"))]
 use self::error::Upgraded;
+use self::error::{ChangesDetected, StepFailed};
 use self::steps::{remote::*, *};
 use self::terminal::*;
 
+mod bandwidth;
 mod config;
+mod config_diff;
+mod config_migration;
 mod ctrlc;
+mod elevation;
 mod error;
 mod execution_context;
 mod executor;
+mod install_hints;
+mod notifications;
+mod reboot_check;
+mod reboot_schedule;
 mod report;
 mod runner;
+mod secrets;
 #[cfg(windows)]
 mod self_renamer;
 #[cfg(feature = "self-update")]
 mod self_update;
+mod service;
+mod sleep_inhibitor;
+mod state_file;
+mod step_output;
 mod steps;
 mod terminal;
+#[cfg(all(target_os = "linux", feature = "tray"))]
+mod tray;
 mod utils;
+mod watchdog;
+mod wizard;
 
 fn run() -> Result<()> {
+    let start = Instant::now();
     ctrlc::set_handler();
 
     let base_dirs = directories::BaseDirs::new().ok_or_else(|| anyhow!("No base directories"))?;
@@ -56,7 +76,7 @@ fn run() -> Result<()> {
     builder.init();
 
     if opt.edit_config() {
-        Config::edit(&base_dirs)?;
+        Config::edit(&base_dirs, &opt)?;
         return Ok(());
     };
 
@@ -65,10 +85,58 @@ fn run() -> Result<()> {
         return Ok(());
     }
 
-    let config = Config::load(&base_dirs, opt)?;
+    if opt.diff_config() {
+        return Config::diff(&base_dirs, &opt);
+    }
+
+    if opt.migrate_config() {
+        return Config::migrate(&base_dirs, &opt);
+    }
+
+    if opt.check_config() {
+        exit(if Config::check(&base_dirs, &opt)? { 0 } else { 1 });
+    }
+
+    if opt.prompt_status() {
+        println!("{}", state_file::prompt_status(&base_dirs));
+        return Ok(());
+    }
+
+    if let Some(max_age) = opt.gate() {
+        exit(if state_file::is_due(&base_dirs, max_age) { 0 } else { 1 });
+    }
+
+    if let Some(schedule) = opt.install_service() {
+        return service::install(&base_dirs, schedule);
+    }
+
+    let mut config = Config::load(&base_dirs, opt)?;
+
+    if config.list_remotes() {
+        return remote::ssh::list_remotes(&config);
+    }
+
+    if config.interactive() {
+        let steps = config.enabled_steps().to_vec();
+        let selection = select_items("Select the steps to run", &steps)?;
+        config.select_steps(
+            steps
+                .into_iter()
+                .zip(selection)
+                .filter_map(|(step, selected)| if selected { Some(step) } else { None })
+                .collect(),
+        );
+    }
+
     terminal::set_title(config.set_title());
     terminal::display_time(config.display_time());
     terminal::set_desktop_notifications(config.notify_each_step());
+    terminal::set_notifications(config.notify_on(), config.notification_command());
+
+    #[cfg(all(target_os = "linux", feature = "tray"))]
+    if config.tray() {
+        tray::show();
+    }
 
     debug!("Version: {}", crate_version!());
     debug!("OS: {}", env!("TARGET"));
@@ -85,17 +153,47 @@ fn run() -> Result<()> {
 
     let git = git::Git::new();
     let mut git_repos = git::Repositories::new(&git);
+    git_repos.set_exclude(config.git_exclude());
 
-    let sudo = utils::sudo();
+    let sudo = if config.no_sudo() {
+        None
+    } else {
+        utils::sudo(config.elevation_command())
+    };
     let run_type = executor::RunType::new(config.dry_run());
 
     let ctx = execution_context::ExecutionContext::new(run_type, &sudo, &git, &config, &base_dirs);
 
     let mut runner = runner::Runner::new(&ctx);
 
+    // Running a single step doesn't take part in resuming, so that it doesn't forget an interrupted run
+    let track_progress = !run_type.dry() && !config.single_step();
+    if track_progress {
+        let resumed = if config.resume() {
+            state_file::interrupted_run(&base_dirs).unwrap_or_else(|| {
+                print_info("There's no interrupted run to resume");
+                Vec::new()
+            })
+        } else {
+            Vec::new()
+        };
+        runner.track_progress(resumed);
+    }
+
+    // The output of failed steps can be opened from the prompt at the end, and excerpts are shown in the summary
+    if (config.keep_at_end() || config.failure_output_lines().is_some()) && !run_type.dry() {
+        step_output::enable();
+    }
+
+    if let Some(timeout) = config.stall_timeout() {
+        if !run_type.dry() {
+            watchdog::enable(timeout, config.no_retry() || is_dumb());
+        }
+    }
+
     #[cfg(feature = "self-update")]
     {
-        if !run_type.dry() && env::var("TOPGRADE_NO_SELF_UPGRADE").is_err() {
+        if !run_type.dry() && !config.single_step() && env::var("TOPGRADE_NO_SELF_UPGRADE").is_err() {
             let result = self_update::self_update();
 
             if let Err(e) = &result {
@@ -108,6 +206,12 @@ fn run() -> Result<()> {
                 print_warning(format!("Self update error: {}", e));
             }
         }
+
+        // Set by the Topgrade which updated itself and restarted as this one
+        if let Ok(version) = env::var("TOPGRADE_SELF_UPGRADED") {
+            env::remove_var("TOPGRADE_SELF_UPGRADED");
+            runner.record_self_update(version);
+        }
     }
 
     #[cfg(windows)]
@@ -123,77 +227,322 @@ fn run() -> Result<()> {
         }
     }
 
+    #[cfg(target_os = "linux")]
+    let distribution = linux::Distribution::detect();
+
     let powershell = powershell::Powershell::new();
-    let should_run_powershell = powershell.profile().is_some() && config.should_run(Step::Powershell);
 
-    #[cfg(windows)]
-    runner.execute(Step::Wsl, "WSL", || windows::run_wsl_topgrade(&ctx))?;
+    let emacs = emacs::Emacs::new(&base_dirs);
+    if config.use_predefined_git_repos() {
+        if config.should_run(Step::Emacs) {
+            if !emacs.is_doom() {
+                if let Some(directory) = emacs.directory() {
+                    git_repos.insert_if_repo(directory);
+                }
+            }
+            git_repos.insert_if_repo(base_dirs.home_dir().join(".doom.d"));
+        }
 
-    if let Some(topgrades) = config.remote_topgrades() {
-        for remote_topgrade in topgrades.iter().filter(|t| config.should_execute_remote(t)) {
-            runner.execute(Step::Remotes, format!("Remote ({})", remote_topgrade), || {
-                remote::ssh::ssh_step(&ctx, remote_topgrade)
-            })?;
+        if config.should_run(Step::Vim) {
+            git_repos.insert_if_repo(base_dirs.home_dir().join(".vim"));
+            git_repos.insert_if_repo(base_dirs.home_dir().join(".config/nvim"));
+        }
+
+        git_repos.insert_if_repo(base_dirs.home_dir().join(".ideavimrc"));
+        git_repos.insert_if_repo(base_dirs.home_dir().join(".intellimacs"));
+
+        #[cfg(unix)]
+        {
+            git_repos.insert_if_repo(zsh::zshrc(&base_dirs));
+            if config.should_run(Step::Tmux) {
+                git_repos.insert_if_repo(base_dirs.home_dir().join(".tmux"));
+            }
+            git_repos.insert_if_repo(base_dirs.home_dir().join(".config/fish"));
+            git_repos.insert_if_repo(base_dirs.config_dir().join("openbox"));
+            git_repos.insert_if_repo(base_dirs.config_dir().join("bspwm"));
+            git_repos.insert_if_repo(base_dirs.config_dir().join("i3"));
+            git_repos.insert_if_repo(base_dirs.config_dir().join("sway"));
+        }
+
+        #[cfg(windows)]
+        git_repos.insert_if_repo(
+            base_dirs
+                .data_local_dir()
+                .join("Packages/Microsoft.WindowsTerminal_8wekyb3d8bbwe/LocalState"),
+        );
+
+        #[cfg(windows)]
+        windows::insert_startup_scripts(&ctx, &mut git_repos).ok();
+
+        if let Some(profile) = powershell.profile() {
+            git_repos.insert_if_repo(profile);
         }
     }
 
-    #[cfg(target_os = "linux")]
-    let distribution = linux::Distribution::detect();
+    let vagrant_boxes = if config.should_run(Step::Vagrant) {
+        vagrant::collect_boxes(&ctx).unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+
+    // Steps with higher priorities run first, so that they run even if the run is cut short
+    for priority in config.priority_levels() {
+        runner.set_priority(priority);
+        run_steps(
+            &mut runner,
+            &ctx,
+            &mut git_repos,
+            &powershell,
+            &emacs,
+            &vagrant_boxes,
+            #[cfg(target_os = "linux")]
+            &distribution,
+        )?;
+    }
+    runner.check_run_key()?;
+
+    if track_progress {
+        state_file::clear_progress(&base_dirs);
+    }
+
+    let mut reboot_reasons = ctx.reboot_reasons();
+    reboot_reasons.extend(reboot_check::reboot_reasons());
+
+    // Reboots in a window are scheduled before the summary, which tells when the machine reboots
+    let reboot_required = config.reboot_policy() == RebootPolicy::IfRequired
+        && !reboot_reasons.is_empty()
+        && !runner.report().data().iter().any(|(_, result)| result.failed())
+        && !config.dry_run();
+    let mut reboot_now = reboot_required;
+    if let Some(at) = config
+        .reboot_window()
+        .filter(|_| reboot_required)
+        .and_then(|window| window.next_reboot(chrono::Local::now()))
+    {
+        reboot_now = false;
+        match reboot_schedule::schedule(&ctx, at) {
+            Ok(()) => ctx.add_summary_note(format!("Reboot scheduled at {}", at.format("%a %Y-%m-%d %H:%M"))),
+            Err(e) => print_warning(format!("Failed scheduling the reboot: {}", e)),
+        }
+    }
+
+    if config.report_format() == ReportFormat::Json {
+        println!("{}", runner.report().to_json());
+    } else if !runner.report().data().is_empty() {
+        print_separator("Summary");
+
+        for (key, result) in runner.report().data() {
+            print_result(key, result);
+            if let Some(excerpt) = runner.failure_excerpt(key) {
+                print_excerpt(excerpt);
+            }
+            print_links(&runner.changelog_links(key));
+        }
+
+        for note in ctx.summary_notes() {
+            print_info(note);
+        }
+
+        print_reboot_required(&reboot_reasons);
+
+        #[cfg(target_os = "linux")]
+        {
+            if let Ok(distribution) = &distribution {
+                distribution.show_summary();
+            }
+        }
+
+        #[cfg(target_os = "freebsd")]
+        freebsd::audit_packages(&sudo).ok();
+
+        #[cfg(target_os = "dragonfly")]
+        dragonfly::audit_packages(&sudo).ok();
+    }
+
+    let mut post_command_failed = false;
+    if let Some(commands) = config.post_commands() {
+        for (name, command) in commands {
+            if generic::run_custom_command(name, command, &ctx).is_err() {
+                post_command_failed = true;
+            }
+        }
+    }
+
+    if runner.report().has_changes() {
+        if let Some(commands) = config.post_change_commands() {
+            for (name, command) in commands {
+                if generic::run_custom_command(name, command, &ctx).is_err() {
+                    post_command_failed = true;
+                }
+            }
+        }
+    }
+
+    let failed = post_command_failed || runner.report().data().iter().any(|(_, result)| result.failed());
+
+    if config.state_file() && !config.dry_run() {
+        if let Err(e) = state_file::write(&base_dirs, runner.report(), runner.durations(), failed, start.elapsed()) {
+            print_warning(format!("Failed writing the state file: {}", e));
+        } else if let Some(metrics_file) = config.metrics_file() {
+            if let Err(e) = state_file::write_metrics(&base_dirs, metrics_file) {
+                print_warning(format!("Failed writing the metrics: {}", e));
+            }
+        }
+    }
+
+    if !config.dry_run() {
+        notifications::send(&config, runner.report(), failed, start.elapsed());
+    }
+
+    if reboot_now && !failed {
+        reboot();
+    }
+
+    if config.keep_at_end() {
+        let has_failure_logs = !runner.failure_logs().is_empty();
+        if has_failure_logs {
+            print_info("\n(R)eboot\n(S)hell\n(O)pen log\n(Q)uit");
+        } else {
+            print_info("\n(R)eboot\n(S)hell\n(Q)uit");
+        }
+        loop {
+            match get_key() {
+                Ok(Key::Char('o')) | Ok(Key::Char('O')) if has_failure_logs => {
+                    if let Err(e) = step_output::open_log(runner.failure_logs()) {
+                        print_warning(format!("Failed opening the log: {}", e));
+                    }
+                    continue;
+                }
+                Ok(Key::Char('s')) | Ok(Key::Char('S')) => {
+                    run_shell();
+                }
+                Ok(Key::Char('r')) | Ok(Key::Char('R')) => {
+                    reboot();
+                }
+                Ok(Key::Char('q')) | Ok(Key::Char('Q')) => (),
+                _ => {
+                    continue;
+                }
+            }
+            break;
+        }
+    }
+
+    if config.notify_on().applies(failed) {
+        terminal::notify_desktop(
+            format!(
+                "Topgrade finished {} in {}",
+                if failed { "with errors" } else { "successfully" },
+                format_duration(start.elapsed())
+            ),
+            None,
+        );
+    }
+
+    #[cfg(all(target_os = "linux", feature = "tray"))]
+    tray::finish(failed);
+
+    if failed {
+        Err(StepFailed.into())
+    } else if config.exit_code_on_changes() && runner.report().has_changes() {
+        Err(ChangesDetected.into())
+    } else {
+        Ok(())
+    }
+}
+
+/// Runs the steps of the current priority level of the runner
+fn run_steps<'a>(
+    runner: &mut runner::Runner<'a>,
+    ctx: &'a execution_context::ExecutionContext<'a>,
+    git_repos: &mut git::Repositories,
+    powershell: &powershell::Powershell,
+    emacs: &emacs::Emacs,
+    vagrant_boxes: &[vagrant::VagrantBox],
+    #[cfg(target_os = "linux")] distribution: &Result<linux::Distribution>,
+) -> Result<()> {
+    let config = ctx.config();
+    let base_dirs = ctx.base_dirs();
+    let run_type = ctx.run_type();
+    let git = ctx.git();
+    #[cfg(any(target_os = "linux", target_os = "freebsd", target_os = "dragonfly"))]
+    let sudo = ctx.sudo();
+
+    #[cfg(windows)]
+    runner.execute(Step::Wsl, "WSL", || windows::run_wsl_topgrade(ctx))?;
+
+    for remote in config.remotes().iter().filter(|r| config.should_execute_remote(r)) {
+        runner.execute(Step::Remotes, format!("Remote ({})", remote.host()), || {
+            remote::ssh::ssh_step(ctx, remote)
+        })?;
+    }
 
     #[cfg(target_os = "linux")]
     {
-        match &distribution {
+        match distribution {
             Ok(distribution) => {
-                runner.execute(Step::System, "System update", || distribution.upgrade(&ctx))?;
+                runner.execute(Step::System, "System update", || distribution.upgrade(ctx))?;
             }
             Err(e) => {
                 println!("Error detecting current distribution: {}", e);
             }
         }
-        runner.execute(Step::ConfigUpdate, "config-update", || linux::run_config_update(&ctx))?;
+        runner.execute(Step::ConfigUpdate, "config-update", || linux::run_config_update(ctx))?;
 
         runner.execute(Step::BrewFormula, "Brew", || {
-            unix::run_brew_formula(&ctx, unix::BrewVariant::Path)
+            unix::run_brew_formula(ctx, unix::BrewVariant::Path)
         })?;
     }
 
     #[cfg(windows)]
     {
-        runner.execute(Step::Chocolatey, "Chocolatey", || windows::run_chocolatey(&ctx))?;
+        runner.execute(Step::Chocolatey, "Chocolatey", || windows::run_chocolatey(ctx))?;
         runner.execute(Step::Scoop, "Scoop", || windows::run_scoop(config.cleanup(), run_type))?;
-        runner.execute(Step::Winget, "Winget", || windows::run_winget(&ctx))?;
+        runner.execute(Step::Winget, "Winget", || windows::run_winget(ctx))?;
+        runner.execute(Step::MicrosoftStore, "Microsoft Store", || {
+            windows::microsoft_store(ctx)
+        })?;
     }
 
     #[cfg(target_os = "macos")]
     {
         runner.execute(Step::BrewFormula, "Brew (ARM)", || {
-            unix::run_brew_formula(&ctx, unix::BrewVariant::MacArm)
+            unix::run_brew_formula(ctx, unix::BrewVariant::MacArm)
         })?;
         runner.execute(Step::BrewFormula, "Brew (Intel)", || {
-            unix::run_brew_formula(&ctx, unix::BrewVariant::MacIntel)
+            unix::run_brew_formula(ctx, unix::BrewVariant::MacIntel)
         })?;
         runner.execute(Step::BrewFormula, "Brew", || {
-            unix::run_brew_formula(&ctx, unix::BrewVariant::Path)
+            unix::run_brew_formula(ctx, unix::BrewVariant::Path)
         })?;
         runner.execute(Step::BrewCask, "Brew Cask (ARM)", || {
-            unix::run_brew_cask(&ctx, unix::BrewVariant::MacArm)
+            unix::run_brew_cask(ctx, unix::BrewVariant::MacArm)
         })?;
         runner.execute(Step::BrewCask, "Brew Cask (Intel)", || {
-            unix::run_brew_cask(&ctx, unix::BrewVariant::MacIntel)
+            unix::run_brew_cask(ctx, unix::BrewVariant::MacIntel)
         })?;
         runner.execute(Step::BrewCask, "Brew Cask", || {
-            unix::run_brew_cask(&ctx, unix::BrewVariant::Path)
+            unix::run_brew_cask(ctx, unix::BrewVariant::Path)
         })?;
-        runner.execute(Step::Macports, "MacPorts", || macos::run_macports(&ctx))?;
+        runner.execute(Step::Macports, "MacPorts", || macos::run_macports(ctx))?;
     }
 
     #[cfg(unix)]
     {
-        runner.execute(Step::Yadm, "yadm", || unix::run_yadm(&ctx))?;
-        runner.execute(Step::Nix, "nix", || unix::run_nix(&ctx))?;
-        runner.execute(Step::HomeManager, "home-manager", || unix::run_home_manager(run_type))?;
-        runner.execute(Step::Asdf, "asdf", || unix::run_asdf(run_type))?;
-        runner.execute(Step::Pkgin, "pkgin", || unix::run_pkgin(&ctx))?;
+        runner.execute(Step::Yadm, "yadm", || unix::run_yadm(ctx))?;
+        runner.execute(Step::Nix, "nix", || unix::run_nix(ctx))?;
+        runner.execute(Step::HomeManager, "home-manager", || unix::run_home_manager(ctx))?;
+        runner.execute(Step::Devbox, "devbox", || unix::run_devbox(ctx))?;
+        runner.execute(Step::Flox, "flox", || unix::run_flox(ctx))?;
+        if let Some(projects) = config.devenv_directories() {
+            for project in projects {
+                runner.execute(Step::Devenv, format!("devenv ({})", project), || {
+                    unix::run_devenv(ctx, project)
+                })?;
+            }
+        }
+        runner.execute(Step::Asdf, "asdf", || unix::run_asdf(ctx))?;
+        runner.execute(Step::Pkgin, "pkgin", || unix::run_pkgin(ctx))?;
     }
 
     #[cfg(target_os = "dragonfly")]
@@ -207,96 +556,90 @@ fn run() -> Result<()> {
     })?;
 
     #[cfg(target_os = "android")]
-    runner.execute(Step::Pkg, "Termux Packages", || android::upgrade_packages(&ctx))?;
+    runner.execute(Step::Pkg, "Termux Packages", || android::upgrade_packages(ctx))?;
 
-    let emacs = emacs::Emacs::new(&base_dirs);
-    if config.use_predefined_git_repos() {
-        if config.should_run(Step::Emacs) {
-            if !emacs.is_doom() {
-                if let Some(directory) = emacs.directory() {
-                    git_repos.insert_if_repo(directory);
-                }
+    if runner.should_run(Step::GitRepos) {
+        if let Some(custom_git_repos) = config.git_repos() {
+            for git_repo in custom_git_repos {
+                git_repos.glob_insert(git_repo);
             }
-            git_repos.insert_if_repo(base_dirs.home_dir().join(".doom.d"));
-        }
-
-        if config.should_run(Step::Vim) {
-            git_repos.insert_if_repo(base_dirs.home_dir().join(".vim"));
-            git_repos.insert_if_repo(base_dirs.home_dir().join(".config/nvim"));
         }
-
-        git_repos.insert_if_repo(base_dirs.home_dir().join(".ideavimrc"));
-        git_repos.insert_if_repo(base_dirs.home_dir().join(".intellimacs"));
-
-        #[cfg(unix)]
-        {
-            git_repos.insert_if_repo(zsh::zshrc(&base_dirs));
-            if config.should_run(Step::Tmux) {
-                git_repos.insert_if_repo(base_dirs.home_dir().join(".tmux"));
+        if config.git_ghq() {
+            if let Err(e) = git_repos.ghq_insert(config.git_ghq_exclude()) {
+                print_warning(format!("Failed listing ghq repositories: {}", e));
             }
-            git_repos.insert_if_repo(base_dirs.home_dir().join(".config/fish"));
-            git_repos.insert_if_repo(base_dirs.config_dir().join("openbox"));
-            git_repos.insert_if_repo(base_dirs.config_dir().join("bspwm"));
-            git_repos.insert_if_repo(base_dirs.config_dir().join("i3"));
-            git_repos.insert_if_repo(base_dirs.config_dir().join("sway"));
         }
+        runner.execute(Step::GitRepos, "Git repositories", || {
+            git.multi_pull_step("Git repositories", git_repos, ctx)
+        })?;
+    }
 
-        #[cfg(windows)]
-        git_repos.insert_if_repo(
-            base_dirs
-                .data_local_dir()
-                .join("Packages/Microsoft.WindowsTerminal_8wekyb3d8bbwe/LocalState"),
-        );
-
-        #[cfg(windows)]
-        windows::insert_startup_scripts(&ctx, &mut git_repos).ok();
-
-        if let Some(profile) = powershell.profile() {
-            git_repos.insert_if_repo(profile);
+    if runner.should_run(Step::Terminals) {
+        let mut terminal_repos = git::Repositories::new(git);
+        terminal_repos.set_exclude(config.git_exclude());
+        for path in generic::terminal_repositories(base_dirs) {
+            terminal_repos.insert_if_repo(path);
         }
+        for repo in config.terminal_repos().into_iter().flatten() {
+            terminal_repos.glob_insert(repo);
+        }
+        runner.execute(Step::Terminals, "Terminal themes and plugins", || {
+            git.multi_pull_step("Terminal themes and plugins", &terminal_repos, ctx)
+        })?;
     }
 
-    if config.should_run(Step::GitRepos) {
-        if let Some(custom_git_repos) = config.git_repos() {
-            for git_repo in custom_git_repos {
-                git_repos.glob_insert(git_repo);
-            }
+    // Only the hooks of the configured repositories are updated, since updating them changes the repository
+    let mut pre_commit_repos: Vec<(&str, bool)> = config
+        .pre_commit_directories()
+        .into_iter()
+        .flatten()
+        .map(|d| (d.as_str(), true))
+        .collect();
+    for repo in git_repos.iter() {
+        if !pre_commit_repos.iter().any(|(r, _)| r == &repo) && Path::new(repo).join(".pre-commit-config.yaml").exists()
+        {
+            pre_commit_repos.push((repo, false));
         }
-        runner.execute(Step::GitRepos, "Git repositories", || {
-            git.multi_pull_step(&git_repos, &ctx)
+    }
+    for (repo, autoupdate) in &pre_commit_repos {
+        runner.execute(Step::PreCommit, format!("pre-commit ({})", repo), || {
+            generic::run_pre_commit(ctx, repo, *autoupdate)
         })?;
     }
+    if !pre_commit_repos.is_empty() {
+        runner.execute(Step::PreCommit, "pre-commit gc", || generic::run_pre_commit_gc(ctx))?;
+    }
 
-    if should_run_powershell {
+    if powershell.profile().is_some() {
         runner.execute(Step::Powershell, "Powershell Modules Update", || {
-            powershell.update_modules(&ctx)
+            powershell.update_modules(ctx)
         })?;
     }
 
     #[cfg(unix)]
     {
-        runner.execute(Step::Shell, "zr", || zsh::run_zr(&base_dirs, run_type))?;
+        runner.execute(Step::Shell, "zr", || zsh::run_zr(base_dirs, run_type))?;
         runner.execute(Step::Shell, "antibody", || zsh::run_antibody(run_type))?;
-        runner.execute(Step::Shell, "antigen", || zsh::run_antigen(&base_dirs, run_type))?;
-        runner.execute(Step::Shell, "zgenom", || zsh::run_zgenom(&base_dirs, run_type))?;
-        runner.execute(Step::Shell, "zplug", || zsh::run_zplug(&base_dirs, run_type))?;
-        runner.execute(Step::Shell, "zinit", || zsh::run_zinit(&base_dirs, run_type))?;
-        runner.execute(Step::Shell, "zi", || zsh::run_zi(&base_dirs, run_type))?;
-        runner.execute(Step::Shell, "zim", || zsh::run_zim(&base_dirs, run_type))?;
-        runner.execute(Step::Shell, "oh-my-zsh", || zsh::run_oh_my_zsh(&ctx))?;
-        runner.execute(Step::Shell, "fisher", || unix::run_fisher(&base_dirs, run_type))?;
-        runner.execute(Step::Shell, "bash-it", || unix::run_bashit(&ctx))?;
-        runner.execute(Step::Shell, "oh-my-fish", || unix::run_oh_my_fish(&ctx))?;
-        runner.execute(Step::Shell, "fish-plug", || unix::run_fish_plug(&ctx))?;
-        runner.execute(Step::Tmux, "tmux", || tmux::run_tpm(&base_dirs, run_type))?;
+        runner.execute(Step::Shell, "antigen", || zsh::run_antigen(base_dirs, run_type))?;
+        runner.execute(Step::Shell, "zgenom", || zsh::run_zgenom(base_dirs, run_type))?;
+        runner.execute(Step::Shell, "zplug", || zsh::run_zplug(base_dirs, run_type))?;
+        runner.execute(Step::Shell, "zinit", || zsh::run_zinit(base_dirs, run_type))?;
+        runner.execute(Step::Shell, "zi", || zsh::run_zi(base_dirs, run_type))?;
+        runner.execute(Step::Shell, "zim", || zsh::run_zim(base_dirs, run_type))?;
+        runner.execute(Step::Shell, "oh-my-zsh", || zsh::run_oh_my_zsh(ctx))?;
+        runner.execute(Step::Shell, "fisher", || unix::run_fisher(base_dirs, run_type))?;
+        runner.execute(Step::Shell, "bash-it", || unix::run_bashit(ctx))?;
+        runner.execute(Step::Shell, "oh-my-fish", || unix::run_oh_my_fish(ctx))?;
+        runner.execute(Step::Shell, "fish-plug", || unix::run_fish_plug(ctx))?;
+        runner.execute(Step::Tmux, "tmux", || tmux::run_tpm(base_dirs, run_type))?;
         runner.execute(Step::Tldr, "TLDR", || unix::run_tldr(run_type))?;
         runner.execute(Step::Pearl, "pearl", || unix::run_pearl(run_type))?;
         #[cfg(not(any(target_os = "macos", target_os = "android")))]
         runner.execute(Step::GnomeShellExtensions, "Gnome Shell Extensions", || {
-            unix::upgrade_gnome_extensions(&ctx)
+            unix::upgrade_gnome_extensions(ctx)
         })?;
         runner.execute(Step::Sdkman, "SDKMAN!", || {
-            unix::run_sdkman(&base_dirs, config.cleanup(), run_type)
+            unix::run_sdkman(base_dirs, config.cleanup(), run_type)
         })?;
     }
 
@@ -308,66 +651,130 @@ fn run() -> Result<()> {
     )))]
     runner.execute(Step::Atom, "apm", || generic::run_apm(run_type))?;
     runner.execute(Step::Fossil, "fossil", || generic::run_fossil(run_type))?;
-    runner.execute(Step::Rustup, "rustup", || generic::run_rustup(&base_dirs, run_type))?;
-    runner.execute(Step::Dotnet, ".NET", || generic::run_dotnet_upgrade(&ctx))?;
-    runner.execute(Step::Choosenim, "choosenim", || generic::run_choosenim(&ctx))?;
-    runner.execute(Step::Cargo, "cargo", || generic::run_cargo_update(&ctx))?;
+    runner.execute(Step::Rustup, "rustup", || generic::run_rustup(base_dirs, run_type))?;
+    runner.execute(Step::Dotnet, ".NET", || generic::run_dotnet_upgrade(ctx))?;
+    runner.execute(Step::Choosenim, "choosenim", || generic::run_choosenim(ctx))?;
+    runner.execute(Step::Cargo, "cargo", || generic::run_cargo_update(ctx))?;
     runner.execute(Step::Flutter, "Flutter", || generic::run_flutter_upgrade(run_type))?;
     runner.execute(Step::Go, "Go", || generic::run_go(run_type))?;
-    runner.execute(Step::Emacs, "Emacs", || emacs.upgrade(&ctx))?;
+    runner.execute(Step::Emacs, "Emacs", || emacs.upgrade(ctx))?;
     runner.execute(Step::Opam, "opam", || generic::run_opam_update(run_type))?;
     runner.execute(Step::Vcpkg, "vcpkg", || generic::run_vcpkg_update(run_type))?;
     runner.execute(Step::Pipx, "pipx", || generic::run_pipx_update(run_type))?;
-    runner.execute(Step::Conda, "conda", || generic::run_conda_update(&ctx))?;
-    runner.execute(Step::Pip3, "pip3", || generic::run_pip3_update(run_type))?;
+    runner.execute(Step::Conda, "conda", || generic::run_conda_update(ctx))?;
+    runner.execute(Step::Pip3, "pip3", || generic::run_pip3_update(ctx))?;
     runner.execute(Step::Stack, "stack", || generic::run_stack_update(run_type))?;
-    runner.execute(Step::Tlmgr, "tlmgr", || generic::run_tlmgr_update(&ctx))?;
+    runner.execute(Step::Tlmgr, "tlmgr", || generic::run_tlmgr_update(ctx))?;
     runner.execute(Step::Myrepos, "myrepos", || {
-        generic::run_myrepos_update(&base_dirs, run_type)
+        generic::run_myrepos_update(base_dirs, run_type)
     })?;
     runner.execute(Step::Chezmoi, "chezmoi", || {
-        generic::run_chezmoi_update(&base_dirs, run_type)
+        generic::run_chezmoi_update(base_dirs, run_type)
     })?;
+    if let Some(workspaces) = config.repo_directories() {
+        for workspace in workspaces {
+            runner.execute(Step::Repo, format!("repo ({})", workspace), || {
+                generic::run_repo_sync(ctx, workspace)
+            })?;
+        }
+    }
+    if let Some(workspaces) = config.west_directories() {
+        for workspace in workspaces {
+            runner.execute(Step::West, format!("west ({})", workspace), || {
+                generic::run_west_update(ctx, workspace)
+            })?;
+        }
+    }
+    if let Some(working_copies) = config.hg_directories() {
+        for working_copy in working_copies {
+            runner.execute(Step::Hg, format!("Mercurial ({})", working_copy), || {
+                generic::run_hg_pull(ctx, working_copy)
+            })?;
+        }
+    }
+    if let Some(working_copies) = config.svn_directories() {
+        for working_copy in working_copies {
+            runner.execute(Step::Svn, format!("Subversion ({})", working_copy), || {
+                generic::run_svn_update(ctx, working_copy)
+            })?;
+        }
+    }
     runner.execute(Step::Jetpack, "jetpack", || generic::run_jetpack(run_type))?;
-    runner.execute(Step::Vim, "vim", || vim::upgrade_vim(&base_dirs, &ctx))?;
-    runner.execute(Step::Vim, "Neovim", || vim::upgrade_neovim(&base_dirs, &ctx))?;
-    runner.execute(Step::Vim, "The Ultimate vimrc", || vim::upgrade_ultimate_vimrc(&ctx))?;
-    runner.execute(Step::Vim, "voom", || vim::run_voom(&base_dirs, run_type))?;
-    runner.execute(Step::Kakoune, "Kakoune", || kakoune::upgrade_kak_plug(&ctx))?;
-    runner.execute(Step::Node, "npm", || node::run_npm_upgrade(&ctx))?;
-    runner.execute(Step::Containers, "Containers", || containers::run_containers(&ctx))?;
-    runner.execute(Step::Deno, "deno", || node::deno_upgrade(&ctx))?;
-    runner.execute(Step::Composer, "composer", || generic::run_composer_update(&ctx))?;
-    runner.execute(Step::Krew, "krew", || generic::run_krew_upgrade(run_type))?;
-    runner.execute(Step::Gem, "gem", || generic::run_gem(&base_dirs, run_type))?;
-    runner.execute(Step::Haxelib, "haxelib", || generic::run_haxelib_update(&ctx))?;
-    runner.execute(Step::Sheldon, "sheldon", || generic::run_sheldon(&ctx))?;
-    runner.execute(Step::Rtcl, "rtcl", || generic::run_rtcl(&ctx))?;
-    runner.execute(Step::Bin, "bin", || generic::bin_update(&ctx))?;
+    runner.execute(Step::Vim, "vim", || vim::upgrade_vim(base_dirs, ctx))?;
+    for appname in config.nvim_appnames() {
+        runner.execute(Step::Vim, vim::nvim_title("Neovim", appname), || {
+            vim::upgrade_neovim(base_dirs, ctx, appname)
+        })?;
+        if config.update_mason() {
+            runner.execute(Step::Vim, vim::nvim_title("Mason", appname), || {
+                vim::upgrade_mason(base_dirs, ctx, appname)
+            })?;
+        }
+    }
+    runner.execute(Step::Vim, "The Ultimate vimrc", || vim::upgrade_ultimate_vimrc(ctx))?;
+    runner.execute(Step::Vim, "voom", || vim::run_voom(base_dirs, run_type))?;
+    for binary in config.vscode_binaries() {
+        runner.execute(Step::Vscode, binary, || {
+            generic::run_vscode_extensions_update(ctx, binary)
+        })?;
+    }
+    runner.execute(Step::Kakoune, "Kakoune", || kakoune::upgrade_kak_plug(ctx))?;
+    runner.execute(Step::Node, "npm", || node::run_npm_upgrade(ctx))?;
+    runner.execute(Step::Containers, "Containers", || containers::run_containers(ctx))?;
+    if let Some(compose_files) = config.compose_files() {
+        for compose_file in compose_files {
+            runner.execute(Step::Containers, format!("Compose ({})", compose_file), || {
+                containers::run_compose_stack(ctx, compose_file)
+            })?;
+        }
+    }
+    runner.execute(Step::Deno, "deno", || node::deno_upgrade(ctx))?;
+    runner.execute(Step::Composer, "composer", || generic::run_composer_update(ctx))?;
+    runner.execute(Step::Krew, "krew", || generic::run_krew_upgrade(ctx))?;
+    runner.execute(Step::LanguageServers, "Language servers", || {
+        language_servers::run_language_servers(ctx)
+    })?;
+    runner.execute(Step::Gem, "gem", || generic::run_gem(base_dirs, run_type))?;
+    runner.execute(Step::Haxelib, "haxelib", || generic::run_haxelib_update(ctx))?;
+    runner.execute(Step::Sheldon, "sheldon", || generic::run_sheldon(ctx))?;
+    runner.execute(Step::Rtcl, "rtcl", || generic::run_rtcl(ctx))?;
+    runner.execute(Step::Bin, "bin", || generic::bin_update(ctx))?;
+    runner.execute(Step::Bazel, "Bazel", || generic::run_bazelisk(ctx))?;
+    runner.execute(Step::Terraform, "Terraform", || generic::run_terraform(ctx))?;
+    runner.execute(Step::Aws, "AWS", || aws::run_aws(ctx))?;
+    runner.execute(Step::AzureCli, "Azure CLI", || generic::run_azure_cli(ctx))?;
     runner.execute(Step::Gcloud, "gcloud", || {
         generic::run_gcloud_components_update(run_type)
     })?;
     runner.execute(Step::Micro, "micro", || generic::run_micro(run_type))?;
     runner.execute(Step::Raco, "raco", || generic::run_raco_update(run_type))?;
-    runner.execute(Step::Spicetify, "spicetify", || generic::spicetify_upgrade(&ctx))?;
+    runner.execute(Step::Spicetify, "spicetify", || generic::spicetify_upgrade(ctx))?;
     runner.execute(Step::GithubCliExtensions, "GitHub CLI Extensions", || {
-        generic::run_ghcli_extensions_upgrade(&ctx)
+        generic::run_ghcli_extensions_upgrade(ctx)
     })?;
 
     #[cfg(target_os = "linux")]
     {
-        runner.execute(Step::DebGet, "deb-get", || linux::run_deb_get(&ctx))?;
-        runner.execute(Step::Toolbx, "toolbx", || toolbx::run_toolbx(&ctx))?;
-        runner.execute(Step::Flatpak, "Flatpak", || linux::flatpak_update(&ctx))?;
-        runner.execute(Step::Snap, "snap", || linux::run_snap(sudo.as_ref(), run_type))?;
-        runner.execute(Step::Pacstall, "pacstall", || linux::run_pacstall(&ctx))?;
+        runner.execute(Step::DebGet, "deb-get", || linux::run_deb_get(ctx))?;
+        runner.execute(Step::Toolbx, "toolbx", || toolbx::run_toolbx(ctx))?;
+        runner.execute(Step::Flatpak, "Flatpak", || linux::flatpak_update(ctx))?;
+        runner.execute(Step::Snap, "snap", || linux::run_snap(ctx))?;
+        runner.execute(Step::Pacstall, "pacstall", || linux::run_pacstall(ctx))?;
+    }
+
+    for device in config.network_devices() {
+        runner.execute(
+            Step::NetworkDevices,
+            format!("Network device ({})", device.url()),
+            || network_devices::check_network_device(ctx, device),
+        )?;
     }
 
     if let Some(commands) = config.commands() {
         for (name, command) in commands {
             if config.should_run_custom_command(name) {
                 runner.execute(Step::CustomCommands, name, || {
-                    generic::run_custom_command(name, command, &ctx)
+                    generic::run_custom_command(name, command, ctx)
                 })?;
             }
         }
@@ -378,7 +785,7 @@ fn run() -> Result<()> {
         runner.execute(Step::System, "pihole", || {
             linux::run_pihole_update(sudo.as_ref(), run_type)
         })?;
-        runner.execute(Step::Firmware, "Firmware upgrades", || linux::run_fwupdmgr(&ctx))?;
+        runner.execute(Step::Firmware, "Firmware upgrades", || firmware::run_fwupdmgr(ctx))?;
         runner.execute(Step::Restarts, "Restarts", || {
             linux::run_needrestart(sudo.as_ref(), run_type)
         })?;
@@ -386,9 +793,10 @@ fn run() -> Result<()> {
 
     #[cfg(target_os = "macos")]
     {
-        runner.execute(Step::Sparkle, "Sparkle", || macos::run_sparkle(&ctx))?;
+        runner.execute(Step::Sparkle, "Sparkle", || macos::run_sparkle(ctx))?;
         runner.execute(Step::Mas, "App Store", || macos::run_mas(run_type))?;
-        runner.execute(Step::System, "System upgrade", || macos::upgrade_macos(&ctx))?;
+        runner.execute(Step::System, "System upgrade", || macos::upgrade_macos(ctx))?;
+        runner.execute(Step::Firmware, "Firmware check", || macos::run_eficheck(ctx))?;
     }
 
     #[cfg(target_os = "freebsd")]
@@ -396,82 +804,30 @@ fn run() -> Result<()> {
         freebsd::upgrade_freebsd(sudo.as_ref(), run_type)
     })?;
 
-    #[cfg(windows)]
-    runner.execute(Step::System, "Windows update", || windows::windows_update(&ctx))?;
-
-    if config.should_run(Step::Vagrant) {
-        if let Ok(boxes) = vagrant::collect_boxes(&ctx) {
-            for vagrant_box in boxes {
-                runner.execute(Step::Vagrant, format!("Vagrant ({})", vagrant_box.smart_name()), || {
-                    vagrant::topgrade_vagrant_box(&ctx, &vagrant_box)
-                })?;
-            }
-        }
-    }
-    runner.execute(Step::Vagrant, "Vagrant boxes", || vagrant::upgrade_vagrant_boxes(&ctx))?;
-
-    if !runner.report().data().is_empty() {
-        print_separator("Summary");
-
-        for (key, result) in runner.report().data() {
-            print_result(key, result);
-        }
-
-        #[cfg(target_os = "linux")]
-        {
-            if let Ok(distribution) = &distribution {
-                distribution.show_summary();
-            }
-        }
+    #[cfg(target_os = "freebsd")]
+    runner.execute(Step::Firmware, "Firmware upgrades", || firmware::run_fwupdmgr(ctx))?;
 
-        #[cfg(target_os = "freebsd")]
-        freebsd::audit_packages(&sudo).ok();
+    #[cfg(windows)]
+    runner.execute(Step::System, "Windows update", || windows::windows_update(ctx))?;
 
-        #[cfg(target_os = "dragonfly")]
-        dragonfly::audit_packages(&sudo).ok();
+    for vagrant_box in vagrant_boxes {
+        runner.execute(Step::Vagrant, format!("Vagrant ({})", vagrant_box.smart_name()), || {
+            vagrant::topgrade_vagrant_box(ctx, vagrant_box)
+        })?;
     }
+    runner.execute(Step::Vagrant, "Vagrant boxes", || vagrant::upgrade_vagrant_boxes(ctx))?;
 
-    let mut post_command_failed = false;
-    if let Some(commands) = config.post_commands() {
-        for (name, command) in commands {
-            if generic::run_custom_command(name, command, &ctx).is_err() {
-                post_command_failed = true;
-            }
-        }
+    if config.toolchain_check() {
+        runner.execute(Step::ToolchainCheck, "Toolchain check", || {
+            generic::check_toolchains(ctx)
+        })?;
     }
 
-    if config.keep_at_end() {
-        print_info("\n(R)eboot\n(S)hell\n(Q)uit");
-        loop {
-            match get_key() {
-                Ok(Key::Char('s')) | Ok(Key::Char('S')) => {
-                    run_shell();
-                }
-                Ok(Key::Char('r')) | Ok(Key::Char('R')) => {
-                    reboot();
-                }
-                Ok(Key::Char('q')) | Ok(Key::Char('Q')) => (),
-                _ => {
-                    continue;
-                }
-            }
-            break;
-        }
+    if config.cleanup() {
+        runner.execute(Step::Cleanup, "Cleanup", || cleanup::run_cleanup(ctx))?;
     }
 
-    let failed = post_command_failed || runner.report().data().iter().any(|(_, result)| result.failed());
-    terminal::notify_desktop(
-        format!(
-            "Topgrade finished {}",
-            if failed { "with errors" } else { "successfully" }
-        ),
-        None,
-    );
-    if failed {
-        Err(StepFailed.into())
-    } else {
-        Ok(())
-    }
+    Ok(())
 }
 
 fn main() {
@@ -487,6 +843,10 @@ fn main() {
                 }
             }
 
+            if error.downcast_ref::<ChangesDetected>().is_some() {
+                exit(2);
+            }
+
             let skip_print = (error.downcast_ref::<StepFailed>().is_some())
                 || (error
                     .downcast_ref::<io::Error>()
@@ -494,7 +854,8 @@ fn main() {
                     .is_some());
 
             if !skip_print {
-                println!("Error: {}", error);
+                // The alternate format includes the causes, such as the parse error of an invalid configuration
+                println!("Error: {:#}", error);
             }
             exit(1);
         }
@@ -0,0 +1,108 @@
+//! Sending the results of the run to a webhook or by email, for runs which nobody watches.
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+use sys_info::hostname;
+
+use crate::config::{Config, WebhookFormat};
+use crate::report::Report;
+use crate::secrets;
+use crate::terminal::{format_duration, print_warning};
+use crate::utils;
+
+/// Run a command with the given standard input, failing with its error output
+fn run_with_input(command: &mut Command, input: &str) -> Result<()> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    child.stdin.take().unwrap().write_all(input.as_bytes())?;
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(anyhow!("{}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+
+    Ok(())
+}
+
+/// Post the payload to the webhook. The URL, which may hold a token, is passed to curl through its standard input
+/// along with the payload, so that it doesn't show up in the process list.
+fn post_webhook(url: &str, payload: &Value) -> Result<()> {
+    let curl = utils::require("curl")?;
+
+    let options = [
+        utils::curl_option("url", url),
+        utils::curl_option("header", "Content-Type: application/json"),
+        utils::curl_option("data-binary", &payload.to_string()),
+    ];
+    run_with_input(
+        Command::new(curl).args(&[
+            "--silent",
+            "--show-error",
+            "--fail",
+            "--max-time",
+            "30",
+            "--config",
+            "-",
+        ]),
+        &options.join("\n"),
+    )
+}
+
+fn send_email(address: &str, subject: &str, body: &str) -> Result<()> {
+    let sendmail = utils::which("sendmail")
+        .or_else(|| utils::which("/usr/sbin/sendmail"))
+        .ok_or_else(|| anyhow!("Cannot find sendmail"))?;
+
+    run_with_input(
+        Command::new(sendmail).arg("-t"),
+        &format!("To: {}\nSubject: {}\n\n{}", address, subject, body),
+    )
+}
+
+/// Send the results of the run to the configured webhook and email address
+pub fn send(config: &Config, report: &Report, failed: bool, duration: Duration) {
+    if !config.notifications_on().applies(failed) {
+        return;
+    }
+
+    let host = hostname().unwrap_or_else(|_| String::from("unknown host"));
+    let title = format!(
+        "Topgrade on {} finished {} in {}",
+        host,
+        if failed { "with errors" } else { "successfully" },
+        format_duration(duration)
+    );
+    let message = format!("{}\n\n{}", title, report.to_text());
+
+    if let Some(url) = config.notification_webhook() {
+        let payload = match config.notification_webhook_format() {
+            WebhookFormat::Json => {
+                let mut payload: Value = serde_json::from_str(&report.to_json()).unwrap();
+                payload["host"] = json!(host);
+                payload["failed"] = json!(failed);
+                payload["duration"] = json!(duration.as_secs());
+                payload
+            }
+            // Matrix bridges such as hookshot accept the format of Slack
+            WebhookFormat::Slack => json!({ "text": message }),
+            // Discord rejects longer messages
+            WebhookFormat::Discord => json!({ "content": message.chars().take(2000).collect::<String>() }),
+        };
+
+        if let Err(e) = secrets::expand(url, config).and_then(|url| post_webhook(&url, &payload)) {
+            print_warning(format!("Failed sending the results to the webhook: {}", e));
+        }
+    }
+
+    if let Some(address) = config.notification_email() {
+        if let Err(e) = send_email(address, &title, &message) {
+            print_warning(format!("Failed emailing the results: {}", e));
+        }
+    }
+}
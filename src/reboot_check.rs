@@ -0,0 +1,76 @@
+//! Detection of upgrades which require a reboot to be applied.
+//!
+//! Steps which know that they require a reboot report it through the execution context. The checks here
+//! inspect the flags the operating system leaves behind once the run is over.
+#[cfg(any(target_os = "linux", windows))]
+use std::process::Command;
+
+#[cfg(target_os = "linux")]
+use crate::utils::which;
+
+/// Reasons for rebooting which the operating system reports
+pub fn reboot_reasons() -> Vec<String> {
+    let mut reasons = Vec::new();
+
+    #[cfg(target_os = "linux")]
+    linux_reboot_reasons(&mut reasons);
+
+    #[cfg(windows)]
+    windows_reboot_reasons(&mut reasons);
+
+    reasons
+}
+
+#[cfg(target_os = "linux")]
+fn linux_reboot_reasons(reasons: &mut Vec<String>) {
+    use std::fs;
+    use std::path::Path;
+
+    // Debian and its derivatives
+    if Path::new("/var/run/reboot-required").exists() {
+        let packages = fs::read_to_string("/var/run/reboot-required.pkgs").unwrap_or_default();
+        let packages: Vec<&str> = packages.lines().filter(|l| !l.is_empty()).collect();
+        if packages.is_empty() {
+            reasons.push(String::from("Upgraded packages require a reboot"));
+        } else {
+            reasons.push(format!("Upgraded packages require a reboot: {}", packages.join(", ")));
+        }
+    }
+
+    // Red Hat and its derivatives. The command exits with 1 when a reboot is required.
+    if let Some(needs_restarting) = which("needs-restarting") {
+        if let Ok(status) = Command::new(needs_restarting).arg("-r").output().map(|o| o.status) {
+            if status.code() == Some(1) {
+                reasons.push(String::from(
+                    "needs-restarting reports that core libraries or services were updated",
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+fn windows_reboot_reasons(reasons: &mut Vec<String>) {
+    const KEYS: &[(&str, &str)] = &[
+        (
+            r"HKLM\SOFTWARE\Microsoft\Windows\CurrentVersion\WindowsUpdate\Auto Update\RebootRequired",
+            "Windows Update requires a reboot",
+        ),
+        (
+            r"HKLM\SOFTWARE\Microsoft\Windows\CurrentVersion\Component Based Servicing\RebootPending",
+            "Windows components are pending a reboot",
+        ),
+    ];
+
+    for (key, reason) in KEYS {
+        // The keys exist only while a reboot is pending
+        let exists = Command::new("reg")
+            .args(&["query", key])
+            .output()
+            .map(|o| o.status.success())
+            .unwrap_or(false);
+        if exists {
+            reasons.push(String::from(*reason));
+        }
+    }
+}
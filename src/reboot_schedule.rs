@@ -0,0 +1,173 @@
+//! Reboots required by the upgrades, scheduled inside a maintenance window with the scheduler of the system.
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, NaiveTime, TimeZone, Weekday};
+
+use crate::execution_context::ExecutionContext;
+
+/// A window such as `Sat 03:00-05:00`, `Sat,Sun 01:00-02:00`, or `03:00-05:00` for every day. Windows which end
+/// before they start end on the next day.
+#[derive(Debug)]
+pub struct RebootWindow {
+    /// The days the window opens on. Empty for every day.
+    days: Vec<Weekday>,
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl FromStr for RebootWindow {
+    type Err = anyhow::Error;
+
+    fn from_str(window: &str) -> Result<Self> {
+        let invalid = || {
+            anyhow!(
+                "Invalid reboot window {:?}. Windows look like \"Sat 03:00-05:00\"",
+                window
+            )
+        };
+
+        let (days, times) = match window.trim().rsplit_once(char::is_whitespace) {
+            Some((days, times)) => (days.trim(), times),
+            None => ("", window.trim()),
+        };
+        let days = if days.is_empty() {
+            Vec::new()
+        } else {
+            days.split(',')
+                .map(|day| day.trim().parse::<Weekday>().map_err(|_| invalid()))
+                .collect::<Result<_>>()?
+        };
+
+        let (start, end) = times.split_once('-').ok_or_else(invalid)?;
+        let parse_time = |time: &str| NaiveTime::parse_from_str(time.trim(), "%H:%M").map_err(|_| invalid());
+
+        Ok(Self {
+            days,
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+        })
+    }
+}
+
+impl RebootWindow {
+    /// The start and the end of the window opening on the date, if it does
+    fn opening(&self, date: NaiveDate) -> Option<(DateTime<Local>, DateTime<Local>)> {
+        if !self.days.is_empty() && !self.days.contains(&date.weekday()) {
+            return None;
+        }
+
+        let end_date = if self.end <= self.start { date.succ_opt()? } else { date };
+        let start = Local.from_local_datetime(&date.and_time(self.start)).earliest()?;
+        let end = Local.from_local_datetime(&end_date.and_time(self.end)).earliest()?;
+        Some((start, end))
+    }
+
+    /// When to reboot: the next opening of the window, or `None` if it's open now
+    pub fn next_reboot(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        let today = now.naive_local().date();
+
+        // Windows which opened yesterday may still be open
+        (-1..=7)
+            .filter_map(|offset| self.opening(today + Duration::days(offset)))
+            .find(|(_, end)| *end > now)
+            .map(|(start, _)| start)
+            .filter(|start| *start > now)
+    }
+}
+
+/// Schedule a reboot at the given time
+pub fn schedule(ctx: &ExecutionContext, at: DateTime<Local>) -> Result<()> {
+    if cfg!(target_os = "linux") && Path::new("/run/systemd/system").exists() {
+        // A timer of a previous run would prevent creating the new one
+        if let Ok(mut command) = ctx.execute_elevated(Path::new("systemctl"), false) {
+            command.args(&["stop", "topgrade-reboot.timer"]).output().ok();
+        }
+
+        return ctx
+            .execute_elevated(Path::new("systemd-run"), false)?
+            .args(&["--unit", "topgrade-reboot", "--timer-property", "AccuracySec=1min"])
+            .arg(format!("--on-calendar={}", at.format("%Y-%m-%d %H:%M:%S")))
+            .args(&["systemctl", "reboot"])
+            .check_run();
+    }
+
+    let minutes = (at - Local::now()).num_minutes().max(1);
+    if cfg!(windows) {
+        ctx.run_type()
+            .execute("shutdown")
+            .args(&["/R", "/T", &(minutes * 60).to_string()])
+            .check_run()
+    } else {
+        // shutdown runs in the background until then
+        ctx.execute_elevated(Path::new("shutdown"), false)?
+            .args(&["-r", &format!("+{}", minutes)])
+            .check_run()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2022-06-04 is a Saturday
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local.ymd(2022, 6, day).and_hms(hour, minute, 0)
+    }
+
+    fn window(window: &str) -> RebootWindow {
+        window.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let parsed = window("Sat, Sun 03:00-05:30");
+        assert_eq!(parsed.days, vec![Weekday::Sat, Weekday::Sun]);
+        assert_eq!(parsed.start, NaiveTime::from_hms(3, 0, 0));
+        assert_eq!(parsed.end, NaiveTime::from_hms(5, 30, 0));
+
+        assert!(window("03:00-05:00").days.is_empty());
+    }
+
+    #[test]
+    fn test_invalid() {
+        for invalid in [
+            "",
+            "Sat",
+            "Sat 03:00",
+            "Funday 03:00-05:00",
+            "Sat 25:00-26:00",
+            "Sat 03:00-",
+        ] {
+            assert!(invalid.parse::<RebootWindow>().is_err(), "{:?} was accepted", invalid);
+        }
+    }
+
+    #[test]
+    fn test_open_now() {
+        assert_eq!(window("Sat 03:00-05:00").next_reboot(at(4, 4, 0)), None);
+        assert_eq!(window("03:00-05:00").next_reboot(at(7, 3, 0)), None);
+    }
+
+    #[test]
+    fn test_next_opening() {
+        assert_eq!(window("03:00-05:00").next_reboot(at(4, 10, 0)), Some(at(5, 3, 0)));
+        assert_eq!(window("Sat 03:00-05:00").next_reboot(at(4, 2, 59)), Some(at(4, 3, 0)));
+        assert_eq!(
+            window("Sat,Sun 03:00-05:00").next_reboot(at(4, 5, 0)),
+            Some(at(5, 3, 0))
+        );
+        assert_eq!(window("Sat 03:00-05:00").next_reboot(at(4, 6, 0)), Some(at(11, 3, 0)));
+    }
+
+    #[test]
+    fn test_spanning_midnight() {
+        let friday_night = window("Fri 23:00-01:00");
+        assert_eq!(friday_night.next_reboot(at(3, 23, 30)), None);
+        // The window opened on Friday is still open on Saturday
+        assert_eq!(friday_night.next_reboot(at(4, 0, 30)), None);
+        assert_eq!(friday_night.next_reboot(at(4, 1, 0)), Some(at(10, 23, 0)));
+        assert_eq!(friday_night.next_reboot(at(3, 22, 0)), Some(at(3, 23, 0)));
+    }
+}
@@ -1,7 +1,31 @@
 use std::borrow::Cow;
 
+use serde::{Deserialize, Serialize};
+
+/// The line printed as the summary by `--report-format json`, which is also how remote summaries are read
+#[derive(Serialize, Deserialize)]
+struct JsonReport {
+    topgrade_report: Vec<JsonStep>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonStep {
+    step: String,
+    result: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+/// A package a step upgraded, with the version it was upgraded to
+#[derive(Clone, Debug)]
+pub struct Upgrade {
+    pub name: String,
+    pub version: String,
+}
+
 pub enum StepResult {
     Success,
+    Changed,
     Failure,
     Ignored,
     Skipped(String),
@@ -10,10 +34,72 @@ pub enum StepResult {
 impl StepResult {
     pub fn failed(&self) -> bool {
         match self {
-            StepResult::Success | StepResult::Ignored | StepResult::Skipped(_) => false,
+            StepResult::Success | StepResult::Changed | StepResult::Ignored | StepResult::Skipped(_) => false,
             StepResult::Failure => true,
         }
     }
+
+    /// The result as the summary shows it
+    fn to_text(&self) -> String {
+        match self {
+            StepResult::Success => String::from("OK"),
+            StepResult::Changed => String::from("CHANGED"),
+            StepResult::Failure => String::from("FAILED"),
+            StepResult::Ignored => String::from("IGNORED"),
+            StepResult::Skipped(reason) => format!("SKIPPED: {}", reason),
+        }
+    }
+
+    /// The name of the result in the JSON report and the state file
+    pub fn name(&self) -> &'static str {
+        match self {
+            StepResult::Success => "success",
+            StepResult::Changed => "changed",
+            StepResult::Failure => "failure",
+            StepResult::Ignored => "ignored",
+            StepResult::Skipped(_) => "skipped",
+        }
+    }
+
+    fn to_json(&self, step: &str) -> JsonStep {
+        let reason = match self {
+            StepResult::Skipped(reason) => Some(reason.clone()),
+            _ => None,
+        };
+
+        JsonStep {
+            step: step.to_string(),
+            result: self.name().to_string(),
+            reason,
+        }
+    }
+
+    fn from_json(step: &JsonStep) -> Option<Self> {
+        Some(match step.result.as_str() {
+            "success" => StepResult::Success,
+            "changed" => StepResult::Changed,
+            "failure" => StepResult::Failure,
+            "ignored" => StepResult::Ignored,
+            "skipped" => StepResult::Skipped(step.reason.clone().unwrap_or_default()),
+            _ => return None,
+        })
+    }
+}
+
+/// Find the summary printed by `--report-format json` in the output of a Topgrade run
+pub fn parse_json_report(output: &str) -> Option<Vec<(String, StepResult)>> {
+    output
+        .lines()
+        .rev()
+        .filter_map(|line| serde_json::from_str::<JsonReport>(line.trim()).ok())
+        .map(|report| {
+            report
+                .topgrade_report
+                .iter()
+                .filter_map(|step| StepResult::from_json(step).map(|result| (step.step.clone(), result)))
+                .collect()
+        })
+        .next()
 }
 
 type CowString<'a> = Cow<'a, str>;
@@ -27,6 +113,13 @@ impl<'a> Report<'a> {
         Self { data: Vec::new() }
     }
 
+    /// Tells whether any step reported changes
+    pub fn has_changes(&self) -> bool {
+        self.data
+            .iter()
+            .any(|(_, result)| matches!(result, StepResult::Changed))
+    }
+
     pub fn push_result<M>(&mut self, result: Option<(M, StepResult)>)
     where
         M: Into<CowString<'a>>,
@@ -42,4 +135,21 @@ impl<'a> Report<'a> {
     pub fn data(&self) -> &ReportData<'a> {
         &self.data
     }
+
+    /// The report as the lines of the summary, without styling
+    pub fn to_text(&self) -> String {
+        self.data
+            .iter()
+            .map(|(key, result)| format!("{}: {}\n", key, result.to_text()))
+            .collect()
+    }
+
+    /// The report as a single line of JSON
+    pub fn to_json(&self) -> String {
+        let report = JsonReport {
+            topgrade_report: self.data.iter().map(|(key, result)| result.to_json(key)).collect(),
+        };
+
+        serde_json::to_string(&report).unwrap()
+    }
 }
@@ -1,16 +1,53 @@
+use crate::bandwidth::Limiter;
+use crate::config::{closest_match, Step};
 use crate::ctrlc;
-use crate::error::{DryRun, SkipStep};
+use crate::error::{DryRun, MissingBinary, SkipStep};
 use crate::execution_context::ExecutionContext;
-use crate::report::{Report, StepResult};
-use crate::{config::Step, terminal::should_retry};
-use anyhow::Result;
+use crate::executor;
+use crate::install_hints::install_hint;
+use crate::report::{Report, StepResult, Upgrade};
+use crate::sleep_inhibitor::SleepInhibitor;
+use crate::state_file;
+use crate::step_output;
+use crate::steps::generic;
+#[cfg(target_os = "linux")]
+use crate::steps::os::linux;
+use crate::terminal::{ask_step, print_separator, print_warning, should_retry, StepAnswer};
+#[cfg(all(target_os = "linux", feature = "tray"))]
+use crate::tray;
+use anyhow::{anyhow, Result};
 use log::debug;
 use std::borrow::Cow;
 use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use strum::VariantNames;
+
+/// The delay before the first automatic retry. Every further retry waits one more multiple of it.
+const AUTO_RETRY_BACKOFF: Duration = Duration::from_secs(5);
 
 pub struct Runner<'a> {
     ctx: &'a ExecutionContext<'a>,
     report: Report<'a>,
+    failure_logs: Vec<(String, String)>,
+    /// The last lines of the standard error of the steps which failed
+    failure_excerpts: Vec<(String, String)>,
+    durations: Vec<(String, Duration)>,
+    /// The packages upgraded by the steps which reported them, with the name of the step
+    upgrades: Vec<(String, String, Vec<Upgrade>)>,
+    /// When the time budget of the run is exceeded
+    deadline: Option<Instant>,
+    /// The priority of the steps which run now
+    priority: i32,
+    /// The steps which finished, including the ones of the interrupted run being resumed. `None` unless the
+    /// progress is recorded.
+    finished: Option<Vec<String>>,
+    ask_each: bool,
+    /// The steps whose hooks ran. Steps made of several parts run their hooks around the first part which runs.
+    hooked: Vec<Step>,
+    /// The titles of the steps, collected to check the one selected with `topgrade run`
+    keys: Vec<Cow<'a, str>>,
 }
 
 impl<'a> Runner<'a> {
@@ -18,31 +55,160 @@ impl<'a> Runner<'a> {
         Runner {
             ctx,
             report: Report::new(),
+            failure_logs: Vec::new(),
+            failure_excerpts: Vec::new(),
+            durations: Vec::new(),
+            upgrades: Vec::new(),
+            deadline: ctx.config().max_duration().map(|budget| Instant::now() + budget),
+            priority: 0,
+            finished: None,
+            ask_each: ctx.config().ask_each(),
+            hooked: Vec::new(),
+            keys: Vec::new(),
         }
     }
 
+    /// Run only the steps with the priority from now on
+    pub fn set_priority(&mut self, priority: i32) {
+        self.priority = priority;
+    }
+
+    /// Whether the step should run now, so that its repositories or instances are worth collecting
+    pub fn should_run(&self, step: Step) -> bool {
+        self.ctx.config().should_run(step) && self.ctx.config().step_priority(step) == self.priority
+    }
+
     pub fn execute<F, M>(&mut self, step: Step, key: M, func: F) -> Result<()>
     where
         F: Fn() -> Result<()>,
         M: Into<Cow<'a, str>> + Debug,
     {
-        if !self.ctx.config().should_run(step) {
+        let key = key.into();
+        if self.ctx.config().run_key().is_some() && !self.keys.contains(&key) {
+            self.keys.push(key.clone());
+        }
+
+        if !self.should_run(step) {
+            return Ok(());
+        }
+
+        if !self.ctx.config().should_run_key(&key) {
+            return Ok(());
+        }
+
+        if self.finished.iter().flatten().any(|finished| finished == &key) {
+            self.report.push_result(Some((
+                key,
+                StepResult::Skipped(String::from("Finished before the interruption")),
+            )));
+            return Ok(());
+        }
+
+        if self
+            .deadline
+            .map(|deadline| Instant::now() >= deadline)
+            .unwrap_or(false)
+        {
+            self.report
+                .push_result(Some((key, StepResult::Skipped(String::from("time budget")))));
             return Ok(());
         }
 
-        let key = key.into();
         debug!("Step {:?}", key);
+        let reported = self.report.data().len();
+        let started = Instant::now();
+        let name = key.to_string();
+        #[cfg(all(target_os = "linux", feature = "tray"))]
+        tray::step_started(&key);
+
+        let _inhibitor = if self.ctx.config().inhibit_sleep(step) {
+            SleepInhibitor::acquire(&key)
+        } else {
+            None
+        };
+        executor::set_interactive(step.is_interactive());
+        executor::set_limiter(self.ctx.config().network_limit(step).and_then(|limit| {
+            Limiter::new(self.ctx.base_dirs(), limit)
+                .map_err(|e| print_warning(format!("Failed limiting the bandwidth of {}: {}", key, e)))
+                .ok()
+        }));
+        let hooks = self
+            .ctx
+            .config()
+            .step_hooks(step)
+            .filter(|_| !self.hooked.contains(&step));
+        let pre = hooks
+            .and_then(|hooks| hooks.pre())
+            .map(|pre| (format!("{} (pre)", key), generic::custom_command(pre, self.ctx)));
+        // The user is asked about the step once it's known to apply, right before it runs its first command
+        let answer = Arc::new(Mutex::new(None));
+        let ask_each = self.ask_each;
+        if ask_each || pre.is_some() {
+            let answer = answer.clone();
+            let name = name.clone();
+            executor::prepare_step(Some(Box::new(move || {
+                if ask_each {
+                    let step_answer = ask_step(&name)?;
+                    let skipped = matches!(step_answer, StepAnswer::No);
+                    *answer.lock().unwrap() = Some(step_answer);
+                    if skipped {
+                        return Err(SkipStep(String::from("Skipped by the user")).into());
+                    }
+                }
+                if let Some((name, command)) = pre {
+                    print_separator(name);
+                    command?.check_run()?;
+                }
+                Ok(())
+            })));
+        } else {
+            executor::prepare_step(None);
+        }
+        let mut auto_retries = 0;
 
         loop {
+            self.ctx.take_changes();
+            self.ctx.take_sub_results();
+            self.ctx.take_upgrades();
+            step_output::take();
             match func() {
                 Ok(()) => {
-                    self.report.push_result(Some((key, StepResult::Success)));
+                    let changes = self.ctx.take_changes();
+                    let upgrades = self.ctx.take_upgrades();
+                    if !upgrades.is_empty() {
+                        self.upgrades
+                            .push((key.to_string(), step.as_ref().to_string(), upgrades));
+                    }
+                    // Steps which report whether they changed anything only restart units if they did
+                    #[cfg(target_os = "linux")]
+                    if changes != Some(false) {
+                        self.restart_user_units(step, &key);
+                    }
+
+                    if let Some(sub_results) = self.ctx.take_sub_results() {
+                        self.push_sub_results(&key, sub_results, false);
+                        break;
+                    }
+
+                    let result = if changes == Some(true) {
+                        StepResult::Changed
+                    } else {
+                        StepResult::Success
+                    };
+                    self.report.push_result(Some((key, result)));
                     break;
                 }
                 Err(e) if e.downcast_ref::<DryRun>().is_some() => break,
                 Err(e) if e.downcast_ref::<SkipStep>().is_some() => {
-                    if self.ctx.config().verbose() || self.ctx.config().show_skipped() {
-                        self.report.push_result(Some((key, StepResult::Skipped(e.to_string()))));
+                    let skipped_by_user = matches!(*answer.lock().unwrap(), Some(StepAnswer::No));
+                    if self.ctx.config().verbose() || self.ctx.config().show_skipped() || skipped_by_user {
+                        let mut reason = e.to_string();
+                        if let Some(MissingBinary(binary)) = e.downcast_ref::<MissingBinary>() {
+                            if let Some(hint) = install_hint(binary) {
+                                reason = format!("{} ({})", reason, hint);
+                            }
+                        }
+                        self.report.push_result(Some((key, StepResult::Skipped(reason))));
                     }
                     break;
                 }
@@ -51,6 +217,18 @@ impl<'a> Runner<'a> {
                     let interrupted = ctrlc::interrupted();
                     if interrupted {
                         ctrlc::unset_interrupted();
+                    } else if auto_retries < self.ctx.config().auto_retry() {
+                        auto_retries += 1;
+                        let delay = AUTO_RETRY_BACKOFF * auto_retries;
+                        print_warning(format!(
+                            "{} failed. Retrying in {} seconds ({}/{})",
+                            key,
+                            delay.as_secs(),
+                            auto_retries,
+                            self.ctx.config().auto_retry()
+                        ));
+                        sleep(delay);
+                        continue;
                     }
 
                     let ignore_failure = self.ctx.config().ignore_failure(step);
@@ -58,6 +236,20 @@ impl<'a> Runner<'a> {
                     let should_retry = should_ask && should_retry(interrupted, key.as_ref())?;
 
                     if !should_retry {
+                        if let Some(lines) = self.ctx.config().failure_output_lines() {
+                            let excerpt = step_output::take_errors(lines);
+                            if !excerpt.is_empty() {
+                                self.failure_excerpts.push((key.to_string(), excerpt));
+                            }
+                        }
+                        if step_output::enabled() {
+                            self.failure_logs
+                                .push((key.to_string(), format!("{}\nError: {:?}\n", step_output::take(), e)));
+                        }
+                        if let Some(sub_results) = self.ctx.take_sub_results() {
+                            self.push_sub_results(&key, sub_results, ignore_failure);
+                            break;
+                        }
                         self.report.push_result(Some((
                             key,
                             if ignore_failure {
@@ -72,10 +264,147 @@ impl<'a> Runner<'a> {
             }
         }
 
+        // The hooks run only if the step did, since they usually prepare for it and clean up after it
+        if executor::finish_step() {
+            if let Some(hooks) = hooks {
+                self.hooked.push(step);
+                if let Some(post) = hooks.post() {
+                    let post_key = format!("{} (post)", name);
+                    if let Err(e) = generic::run_custom_command(&post_key, post, self.ctx) {
+                        debug!("The post hook of {:?} failed: {:?}", name, e);
+                        self.report.push_result(Some((post_key, StepResult::Failure)));
+                    }
+                }
+            }
+        }
+
+        executor::set_limiter(None);
+        if matches!(*answer.lock().unwrap(), Some(StepAnswer::All)) {
+            self.ask_each = false;
+        }
+
+        if self.report.data().len() > reported {
+            self.durations.push((name.clone(), started.elapsed()));
+        }
+
+        // Failed steps run again when the run is resumed
+        let failed = self.report.data()[reported..].iter().any(|(_, result)| result.failed());
+        if let Some(finished) = self.finished.as_mut().filter(|_| !failed) {
+            finished.push(name);
+            if let Err(e) = state_file::write_progress(self.ctx.base_dirs(), finished) {
+                debug!("Failed recording the progress: {}", e);
+            }
+        }
+
+        #[cfg(all(target_os = "linux", feature = "tray"))]
+        tray::step_finished(failed);
+
         Ok(())
     }
 
+    /// Make sure that the title selected with `topgrade run` is the one of a step, suggesting the closest title
+    /// otherwise
+    pub fn check_run_key(&self) -> Result<()> {
+        let target = match self.ctx.config().run_key() {
+            Some(target) if !self.keys.iter().any(|key| key == target) => target,
+            _ => return Ok(()),
+        };
+
+        let titles = self.keys.iter().map(|key| key.as_ref());
+        Err(
+            match closest_match(target, Step::VARIANTS.iter().copied().chain(titles)) {
+                Some(candidate) => anyhow!("Unknown step `{}`. Did you mean `{}`?", target, candidate),
+                None => anyhow!("Unknown step `{}`", target),
+            },
+        )
+    }
+
+    /// Record the steps which finish, so that the run can be resumed if it's interrupted. The given steps, which
+    /// finished in the interrupted run, are skipped.
+    pub fn track_progress(&mut self, resumed: Vec<String>) {
+        self.finished = Some(resumed);
+    }
+
+    /// Restart the systemd user units mapped to the step in the configuration
+    #[cfg(target_os = "linux")]
+    fn restart_user_units(&self, step: Step, key: &str) {
+        if let Some(units) = self.ctx.config().restart_user_units(step) {
+            if let Err(e) = linux::restart_user_units(self.ctx, units) {
+                print_warning(format!("Failed restarting the user units of {}: {}", key, e));
+            }
+        }
+    }
+
+    /// Report the results of the parts of a step instead of its own result
+    fn push_sub_results(&mut self, key: &str, sub_results: Vec<(String, StepResult)>, ignore_failure: bool) {
+        for (name, result) in sub_results {
+            let result = match result {
+                StepResult::Failure if ignore_failure => StepResult::Ignored,
+                result => result,
+            };
+            self.report.push_result(Some((format!("{} - {}", key, name), result)));
+        }
+    }
+
     pub fn report(&self) -> &Report {
         &self.report
     }
+
+    /// How long the steps which reported a result took, including their retries and hooks
+    pub fn durations(&self) -> &[(String, Duration)] {
+        &self.durations
+    }
+
+    /// Record the upgrade of Topgrade by itself, which happened before it restarted
+    #[cfg(feature = "self-update")]
+    pub fn record_self_update(&mut self, version: String) {
+        self.report
+            .push_result(Some((String::from("Self update"), StepResult::Changed)));
+        self.upgrades.push((
+            String::from("Self update"),
+            String::from("self_update"),
+            vec![Upgrade {
+                name: String::from("topgrade"),
+                version,
+            }],
+        ));
+    }
+
+    /// Links to the changelogs of the packages upgraded by the step, as the text and the URL of each link. Only
+    /// steps with a changelog URL in the configuration have links.
+    pub fn changelog_links(&self, key: &str) -> Vec<(String, String)> {
+        self.upgrades
+            .iter()
+            .filter(|(step_key, _, _)| step_key == key)
+            .filter_map(|(_, step, upgrades)| {
+                self.ctx
+                    .config()
+                    .changelog_url(step)
+                    .map(|template| (template, upgrades))
+            })
+            .flat_map(|(template, upgrades)| {
+                upgrades.iter().map(move |upgrade| {
+                    (
+                        format!("{} {}", upgrade.name, upgrade.version),
+                        template
+                            .replace("{name}", &upgrade.name)
+                            .replace("{version}", &upgrade.version),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// The last lines of the standard error of the failed step, if they were captured
+    pub fn failure_excerpt(&self, key: &str) -> Option<&str> {
+        self.failure_excerpts
+            .iter()
+            .find(|(step, _)| step == key)
+            .map(|(_, excerpt)| excerpt.as_str())
+    }
+
+    /// The captured output of the steps which failed
+    pub fn failure_logs(&self) -> &[(String, String)] {
+        &self.failure_logs
+    }
 }
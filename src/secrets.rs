@@ -0,0 +1,132 @@
+//! Secrets referenced as `secret("name")` in the configuration, so that tokens and passwords don't have to be
+//! written in it. They're looked up when they're used, in a sops-encrypted file or in the keychain of the system.
+use std::collections::HashMap;
+use std::process::Command;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+
+use crate::config::Config;
+use crate::executor::CommandExt;
+use crate::utils;
+
+/// The service under which secrets are stored in the keychain
+const SERVICE: &str = "topgrade";
+
+lazy_static! {
+    static ref PLACEHOLDER: Regex = Regex::new(r#"secret\("([^"]+)"\)"#).unwrap();
+    static ref CACHE: Mutex<HashMap<String, String>> = Mutex::new(HashMap::new());
+}
+
+#[cfg(target_os = "macos")]
+fn keychain_lookup(name: &str) -> Result<String> {
+    Command::new("security")
+        .args(&["find-generic-password", "-s", SERVICE, "-a", name, "-w"])
+        .check_output()
+}
+
+#[cfg(windows)]
+fn keychain_lookup(name: &str) -> Result<String> {
+    let script = format!(
+        "[void][Windows.Security.Credentials.PasswordVault, Windows.Security.Credentials, ContentType=WindowsRuntime]; \
+         $credential = (New-Object Windows.Security.Credentials.PasswordVault).Retrieve('{}', '{}'); \
+         $credential.RetrievePassword(); $credential.Password",
+        SERVICE,
+        name.replace('\'', "''")
+    );
+    Command::new("powershell")
+        .args(&["-NoProfile", "-Command", &script])
+        .check_output()
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+fn keychain_lookup(name: &str) -> Result<String> {
+    let secret_tool = utils::require("secret-tool")?;
+    Command::new(secret_tool)
+        .args(&["lookup", "service", SERVICE, "name", name])
+        .check_output()
+}
+
+fn lookup(name: &str, config: &Config) -> Result<String> {
+    let value = match config.secrets_file() {
+        // sops decrypts files encrypted with age as well as with GPG or a cloud KMS
+        Some(file) => {
+            let sops = utils::require("sops")?;
+            Command::new(sops)
+                .args(&["--decrypt", "--extract", &format!("[\"{}\"]", name)])
+                .arg(file)
+                .check_output()
+        }
+        None => keychain_lookup(name),
+    }
+    .with_context(|| format!("Failed looking up the secret {}", name))?;
+
+    Ok(value.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+/// The value of a secret. Each secret is looked up once per run.
+fn resolve(name: &str, config: &Config) -> Result<String> {
+    if let Some(value) = CACHE.lock().unwrap().get(name) {
+        return Ok(value.clone());
+    }
+
+    let value = lookup(name, config)?;
+    CACHE.lock().unwrap().insert(name.to_string(), value.clone());
+    Ok(value)
+}
+
+/// Replace every placeholder in the text by the value of its secret
+pub fn expand(text: &str, config: &Config) -> Result<String> {
+    let mut error = None;
+    let expanded = PLACEHOLDER.replace_all(text, |captures: &Captures| match resolve(&captures[1], config) {
+        Ok(value) => value,
+        Err(e) => {
+            error.get_or_insert(e);
+            String::new()
+        }
+    });
+
+    match error {
+        Some(e) => Err(e),
+        None => Ok(expanded.into_owned()),
+    }
+}
+
+/// The environment variable holding a secret in custom commands
+fn variable_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("TOPGRADE_SECRET_{}", name)
+}
+
+/// Replace every placeholder in a shell command by a reference to an environment variable holding the secret, so
+/// that secrets don't show in the list of processes. Returns the command and the variables to set.
+pub fn expand_to_environment(command: &str, config: &Config) -> Result<(String, Vec<(String, String)>)> {
+    let mut variables: Vec<(String, String)> = Vec::new();
+    for captures in PLACEHOLDER.captures_iter(command) {
+        let variable = variable_name(&captures[1]);
+        if !variables.iter().any(|(name, _)| name == &variable) {
+            variables.push((variable, resolve(&captures[1], config)?));
+        }
+    }
+
+    let command = PLACEHOLDER.replace_all(command, |captures: &Captures| {
+        if cfg!(windows) {
+            format!("$env:{}", variable_name(&captures[1]))
+        } else {
+            format!("${{{}}}", variable_name(&captures[1]))
+        }
+    });
+
+    Ok((command.into_owned(), variables))
+}
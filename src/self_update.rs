@@ -40,6 +40,9 @@ pub fn self_update() -> Result<()> {
             print_warning("Respawning...");
             let mut command = Command::new(current_exe?);
             command.args(env::args().skip(1)).env("TOPGRADE_NO_SELF_UPGRADE", "");
+            if let UpdateStatus::Updated(release) = &result {
+                command.env("TOPGRADE_SELF_UPGRADED", &release.version);
+            }
 
             #[cfg(unix)]
             {
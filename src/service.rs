@@ -0,0 +1,229 @@
+//! Installation of a periodic job running Topgrade in the background.
+//!
+//! Unix systems running systemd get a user service and timer, macOS a launchd agent and Windows a scheduled task.
+//! Other systems, like the BSDs, aren't supported because they lack `systemctl`.
+use std::env;
+#[cfg(not(windows))]
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+#[cfg(any(windows, target_os = "macos"))]
+use anyhow::bail;
+#[cfg(not(windows))]
+use anyhow::Context;
+use anyhow::Result;
+use directories::BaseDirs;
+
+use crate::executor::CommandExt;
+use crate::terminal::print_separator;
+
+/// Arguments which only make sense when installing the service and are not forwarded to it
+const INSTALL_ARGUMENTS: &[&str] = &["--install-service"];
+const INSTALL_ARGUMENTS_WITH_VALUE: &[&str] = &["--schedule"];
+
+/// The arguments the installed service should run Topgrade with
+fn service_arguments() -> Vec<String> {
+    let mut result = Vec::new();
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        if INSTALL_ARGUMENTS.contains(&arg.as_str()) {
+            continue;
+        }
+
+        if INSTALL_ARGUMENTS_WITH_VALUE.contains(&arg.as_str()) {
+            args.next();
+            continue;
+        }
+
+        if INSTALL_ARGUMENTS_WITH_VALUE
+            .iter()
+            .any(|a| arg.starts_with(a) && arg[a.len()..].starts_with('='))
+        {
+            continue;
+        }
+
+        result.push(arg);
+    }
+
+    result
+}
+
+#[cfg(not(windows))]
+fn quote(argument: &str) -> String {
+    if argument.is_empty() || argument.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\') {
+        format!("\"{}\"", argument.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        argument.to_string()
+    }
+}
+
+/// Quotes the argument the way `CommandLineToArgvW` splits it back
+#[cfg(windows)]
+fn quote(argument: &str) -> String {
+    if !argument.is_empty() && !argument.contains(|c: char| c.is_whitespace() || c == '"') {
+        return argument.to_string();
+    }
+
+    let mut result = String::from("\"");
+    let mut backslashes = 0;
+    for c in argument.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                result.extend(std::iter::repeat('\\').take(backslashes * 2 + 1));
+                backslashes = 0;
+            }
+            _ => {
+                result.extend(std::iter::repeat('\\').take(backslashes));
+                backslashes = 0;
+            }
+        }
+        if c != '\\' {
+            result.push(c);
+        }
+    }
+    result.extend(std::iter::repeat('\\').take(backslashes * 2));
+    result.push('"');
+    result
+}
+
+#[cfg(not(windows))]
+fn write_file(path: &Path, contents: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, contents).with_context(|| format!("Failed writing {}", path.display()))?;
+    println!("Wrote {}", path.display());
+    Ok(())
+}
+
+/// Install the service running Topgrade according to the given schedule
+pub fn install(base_dirs: &BaseDirs, schedule: &str) -> Result<()> {
+    print_separator("Installing service");
+
+    let topgrade = env::current_exe()?;
+    let arguments = service_arguments();
+
+    install_for_platform(base_dirs, &topgrade, &arguments, schedule)
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn install_for_platform(base_dirs: &BaseDirs, topgrade: &Path, arguments: &[String], schedule: &str) -> Result<()> {
+    let systemctl = crate::utils::require("systemctl")?;
+    let unit_dir = base_dirs.config_dir().join("systemd/user");
+
+    let mut exec_start = quote(&topgrade.to_string_lossy());
+    for argument in arguments {
+        exec_start.push(' ');
+        exec_start.push_str(&quote(argument));
+    }
+
+    write_file(
+        &unit_dir.join("topgrade.service"),
+        &format!(
+            "[Unit]\nDescription=Upgrade everything with Topgrade\n\n\
+             [Service]\nType=oneshot\nExecStart={}\n",
+            exec_start
+        ),
+    )?;
+
+    write_file(
+        &unit_dir.join("topgrade.timer"),
+        &format!(
+            "[Unit]\nDescription=Run Topgrade periodically\n\n\
+             [Timer]\nOnCalendar={}\nPersistent=true\n\n\
+             [Install]\nWantedBy=timers.target\n",
+            schedule
+        ),
+    )?;
+
+    Command::new(&systemctl)
+        .args(&["--user", "daemon-reload"])
+        .check_output()?;
+    Command::new(&systemctl)
+        .args(&["--user", "enable", "--now", "topgrade.timer"])
+        .check_output()?;
+
+    Ok(())
+}
+
+/// Translates the schedule to the interval of the job in seconds
+#[cfg(target_os = "macos")]
+fn schedule_interval(schedule: &str) -> Result<u32> {
+    match schedule {
+        "hourly" => Ok(60 * 60),
+        "daily" => Ok(24 * 60 * 60),
+        "weekly" => Ok(7 * 24 * 60 * 60),
+        _ => bail!("Unsupported schedule {}. Use hourly, daily or weekly", schedule),
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn install_for_platform(base_dirs: &BaseDirs, topgrade: &Path, arguments: &[String], schedule: &str) -> Result<()> {
+    let launchctl = crate::utils::require("launchctl")?;
+    let interval = schedule_interval(schedule)?;
+    let plist = base_dirs
+        .home_dir()
+        .join("Library/LaunchAgents/com.github.r-darwish.topgrade.plist");
+
+    let escape = |s: &str| s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+    let mut program_arguments = format!("<string>{}</string>\n", escape(&topgrade.to_string_lossy()));
+    for argument in arguments {
+        program_arguments.push_str(&format!("<string>{}</string>\n", escape(argument)));
+    }
+
+    write_file(
+        &plist,
+        &format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+             <plist version=\"1.0\">\n\
+             <dict>\n\
+             <key>Label</key>\n\
+             <string>com.github.r-darwish.topgrade</string>\n\
+             <key>ProgramArguments</key>\n\
+             <array>\n\
+             {}\
+             </array>\n\
+             <key>StartInterval</key>\n\
+             <integer>{}</integer>\n\
+             </dict>\n\
+             </plist>\n",
+            program_arguments, interval
+        ),
+    )?;
+
+    // Reloading an already installed agent fails, so the result of unloading it is ignored
+    Command::new(&launchctl).arg("unload").arg(&plist).output().ok();
+    Command::new(&launchctl)
+        .args(&["load", "-w"])
+        .arg(&plist)
+        .check_output()?;
+
+    Ok(())
+}
+
+#[cfg(windows)]
+fn install_for_platform(_base_dirs: &BaseDirs, topgrade: &Path, arguments: &[String], schedule: &str) -> Result<()> {
+    let frequency = match schedule {
+        "hourly" => "HOURLY",
+        "daily" => "DAILY",
+        "weekly" => "WEEKLY",
+        _ => bail!("Unsupported schedule {}. Use hourly, daily or weekly", schedule),
+    };
+
+    let mut task = quote(&topgrade.to_string_lossy());
+    for argument in arguments {
+        task.push(' ');
+        task.push_str(&quote(argument));
+    }
+
+    Command::new("schtasks")
+        .args(&["/Create", "/F", "/TN", "Topgrade", "/SC", frequency, "/TR"])
+        .arg(task)
+        .check_output()?;
+
+    Ok(())
+}
@@ -0,0 +1,68 @@
+//! Locks keeping the machine from sleeping or shutting down in the middle of a step, with systemd-inhibit on Linux
+//! and caffeinate on macOS.
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::process::{self, Child, Command, Stdio};
+
+use log::debug;
+#[cfg(unix)]
+use nix::sys::signal::{killpg, Signal};
+#[cfg(unix)]
+use nix::unistd::{setpgid, Pid};
+
+use crate::utils::which;
+
+/// Holds the lock until it's dropped
+pub struct SleepInhibitor(Child);
+
+impl SleepInhibitor {
+    /// Take the lock for the step. Returns `None` if the system has no way to hold it.
+    pub fn acquire(step: &str) -> Option<Self> {
+        // The locks follow the Topgrade process, so that they aren't left behind if it's killed
+        let pid = process::id().to_string();
+        let mut command = if cfg!(target_os = "linux") {
+            let mut command = Command::new(which("systemd-inhibit")?);
+            command
+                .args(&["--what=shutdown:sleep", "--who=Topgrade", "--mode=block"])
+                .arg(format!("--why=Running {}", step))
+                .args(&["tail", "-f", "/dev/null", "--pid", &pid]);
+            command
+        } else if cfg!(target_os = "macos") {
+            let mut command = Command::new(which("caffeinate")?);
+            command.args(&["-i", "-s", "-w", &pid]);
+            command
+        } else {
+            return None;
+        };
+
+        // systemd-inhibit runs the command in a child process, so they're put in their own process group which is
+        // stopped as a whole
+        #[cfg(unix)]
+        unsafe {
+            command.pre_exec(|| setpgid(Pid::from_raw(0), Pid::from_raw(0)).map_err(Into::into));
+        }
+
+        match command
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(child) => Some(Self(child)),
+            Err(e) => {
+                debug!("Failed to keep the system awake: {}", e);
+                None
+            }
+        }
+    }
+}
+
+impl Drop for SleepInhibitor {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        killpg(Pid::from_raw(self.0.id() as i32), Signal::SIGTERM).ok();
+        #[cfg(not(unix))]
+        self.0.kill().ok();
+        self.0.wait().ok();
+    }
+}
@@ -0,0 +1,238 @@
+//! A small file recording the last run, so that shell prompts can remind of running Topgrade.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use directories::BaseDirs;
+use serde::{Deserialize, Serialize};
+
+use crate::report::{Report, StepResult};
+use crate::terminal::print_warning;
+
+#[derive(Serialize, Deserialize)]
+struct StepState {
+    step: String,
+    /// As in the JSON report: success, changed, failure, ignored or skipped
+    result: String,
+    /// In seconds. Results of the parts of a step, such as remote ones, don't have their own duration.
+    duration: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct State {
+    /// Unix timestamp of the last run
+    last_run: u64,
+    /// Unix timestamp of the last run in which no step failed
+    last_success: Option<u64>,
+    /// Whether a step of the last run failed
+    #[serde(default)]
+    failed: bool,
+    /// The duration of the last run in seconds
+    #[serde(default)]
+    duration: u64,
+    /// The steps which reported changes in the last run
+    changed: Vec<String>,
+    /// The results of the last run
+    #[serde(default)]
+    steps: Vec<StepState>,
+}
+
+/// The steps which finished in a run which hasn't completed, so that it can be resumed after an interruption
+#[derive(Serialize, Deserialize, Default)]
+struct Progress {
+    finished: Vec<String>,
+}
+
+fn path(base_dirs: &BaseDirs) -> PathBuf {
+    base_dirs.data_local_dir().join("topgrade").join("state.json")
+}
+
+fn progress_path(base_dirs: &BaseDirs) -> PathBuf {
+    base_dirs.data_local_dir().join("topgrade").join("progress.json")
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+fn read(base_dirs: &BaseDirs) -> Option<State> {
+    let contents = fs::read_to_string(path(base_dirs)).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+/// Record the run. The time of the last successful run is kept when the run failed.
+pub fn write(
+    base_dirs: &BaseDirs,
+    report: &Report,
+    step_durations: &[(String, Duration)],
+    failed: bool,
+    duration: Duration,
+) -> Result<()> {
+    let now = now();
+    let last_success = if failed {
+        read(base_dirs).and_then(|state| state.last_success)
+    } else {
+        Some(now)
+    };
+
+    let state = State {
+        last_run: now,
+        last_success,
+        failed,
+        duration: duration.as_secs(),
+        changed: report
+            .data()
+            .iter()
+            .filter(|(_, result)| matches!(result, StepResult::Changed))
+            .map(|(key, _)| key.to_string())
+            .collect(),
+        steps: report
+            .data()
+            .iter()
+            .map(|(key, result)| StepState {
+                step: key.to_string(),
+                result: result.name().to_string(),
+                duration: step_durations
+                    .iter()
+                    .find(|(step, _)| step == key)
+                    .map(|(_, duration)| duration.as_secs_f64()),
+            })
+            .collect(),
+    };
+
+    let path = path(base_dirs);
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(&path, serde_json::to_string(&state)?)?;
+    Ok(())
+}
+
+/// Escape a label value of the Prometheus text format
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Write the recorded run as metrics in the text format of Prometheus, for the textfile collector of
+/// node_exporter. The file is replaced at once so that the collector never reads it half written.
+pub fn write_metrics(base_dirs: &BaseDirs, metrics_file: &Path) -> Result<()> {
+    let state = read(base_dirs).ok_or_else(|| anyhow!("The state file is missing"))?;
+
+    let mut metrics = String::new();
+    let mut push = |name: &str, help: &str, samples: Vec<(String, String)>| {
+        metrics.push_str(&format!("# HELP {} {}\n# TYPE {} gauge\n", name, help, name));
+        for (labels, value) in samples {
+            metrics.push_str(&format!("{}{} {}\n", name, labels, value));
+        }
+    };
+
+    push(
+        "topgrade_last_run_timestamp_seconds",
+        "Time of the last run.",
+        vec![(String::new(), state.last_run.to_string())],
+    );
+    if let Some(last_success) = state.last_success {
+        push(
+            "topgrade_last_success_timestamp_seconds",
+            "Time of the last run in which no step failed.",
+            vec![(String::new(), last_success.to_string())],
+        );
+    }
+    push(
+        "topgrade_last_run_failed",
+        "Whether a step of the last run failed.",
+        vec![(String::new(), (state.failed as u8).to_string())],
+    );
+    push(
+        "topgrade_last_run_duration_seconds",
+        "Duration of the last run.",
+        vec![(String::new(), state.duration.to_string())],
+    );
+    push(
+        "topgrade_step_result",
+        "Result of the step in the last run.",
+        state
+            .steps
+            .iter()
+            .map(|step| {
+                (
+                    format!("{{step=\"{}\",result=\"{}\"}}", escape_label(&step.step), step.result),
+                    String::from("1"),
+                )
+            })
+            .collect(),
+    );
+    push(
+        "topgrade_step_duration_seconds",
+        "Duration of the step in the last run.",
+        state
+            .steps
+            .iter()
+            .filter_map(|step| {
+                step.duration.map(|duration| {
+                    (
+                        format!("{{step=\"{}\"}}", escape_label(&step.step)),
+                        format!("{:.3}", duration),
+                    )
+                })
+            })
+            .collect(),
+    );
+
+    let temporary = metrics_file.with_extension("prom.tmp");
+    fs::write(&temporary, metrics)?;
+    fs::rename(&temporary, metrics_file)?;
+    Ok(())
+}
+
+/// A short status for shell prompts, telling the days since the last successful run
+pub fn prompt_status(base_dirs: &BaseDirs) -> String {
+    match read(base_dirs).and_then(|state| state.last_success) {
+        Some(last_success) => format!("⟳ {}d", now().saturating_sub(last_success) / (24 * 60 * 60)),
+        None => String::from("⟳ never"),
+    }
+}
+
+/// Whether the last successful run is older than `max_age`. Without any recorded run, a run is due. That's the case
+/// as well when runs aren't recorded, which is warned about.
+pub fn is_due(base_dirs: &BaseDirs, max_age: Duration) -> bool {
+    let state = match read(base_dirs) {
+        Some(state) => state,
+        None => {
+            print_warning("No run was recorded. Runs are only recorded with `state_file = true` in the configuration");
+            return true;
+        }
+    };
+
+    match state.last_success {
+        Some(last_success) => now().saturating_sub(last_success) > max_age.as_secs(),
+        None => true,
+    }
+}
+
+/// The steps which finished in the interrupted run, if the last run didn't complete
+pub fn interrupted_run(base_dirs: &BaseDirs) -> Option<Vec<String>> {
+    let contents = fs::read_to_string(progress_path(base_dirs)).ok()?;
+    serde_json::from_str::<Progress>(&contents)
+        .ok()
+        .map(|progress| progress.finished)
+}
+
+/// Record the steps which finished so far in the run
+pub fn write_progress(base_dirs: &BaseDirs, finished: &[String]) -> Result<()> {
+    let progress = Progress {
+        finished: finished.to_vec(),
+    };
+
+    let path = progress_path(base_dirs);
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(&path, serde_json::to_string(&progress)?)?;
+    Ok(())
+}
+
+/// Forget the progress once the run completed
+pub fn clear_progress(base_dirs: &BaseDirs) {
+    fs::remove_file(progress_path(base_dirs)).ok();
+}
@@ -0,0 +1,82 @@
+//! Capture of the output of the running step, so that the output of failed steps can be reviewed at the end.
+use std::env;
+use std::io::Write;
+use std::process::Command;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+use log::debug;
+
+use crate::utils::{editor, which};
+
+lazy_static! {
+    static ref OUTPUT: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+    /// The standard error alone, for the excerpts of failed steps in the summary
+    static ref ERRORS: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+}
+
+/// Start capturing the output of commands. Commands are piped through Topgrade from now on.
+pub fn enable() {
+    *OUTPUT.lock().unwrap() = Some(Vec::new());
+}
+
+/// Whether the output of commands is captured
+pub fn enabled() -> bool {
+    OUTPUT.lock().unwrap().is_some()
+}
+
+/// Append output of a command to the output of the current step
+pub fn record(data: &[u8], is_error: bool) {
+    if let Some(output) = OUTPUT.lock().unwrap().as_mut() {
+        output.extend_from_slice(data);
+        if is_error {
+            ERRORS.lock().unwrap().extend_from_slice(data);
+        }
+    }
+}
+
+/// Return the output captured since the last call, clearing it along with the standard error
+pub fn take() -> String {
+    ERRORS.lock().unwrap().clear();
+    OUTPUT
+        .lock()
+        .unwrap()
+        .as_mut()
+        .map(|output| String::from_utf8_lossy(&std::mem::take(output)).into_owned())
+        .unwrap_or_default()
+}
+
+/// Return the last lines of the standard error captured since the last call, clearing them
+pub fn take_errors(lines: usize) -> String {
+    let errors = std::mem::take(&mut *ERRORS.lock().unwrap());
+    let errors = String::from_utf8_lossy(&errors);
+    let errors: Vec<&str> = errors.lines().filter(|line| !line.trim().is_empty()).collect();
+
+    errors[errors.len().saturating_sub(lines)..].join("\n")
+}
+
+/// Write the given logs to a temporary file and open it with the pager, or with the editor if there's no pager
+pub fn open_log(logs: &[(String, String)]) -> Result<()> {
+    let mut file = tempfile::Builder::new().prefix("topgrade-").suffix(".log").tempfile()?;
+
+    for (key, output) in logs {
+        writeln!(file, "==> {}\n{}", key, output)?;
+    }
+    file.flush()?;
+
+    let viewer: Vec<String> = match env::var("PAGER").ok().filter(|pager| !pager.trim().is_empty()) {
+        Some(pager) => pager.split_whitespace().map(|s| s.to_owned()).collect(),
+        None if which("less").is_some() => vec![String::from("less")],
+        None => editor(),
+    };
+    debug!("Opening {} with {:?}", file.path().display(), viewer);
+
+    Command::new(&viewer[0])
+        .args(&viewer[1..])
+        .arg(file.path())
+        .spawn()
+        .and_then(|mut p| p.wait())?;
+
+    Ok(())
+}
@@ -0,0 +1,239 @@
+//! Updates of the AWS command line tools according to the way each of them was installed.
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::error::SkipStep;
+use crate::execution_context::ExecutionContext;
+use crate::terminal::print_separator;
+use crate::utils::{self, which};
+
+struct Tool {
+    name: &'static str,
+    /// The name of the directory the official installer installs to
+    install_dir_name: &'static str,
+    /// The install script inside the archive of the Linux installer
+    install_script: &'static str,
+    /// The URLs of the official installers. `{arch}` is replaced by the architecture.
+    linux_installer: &'static str,
+    macos_installer: &'static str,
+    /// How the installers call 64-bit ARM
+    aarch64_name: &'static str,
+    pip_package: &'static str,
+}
+
+const AWS_CLI: Tool = Tool {
+    name: "AWS CLI",
+    install_dir_name: "aws-cli",
+    install_script: "aws/install",
+    linux_installer: "https://awscli.amazonaws.com/awscli-exe-linux-{arch}.zip",
+    macos_installer: "https://awscli.amazonaws.com/AWSCLIV2.pkg",
+    aarch64_name: "aarch64",
+    pip_package: "awscli",
+};
+
+const SAM_CLI: Tool = Tool {
+    name: "SAM CLI",
+    install_dir_name: "aws-sam-cli",
+    install_script: "install",
+    linux_installer: "https://github.com/aws/aws-sam-cli/releases/latest/download/aws-sam-cli-linux-{arch}.zip",
+    macos_installer: "https://github.com/aws/aws-sam-cli/releases/latest/download/aws-sam-cli-macos-{arch}.pkg",
+    aarch64_name: "arm64",
+    pip_package: "aws-sam-cli",
+};
+
+impl Tool {
+    /// The URL of the official installer for the current platform
+    fn installer_url(&self) -> Option<String> {
+        let url = if cfg!(target_os = "linux") {
+            self.linux_installer
+        } else if cfg!(target_os = "macos") {
+            self.macos_installer
+        } else {
+            return None;
+        };
+
+        let arch = match std::env::consts::ARCH {
+            "aarch64" => self.aarch64_name,
+            arch => arch,
+        };
+
+        Some(url.replace("{arch}", arch))
+    }
+}
+
+/// How a tool was installed, as far as it can be told from its executable
+enum Installation {
+    Homebrew,
+    Pipx,
+    /// Installed with pip by the given Python interpreter
+    Pip(PathBuf),
+    /// Installed by the official installer into `install_dir`, with the executable linked from `bin_dir`
+    Installer {
+        install_dir: PathBuf,
+        bin_dir: PathBuf,
+    },
+}
+
+fn detect_installation(tool: &Tool, binary: &Path) -> Result<Installation> {
+    let canonical = binary.canonicalize().unwrap_or_else(|_| binary.to_owned());
+    let canonical_str = canonical.to_string_lossy();
+
+    if canonical_str.contains("Cellar") || canonical_str.contains("linuxbrew") {
+        return Ok(Installation::Homebrew);
+    }
+
+    if canonical_str.contains("pipx") {
+        return Ok(Installation::Pipx);
+    }
+
+    if let Some(install_dir) = canonical
+        .ancestors()
+        .find(|dir| dir.file_name() == Some(OsStr::new(tool.install_dir_name)))
+    {
+        return Ok(Installation::Installer {
+            install_dir: install_dir.to_owned(),
+            bin_dir: binary.parent().unwrap().to_owned(),
+        });
+    }
+
+    // Scripts installed by pip start with a shebang pointing at the interpreter which installed them
+    let contents = fs::read(&canonical)?;
+    let first_line = String::from_utf8_lossy(contents.split(|b| *b == b'\n').next().unwrap_or_default()).into_owned();
+    if let Some(python) = first_line.strip_prefix("#!").and_then(shebang_interpreter) {
+        return Ok(Installation::Pip(python));
+    }
+
+    Err(SkipStep(format!("Cannot tell how {} was installed", binary.display())).into())
+}
+
+/// The Python interpreter of a shebang such as `/usr/bin/python3 -s` or `/usr/bin/env python3`, without its
+/// arguments. Interpreters run through env are looked up in PATH.
+fn shebang_interpreter(shebang: &str) -> Option<PathBuf> {
+    let mut words = shebang.split_whitespace();
+    let program = words.next()?;
+    let interpreter = if Path::new(program).file_name() == Some(OsStr::new("env")) {
+        // Skip the options of env, such as -S
+        let name = words.find(|word| !word.starts_with('-'))?;
+        which(name)?
+    } else {
+        PathBuf::from(program)
+    };
+
+    if interpreter.file_name()?.to_string_lossy().contains("python") {
+        Some(interpreter)
+    } else {
+        None
+    }
+}
+
+fn run_installer(ctx: &ExecutionContext, tool: &Tool, install_dir: &Path, bin_dir: &Path) -> Result<()> {
+    let url = tool.installer_url().ok_or_else(|| {
+        SkipStep(format!(
+            "{} was installed by an installer Topgrade can't run",
+            tool.name
+        ))
+    })?;
+    let curl = utils::require("curl")?;
+    let tempdir = tempfile::tempdir()?;
+    let download = tempdir.path().join(url.rsplit('/').next().unwrap());
+
+    ctx.run_type()
+        .execute(&curl)
+        .args(&["-fsSL", "-o"])
+        .arg(&download)
+        .arg(&url)
+        .check_run()?;
+
+    if cfg!(target_os = "macos") {
+        return ctx
+            .execute_elevated(Path::new("installer"), false)?
+            .arg("-pkg")
+            .arg(&download)
+            .args(&["-target", "/"])
+            .check_run();
+    }
+
+    let unzip = utils::require("unzip")?;
+    let extracted = tempdir.path().join("extracted");
+    ctx.run_type()
+        .execute(&unzip)
+        .arg("-q")
+        .arg(&download)
+        .arg("-d")
+        .arg(&extracted)
+        .check_run()?;
+
+    let install_script = extracted.join(tool.install_script);
+    let mut command = if install_dir.starts_with(ctx.base_dirs().home_dir()) {
+        ctx.run_type().execute(&install_script)
+    } else {
+        ctx.execute_elevated(&install_script, false)?
+    };
+
+    command
+        .arg("--install-dir")
+        .arg(install_dir)
+        .arg("--bin-dir")
+        .arg(bin_dir)
+        .arg("--update")
+        .check_run()
+}
+
+fn update_tool(ctx: &ExecutionContext, tool: &Tool, binary: &Path) -> Result<()> {
+    match detect_installation(tool, binary)? {
+        Installation::Homebrew => Err(SkipStep(format!("{} is updated by Homebrew", tool.name)).into()),
+        Installation::Pipx => Err(SkipStep(format!("{} is updated by pipx", tool.name)).into()),
+        Installation::Pip(python) => {
+            let home = ctx.base_dirs().home_dir();
+            if !binary.starts_with(home) {
+                return Err(SkipStep(format!(
+                    "{} was installed system wide with pip. Update it with the package manager which owns it",
+                    tool.name
+                ))
+                .into());
+            }
+
+            let mut command = ctx.run_type().execute(&python);
+            command.args(&["-m", "pip", "install", "--upgrade"]);
+            // Interpreters outside of the home directory are system ones, whose packages are installed per user
+            if !python.starts_with(home) {
+                command.arg("--user");
+            }
+            command.arg(tool.pip_package).check_run()
+        }
+        Installation::Installer { install_dir, bin_dir } => run_installer(ctx, tool, &install_dir, &bin_dir),
+    }
+}
+
+pub fn run_aws(ctx: &ExecutionContext) -> Result<()> {
+    let aws = which("aws");
+    let sam = which("sam");
+    if aws.is_none() && sam.is_none() {
+        utils::require("aws")?;
+    }
+
+    print_separator("AWS");
+
+    let mut updated = false;
+    for (tool, binary) in [(&AWS_CLI, aws), (&SAM_CLI, sam)] {
+        let binary = match binary {
+            Some(binary) => binary,
+            None => continue,
+        };
+
+        match update_tool(ctx, tool, &binary) {
+            Ok(()) => updated = true,
+            Err(e) if e.is::<SkipStep>() => println!("{}", e),
+            Err(e) => return Err(e),
+        }
+    }
+
+    if updated {
+        Ok(())
+    } else {
+        Err(SkipStep(String::from("No AWS tool can be updated by Topgrade")).into())
+    }
+}
@@ -0,0 +1,71 @@
+use anyhow::Result;
+
+use crate::config::Step;
+use crate::error::SkipStep;
+use crate::execution_context::ExecutionContext;
+#[cfg(target_os = "linux")]
+use crate::steps::os::linux;
+#[cfg(unix)]
+use crate::steps::os::unix::{self, BrewVariant};
+use crate::terminal::print_separator;
+
+/// The steps whose package managers Homebrew cleans up after
+#[cfg(target_os = "macos")]
+const BREW_STEPS: &[Step] = &[Step::BrewFormula, Step::BrewCask];
+#[cfg(all(unix, not(target_os = "macos")))]
+const BREW_STEPS: &[Step] = &[Step::BrewFormula];
+
+/// A cleanup action with the steps of the package managers it cleans up after
+type Action<'a> = (&'static [Step], Box<dyn Fn() -> Result<()> + 'a>);
+
+/// Run the cleanup actions of the package managers which don't clean up during their own step. The actions of
+/// package managers whose steps don't run are left out.
+#[cfg_attr(windows, allow(unused_variables))]
+pub fn run_cleanup(ctx: &ExecutionContext) -> Result<()> {
+    print_separator("Cleanup");
+
+    #[allow(unused_mut)]
+    let mut actions: Vec<Action> = Vec::new();
+
+    #[cfg(target_os = "linux")]
+    {
+        actions.push((
+            &[Step::System],
+            Box::new(|| linux::Distribution::detect()?.cleanup(ctx)),
+        ));
+        actions.push((&[Step::System], Box::new(|| linux::vacuum_journal(ctx))));
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        actions.push((
+            BREW_STEPS,
+            Box::new(|| unix::run_brew_cleanup(ctx, BrewVariant::MacArm)),
+        ));
+        actions.push((
+            BREW_STEPS,
+            Box::new(|| unix::run_brew_cleanup(ctx, BrewVariant::MacIntel)),
+        ));
+    }
+
+    #[cfg(unix)]
+    actions.push((BREW_STEPS, Box::new(|| unix::run_brew_cleanup(ctx, BrewVariant::Path))));
+
+    let mut ran = false;
+    for (steps, action) in actions {
+        if !steps.iter().any(|step| ctx.config().should_run(*step)) {
+            continue;
+        }
+        match action() {
+            Ok(()) => ran = true,
+            Err(e) if e.is::<SkipStep>() => (),
+            Err(e) => return Err(e),
+        }
+    }
+
+    if ran {
+        Ok(())
+    } else {
+        Err(SkipStep(String::from("Nothing to clean up")).into())
+    }
+}
@@ -1,11 +1,14 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use glob::Pattern;
 
+use crate::config::ContainerRuntime;
 use crate::error::{self, TopgradeError};
 use crate::executor::CommandExt;
 use crate::terminal::print_separator;
+use crate::utils::PathExt;
 use crate::{execution_context::ExecutionContext, utils::require};
 use log::{debug, error, warn};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 // A string found in the output of docker for containers that weren't found in
@@ -17,7 +20,7 @@ const NONEXISTENT_REPO: &str = "repository does not exist";
 
 /// Returns a Vector of all containers, with Strings in the format
 /// "REGISTRY/[PATH/]CONTAINER_NAME:TAG"
-fn list_containers(crt: &Path) -> Result<Vec<String>> {
+fn list_containers(crt: &Path, exclude: &[Pattern]) -> Result<Vec<String>> {
     debug!(
         "Querying '{} image ls --format \"{{{{.Repository}}}}:{{{{.Tag}}}}\"' for containers",
         crt.display()
@@ -46,6 +49,11 @@ fn list_containers(crt: &Path) -> Result<Vec<String>> {
             continue;
         }
 
+        if exclude.iter().any(|pattern| pattern.matches(line)) {
+            debug!("Skipping excluded container '{}'", line);
+            continue;
+        }
+
         debug!("Using container '{}'", line);
         retval.push(String::from(line));
     }
@@ -54,13 +62,25 @@ fn list_containers(crt: &Path) -> Result<Vec<String>> {
 }
 
 pub fn run_containers(ctx: &ExecutionContext) -> Result<()> {
-    // Prefer podman, fall back to docker if not present
-    let crt = require("podman").or_else(|_| require("docker"))?;
+    let crt = match ctx.config().container_runtime() {
+        Some(ContainerRuntime::Docker) => require("docker")?,
+        Some(ContainerRuntime::Podman) => require("podman")?,
+        // Prefer podman, fall back to docker if not present
+        None => require("podman").or_else(|_| require("docker"))?,
+    };
     debug!("Using container runtime '{}'", crt.display());
 
+    let exclude = ctx
+        .config()
+        .containers_exclude()
+        .into_iter()
+        .flatten()
+        .map(|pattern| Pattern::new(pattern).with_context(|| format!("Invalid exclude pattern {}", pattern)))
+        .collect::<Result<Vec<_>>>()?;
+
     print_separator("Containers");
     let mut success = true;
-    let containers = list_containers(&crt)?;
+    let containers = list_containers(&crt, &exclude)?;
     debug!("Containers to inspect: {:?}", containers);
 
     for container in containers.iter() {
@@ -107,3 +127,43 @@ pub fn run_containers(ctx: &ExecutionContext) -> Result<()> {
         Err(anyhow::anyhow!(error::StepFailed))
     }
 }
+
+/// Returns the command running Docker Compose, preferring the compose plugin of docker
+fn compose_command() -> Result<(PathBuf, Vec<&'static str>)> {
+    if let Ok(docker) = require("docker") {
+        if Command::new(&docker)
+            .args(&["compose", "version"])
+            .check_output()
+            .is_ok()
+        {
+            return Ok((docker, vec!["compose"]));
+        }
+    }
+
+    let compose = require("docker-compose").or_else(|_| require("podman-compose"))?;
+    Ok((compose, vec![]))
+}
+
+/// Pull the images of a compose stack and recreate the containers whose images changed
+pub fn run_compose_stack(ctx: &ExecutionContext, compose_file: &str) -> Result<()> {
+    let compose_file = Path::new(compose_file).require()?;
+    let (compose, args) = compose_command()?;
+
+    print_separator(format!("Compose ({})", compose_file.display()));
+
+    ctx.run_type()
+        .execute(&compose)
+        .args(&args)
+        .arg("-f")
+        .arg(compose_file)
+        .arg("pull")
+        .check_run()?;
+
+    ctx.run_type()
+        .execute(&compose)
+        .args(&args)
+        .arg("-f")
+        .arg(compose_file)
+        .args(&["up", "-d"])
+        .check_run()
+}
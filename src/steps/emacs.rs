@@ -11,6 +11,7 @@ use crate::utils::{require, require_option, PathExt};
 use crate::Step;
 
 const EMACS_UPGRADE: &str = include_str!("emacs.el");
+const STRAIGHT_UPGRADE: &str = "(progn (straight-pull-all) (straight-rebuild-all))";
 #[cfg(windows)]
 const DOOM_PATH: &str = "bin/doom.cmd";
 #[cfg(unix)]
@@ -19,6 +20,7 @@ const DOOM_PATH: &str = "bin/doom";
 pub struct Emacs {
     directory: Option<PathBuf>,
     doom: Option<PathBuf>,
+    straight: bool,
 }
 
 impl Emacs {
@@ -52,7 +54,15 @@ impl Emacs {
     pub fn new(base_dirs: &BaseDirs) -> Self {
         let directory = Emacs::directory_path(base_dirs);
         let doom = directory.as_ref().and_then(|d| d.join(DOOM_PATH).if_exists());
-        Self { directory, doom }
+        let straight = directory
+            .as_ref()
+            .map(|d| d.join("straight/repos").is_dir())
+            .unwrap_or(false);
+        Self {
+            directory,
+            doom,
+            straight,
+        }
     }
 
     pub fn is_doom(&self) -> bool {
@@ -88,6 +98,12 @@ impl Emacs {
 
         print_separator("Emacs");
 
+        let expression = match ctx.config().emacs_update_function() {
+            Some(function) => format!("({})", function),
+            None if self.straight => String::from(STRAIGHT_UPGRADE),
+            None => String::from(EMACS_UPGRADE),
+        };
+
         let mut command = ctx.run_type().execute(&emacs);
 
         command
@@ -97,14 +113,14 @@ impl Emacs {
 
         #[cfg(unix)]
         command.arg(
-            EMACS_UPGRADE
+            expression
                 .chars()
                 .map(|c| if c.is_whitespace() { '\u{00a0}' } else { c })
                 .collect::<String>(),
         );
 
         #[cfg(not(unix))]
-        command.arg(EMACS_UPGRADE);
+        command.arg(expression);
 
         command.check_run()
     }
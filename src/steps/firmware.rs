@@ -0,0 +1,136 @@
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::Result;
+use log::debug;
+use serde::Deserialize;
+
+#[cfg(target_os = "linux")]
+use crate::error::SkipStep;
+use crate::execution_context::ExecutionContext;
+use crate::executor::{Executor, ExecutorExitStatus, RunType};
+use crate::terminal::print_separator;
+use crate::utils::{require, Check};
+use crate::Step;
+
+pub fn run_fwupdmgr(ctx: &ExecutionContext) -> Result<()> {
+    let fwupdmgr = require("fwupdmgr")?;
+
+    #[cfg(target_os = "linux")]
+    {
+        if crate::steps::os::linux::is_wsl()? {
+            return Err(SkipStep(String::from("Should not run in WSL")).into());
+        }
+    }
+
+    print_separator("Firmware upgrades");
+
+    ctx.run_type()
+        .execute(&fwupdmgr)
+        .arg("refresh")
+        .check_run_with_codes(&[2])?;
+
+    if !ctx.config().firmware_upgrade() {
+        return ctx
+            .run_type()
+            .execute(&fwupdmgr)
+            .arg("get-updates")
+            .check_run_with_codes(&[2]);
+    }
+
+    let pending = pending_firmware_updates(&fwupdmgr);
+    debug!("Pending firmware updates: {:?}", pending);
+
+    let updated: Vec<&FirmwareDevice> = match ctx.config().firmware_devices() {
+        Some(allowed) => {
+            let mut updated = Vec::new();
+            for device in &pending {
+                if let Some(guid) = device
+                    .guid
+                    .iter()
+                    .find(|guid| allowed.iter().any(|a| a.eq_ignore_ascii_case(guid)))
+                {
+                    let mut updmgr = ctx.run_type().execute(&fwupdmgr);
+                    updmgr.arg("update").arg(guid);
+                    if ctx.config().yes(Step::System) {
+                        updmgr.arg("-y");
+                    }
+                    if run_update(&mut updmgr)? {
+                        updated.push(device);
+                    }
+                } else {
+                    debug!("Skipping firmware of {}", device.name);
+                }
+            }
+            updated
+        }
+        None => {
+            let mut updmgr = ctx.run_type().execute(&fwupdmgr);
+            updmgr.arg("update");
+            if ctx.config().yes(Step::System) {
+                updmgr.arg("-y");
+            }
+            if run_update(&mut updmgr)? {
+                pending.iter().collect()
+            } else {
+                Vec::new()
+            }
+        }
+    };
+
+    if let RunType::Wet = ctx.run_type() {
+        ctx.report_changes(!updated.is_empty());
+        for device in updated {
+            if device.needs_reboot() {
+                ctx.report_reboot_required(format!("Firmware of {} is applied on reboot", device.name));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Run `fwupdmgr update`, telling whether it updated anything. It exits with 2 when there was nothing to do, which
+/// is also the case when the user declined the update.
+fn run_update(updmgr: &mut Executor) -> Result<bool> {
+    match updmgr.spawn()?.wait()? {
+        ExecutorExitStatus::Wet(status) if status.code() == Some(2) => Ok(false),
+        status => status.check().map(|()| true),
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "PascalCase")]
+struct FirmwareUpdates {
+    #[serde(default)]
+    devices: Vec<FirmwareDevice>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct FirmwareDevice {
+    name: String,
+    #[serde(default)]
+    guid: Vec<String>,
+    #[serde(default)]
+    flags: Vec<String>,
+}
+
+impl FirmwareDevice {
+    fn needs_reboot(&self) -> bool {
+        self.flags.iter().any(|f| f == "needs-reboot" || f == "needs-shutdown")
+    }
+}
+
+/// The devices with pending firmware updates, as reported by `fwupdmgr get-updates --json`.
+///
+/// fwupdmgr exits with an error when there are no updates, so any failure is treated as nothing to update.
+fn pending_firmware_updates(fwupdmgr: &Path) -> Vec<FirmwareDevice> {
+    Command::new(fwupdmgr)
+        .args(&["get-updates", "--json"])
+        .output()
+        .ok()
+        .and_then(|output| serde_json::from_slice::<FirmwareUpdates>(&output.stdout).ok())
+        .unwrap_or_default()
+        .devices
+}
@@ -8,17 +8,78 @@ use std::{fs, io::Write};
 use anyhow::Result;
 use directories::BaseDirs;
 use log::debug;
+use serde::Deserialize;
 use tempfile::tempfile_in;
+use walkdir::WalkDir;
 
+use crate::config::Step;
 use crate::execution_context::ExecutionContext;
-use crate::executor::{CommandExt, ExecutorOutput, RunType};
+use crate::executor::{CommandExt, Executor, ExecutorOutput, RunType};
+use crate::report::Upgrade;
+use crate::secrets;
 use crate::terminal::{print_separator, shell};
-use crate::utils::{self, require_option, PathExt};
+use crate::utils::{self, require_option, which, PathExt};
 use crate::{
-    error::{SkipStep, TopgradeError},
+    error::{SkipStep, StepFailed, TopgradeError},
     terminal::print_warning,
 };
 
+/// A crate in the manifest of cargo-binstall
+#[derive(Deserialize)]
+struct BinstallCrate {
+    name: String,
+}
+
+/// The crates installed by cargo-binstall. Its manifest is a stream of JSON objects.
+fn binstall_crates(cargo_dir: &Path) -> Vec<String> {
+    fs::read_to_string(cargo_dir.join("binstall/crates-v1.json"))
+        .map(|manifest| {
+            serde_json::Deserializer::from_str(&manifest)
+                .into_iter::<BinstallCrate>()
+                .filter_map(|c| c.ok())
+                .map(|c| c.name)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The names of the crates installed by cargo. Entries in the `v1` table look like `name version (source)`.
+fn cargo_installed_crates(toml_file: &Path) -> Result<Vec<String>> {
+    let manifest: toml::Value = toml::from_str(&fs::read_to_string(toml_file)?)?;
+
+    Ok(manifest
+        .get("v1")
+        .and_then(|v1| v1.as_table())
+        .map(|v1| {
+            v1.keys()
+                .filter_map(|key| key.split_whitespace().next())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+/// The crates cargo-update would upgrade, with their newest versions. Its list is a table whose rows end with the
+/// installed and the latest versions, and whether the crate needs an update.
+fn cargo_outdated_crates(cargo_update: &Path) -> Vec<Upgrade> {
+    Command::new(cargo_update)
+        .args(&["install-update", "--list", "--git"])
+        .check_output()
+        .map(|output| {
+            output
+                .lines()
+                .filter_map(|line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+                    [name, _, latest, "Yes"] => Some(Upgrade {
+                        name: name.to_string(),
+                        version: latest.trim_start_matches('v').to_string(),
+                    }),
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
 pub fn run_cargo_update(ctx: &ExecutionContext) -> Result<()> {
     let cargo_dir = env::var_os("CARGO_HOME")
         .map(PathBuf::from)
@@ -38,11 +99,39 @@ pub fn run_cargo_update(ctx: &ExecutionContext) -> Result<()> {
     }
 
     print_separator("Cargo");
+
+    // cargo-update would build the crates installed by cargo-binstall from source, so binstall updates them itself
+    let binstall = utils::require("cargo-binstall")
+        .ok()
+        .or_else(|| cargo_dir.join("bin/cargo-binstall").if_exists());
+    let binstalled = match &binstall {
+        Some(_) => binstall_crates(&cargo_dir),
+        None => Vec::new(),
+    };
+
+    if let Some(binstall) = binstall.as_ref().filter(|_| !binstalled.is_empty()) {
+        let mut command = ctx.run_type().execute(binstall);
+        command.args(&binstalled);
+        if ctx.config().yes(Step::Cargo) {
+            command.arg("--no-confirm");
+        }
+        command.check_run()?;
+    }
+
+    let remaining: Vec<String> = cargo_installed_crates(&toml_file)?
+        .into_iter()
+        .filter(|c| !binstalled.contains(c))
+        .collect();
+    if !binstalled.is_empty() && remaining.is_empty() {
+        return Ok(());
+    }
+
     let cargo_update = utils::require("cargo-install-update")
         .ok()
         .or_else(|| cargo_dir.join("bin/cargo-install-update").if_exists());
     let cargo_update = match cargo_update {
         Some(e) => e,
+        None if !binstalled.is_empty() => return Ok(()),
         None => {
             let message = String::from("cargo-update isn't installed so Topgrade can't upgrade cargo packages.\nInstall cargo-update by running `cargo install cargo-update`");
             print_warning(&message);
@@ -50,10 +139,30 @@ pub fn run_cargo_update(ctx: &ExecutionContext) -> Result<()> {
         }
     };
 
-    ctx.run_type()
-        .execute(cargo_update)
-        .args(&["install-update", "--git", "--all"])
-        .check_run()
+    let report_upgrades = !ctx.run_type().dry() && ctx.config().changelog_url(Step::Cargo.as_ref()).is_some();
+    let outdated: Vec<Upgrade> = if report_upgrades {
+        cargo_outdated_crates(&cargo_update)
+            .into_iter()
+            .filter(|upgrade| remaining.contains(&upgrade.name))
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let mut command = ctx.run_type().execute(cargo_update);
+    command.args(&["install-update", "--git"]);
+    command.args(ctx.config().step_arguments(Step::Cargo));
+    if binstalled.is_empty() {
+        command.arg("--all");
+    } else {
+        command.args(&remaining);
+    }
+    command.check_run()?;
+
+    if report_upgrades {
+        ctx.report_upgrades(outdated);
+    }
+    Ok(())
 }
 
 pub fn run_flutter_upgrade(run_type: RunType) -> Result<()> {
@@ -108,9 +217,7 @@ pub fn run_haxelib_update(ctx: &ExecutionContext) -> Result<()> {
     let mut command = if directory_writable {
         ctx.run_type().execute(&haxelib)
     } else {
-        let mut c = ctx
-            .run_type()
-            .execute(ctx.sudo().as_ref().ok_or(TopgradeError::SudoRequired)?);
+        let mut c = ctx.run_type().execute(ctx.require_sudo()?);
         c.arg(&haxelib);
         c
     };
@@ -185,12 +292,51 @@ pub fn run_choosenim(ctx: &ExecutionContext) -> Result<()> {
     run_type.execute(&choosenim).args(&["update", "stable"]).check_run()
 }
 
-pub fn run_krew_upgrade(run_type: RunType) -> Result<()> {
+pub fn run_krew_upgrade(ctx: &ExecutionContext) -> Result<()> {
     let krew = utils::require("kubectl-krew")?;
 
     print_separator("Krew");
 
-    run_type.execute(&krew).args(&["upgrade"]).check_run()
+    let index_list = Command::new(&krew).args(&["index", "list"]).check_output()?;
+    // The first line is the header of the table
+    let mut indexes: Vec<&str> = index_list
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().next())
+        .collect();
+
+    for (name, url) in ctx.config().krew_indexes().into_iter().flatten() {
+        if !indexes.contains(&name.as_str()) {
+            ctx.run_type()
+                .execute(&krew)
+                .args(&["index", "add", name, url])
+                .check_run()?;
+            indexes.push(name);
+        }
+    }
+
+    ctx.run_type().execute(&krew).arg("update").check_run()?;
+
+    let mut command = ctx.run_type().execute(&krew);
+    command.arg("upgrade");
+    if let Some(arguments) = ctx.config().krew_arguments() {
+        command.args(arguments.split_whitespace());
+    }
+    command.check_run()?;
+
+    if ctx.config().cleanup() {
+        // Plugins from indexes other than the default one are listed as index/plugin
+        let plugins = Command::new(&krew).arg("list").check_output()?;
+        for plugin in plugins.lines().map(str::trim) {
+            if let Some((index, _)) = plugin.split_once('/') {
+                if !indexes.contains(&index) {
+                    ctx.run_type().execute(&krew).args(&["uninstall", plugin]).check_run()?;
+                }
+            }
+        }
+    }
+
+    Ok(())
 }
 
 pub fn run_gcloud_components_update(run_type: RunType) -> Result<()> {
@@ -204,6 +350,194 @@ pub fn run_gcloud_components_update(run_type: RunType) -> Result<()> {
         .check_run()
 }
 
+pub fn run_bazelisk(ctx: &ExecutionContext) -> Result<()> {
+    let bazelisk = utils::require("bazelisk")?;
+
+    print_separator("Bazel");
+
+    // Outside of a workspace pinning a version with .bazelversion, bazelisk fetches the latest release
+    ctx.run_type()
+        .execute(&bazelisk)
+        .arg("version")
+        .current_dir(ctx.base_dirs().home_dir())
+        .check_run()?;
+
+    if ctx.config().cleanup() {
+        if let Some(disk_cache) = ctx.config().bazel_disk_cache() {
+            trim_bazel_disk_cache(ctx, Path::new(disk_cache), ctx.config().bazel_disk_cache_max_size_mb())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove the least recently modified entries of the disk cache until it fits in `max_size_mb`
+fn trim_bazel_disk_cache(ctx: &ExecutionContext, disk_cache: &Path, max_size_mb: u64) -> Result<()> {
+    let disk_cache = disk_cache.require()?;
+
+    let mut entries: Vec<_> = WalkDir::new(&disk_cache)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.metadata().ok().map(|m| (e.into_path(), m)))
+        .filter(|(_, metadata)| metadata.is_file())
+        .map(|(path, metadata)| (path, metadata.len(), metadata.modified().ok()))
+        .collect();
+
+    let max_size = max_size_mb * 1024 * 1024;
+    let mut size: u64 = entries.iter().map(|(_, len, _)| len).sum();
+    debug!("Bazel disk cache {} holds {} bytes", disk_cache.display(), size);
+    if size <= max_size {
+        return Ok(());
+    }
+
+    entries.sort_by_key(|(_, _, modified)| *modified);
+
+    let mut removed = 0;
+    for (path, len, _) in entries {
+        if size <= max_size {
+            break;
+        }
+
+        if !ctx.run_type().dry() {
+            fs::remove_file(&path)?;
+        }
+        size -= len;
+        removed += 1;
+    }
+
+    println!(
+        "{} {} entries from the Bazel disk cache to trim it to {} MB",
+        if ctx.run_type().dry() {
+            "Would remove"
+        } else {
+            "Removed"
+        },
+        removed,
+        max_size_mb
+    );
+
+    Ok(())
+}
+
+pub fn run_terraform(ctx: &ExecutionContext) -> Result<()> {
+    let tfenv = utils::which("tfenv").filter(|_| ctx.config().tfenv_install_latest());
+    let tflint = utils::which("tflint");
+    let terragrunt = utils::which("terragrunt");
+
+    if tfenv.is_none() && tflint.is_none() && terragrunt.is_none() {
+        return Err(SkipStep(String::from("No Terraform tools found")).into());
+    }
+
+    print_separator("Terraform");
+
+    if let Some(tfenv) = tfenv {
+        ctx.run_type()
+            .execute(&tfenv)
+            .args(&["install", "latest"])
+            .check_run()?;
+    }
+
+    // tflint reads the plugins to install from ~/.tflint.hcl when the current directory has no configuration
+    if let Some(tflint) = tflint {
+        if ctx.base_dirs().home_dir().join(".tflint.hcl").exists() {
+            ctx.run_type()
+                .execute(&tflint)
+                .arg("--init")
+                .current_dir(ctx.base_dirs().home_dir())
+                .check_run()?;
+        }
+    }
+
+    // Terragrunt has no self update. It's installed by the system package manager or by hand.
+    if let Some(terragrunt) = terragrunt {
+        ctx.run_type().execute(&terragrunt).arg("--version").check_run()?;
+    }
+
+    Ok(())
+}
+
+/// Install the hook environments of the repository, after updating its hooks with `pre-commit autoupdate` if
+/// `autoupdate` is set
+pub fn run_pre_commit(ctx: &ExecutionContext, repository: &str, autoupdate: bool) -> Result<()> {
+    let pre_commit = utils::require("pre-commit")?;
+    let repository = Path::new(repository).require()?;
+
+    print_separator(format!("pre-commit ({})", repository.display()));
+
+    if autoupdate {
+        ctx.run_type()
+            .execute(&pre_commit)
+            .arg("autoupdate")
+            .current_dir(&repository)
+            .check_run()?;
+    }
+
+    ctx.run_type()
+        .execute(&pre_commit)
+        .arg("install-hooks")
+        .current_dir(&repository)
+        .check_run()
+}
+
+/// Remove the hook environments which no repository uses anymore
+pub fn run_pre_commit_gc(ctx: &ExecutionContext) -> Result<()> {
+    let pre_commit = utils::require("pre-commit")?;
+
+    print_separator("pre-commit gc");
+
+    ctx.run_type().execute(&pre_commit).arg("gc").check_run()
+}
+
+pub fn run_azure_cli(ctx: &ExecutionContext) -> Result<()> {
+    let az = utils::require("az")?;
+
+    print_separator("Azure CLI");
+
+    // Extensions are updated one by one below so that a failing extension is reported on its own
+    let mut command = ctx.run_type().execute(&az);
+    command.args(&["upgrade", "--all", "false"]);
+    if ctx.config().yes(Step::AzureCli) {
+        command.arg("--yes");
+    }
+    command.check_run()?;
+
+    let extensions = Command::new(&az)
+        .args(&["extension", "list", "--query", "[].name", "--output", "tsv"])
+        .check_output()?;
+
+    let mut success = true;
+    for extension in extensions.lines().map(str::trim).filter(|e| !e.is_empty()) {
+        if let Err(e) = ctx
+            .run_type()
+            .execute(&az)
+            .args(&["extension", "update", "--name", extension])
+            .check_run()
+        {
+            print_warning(format!("Updating the {} extension failed: {}", extension, e));
+            success = false;
+        }
+    }
+
+    if success {
+        Ok(())
+    } else {
+        Err(StepFailed.into())
+    }
+}
+
+pub fn run_vscode_extensions_update(ctx: &ExecutionContext, binary: &str) -> Result<()> {
+    let code = utils::require(binary)?;
+
+    print_separator(match binary {
+        "code" => "VS Code",
+        "codium" => "VSCodium",
+        other => other,
+    });
+
+    // Requires VS Code 1.86 or later
+    ctx.run_type().execute(&code).arg("--update-extensions").check_run()
+}
+
 pub fn run_jetpack(run_type: RunType) -> Result<()> {
     let jetpack = utils::require("jetpack")?;
 
@@ -244,26 +578,73 @@ pub fn run_pipx_update(run_type: RunType) -> Result<()> {
 }
 
 pub fn run_conda_update(ctx: &ExecutionContext) -> Result<()> {
-    let conda = utils::require("conda")?;
-
-    let output = Command::new("conda")
-        .args(&["config", "--show", "auto_activate_base"])
-        .output()?;
-    let string_output = String::from_utf8(output.stdout)?;
-    debug!("Conda output: {}", string_output);
-    if string_output.contains("False") {
-        return Err(SkipStep("auto_activate_base is set to False".to_string()).into());
+    // mamba is a faster drop-in replacement for conda, while micromamba is a standalone executable without conda
+    let micromamba = which("micromamba");
+    let conda = utils::require("mamba")
+        .or_else(|_| utils::require("conda"))
+        .or_else(|e| micromamba.clone().ok_or(e))?;
+    let is_micromamba = Some(&conda) == micromamba.as_ref();
+    let environments = ctx.config().conda_environments();
+
+    // Only conda itself knows whether the base environment is activated automatically
+    if environments.is_none() && !is_micromamba {
+        if let Some(conda_config) = which("conda") {
+            let output = Command::new(conda_config)
+                .args(&["config", "--show", "auto_activate_base"])
+                .output()?;
+            let string_output = String::from_utf8(output.stdout)?;
+            debug!("Conda output: {}", string_output);
+            if string_output.contains("False") {
+                return Err(SkipStep("auto_activate_base is set to False".to_string()).into());
+            }
+        }
     }
 
     print_separator("Conda");
 
-    ctx.run_type()
-        .execute(&conda)
-        .args(&["update", "--all", "-y"])
-        .check_run()
+    if is_micromamba {
+        ctx.run_type().execute(&conda).arg("self-update").check_run()?;
+    }
+
+    match environments {
+        Some(environments) => {
+            for environment in environments {
+                ctx.run_type()
+                    .execute(&conda)
+                    .args(&["update", "--all", "-y", "-n", environment])
+                    .check_run()?;
+            }
+        }
+        // micromamba has no active environment outside of a shell which activated one
+        None if is_micromamba => ctx
+            .run_type()
+            .execute(&conda)
+            .args(&["update", "--all", "-y", "-n", "base"])
+            .check_run()?,
+        None => ctx
+            .run_type()
+            .execute(&conda)
+            .args(&["update", "--all", "-y"])
+            .check_run()?,
+    }
+
+    if ctx.config().cleanup() {
+        ctx.run_type()
+            .execute(&conda)
+            .args(&["clean", "--all", "-y"])
+            .check_run()?;
+    }
+
+    Ok(())
 }
 
-pub fn run_pip3_update(run_type: RunType) -> Result<()> {
+/// A package in the output of `pip list --format=json`
+#[derive(Deserialize)]
+struct PipPackage {
+    name: String,
+}
+
+pub fn run_pip3_update(ctx: &ExecutionContext) -> Result<()> {
     let python3 = utils::require("python3")?;
     Command::new(&python3)
         .args(&["-m", "pip"])
@@ -276,10 +657,43 @@ pub fn run_pip3_update(run_type: RunType) -> Result<()> {
         return Err(SkipStep("Does not run inside a virtual environment".to_string()).into());
     }
 
-    run_type
+    ctx.run_type()
         .execute(&python3)
         .args(&["-m", "pip", "install", "--upgrade", "--user", "pip"])
-        .check_run()
+        .check_run()?;
+
+    if !ctx.config().pip3_upgrade_user_packages() {
+        return Ok(());
+    }
+
+    let outdated = Command::new(&python3)
+        .args(&["-m", "pip", "list", "--outdated", "--user", "--format=json"])
+        .check_output()?;
+    let exclude = ctx.config().pip3_exclude();
+    let packages: Vec<String> = serde_json::from_str::<Vec<PipPackage>>(&outdated)?
+        .into_iter()
+        .map(|package| package.name)
+        .filter(|name| name != "pip")
+        .filter(|name| {
+            !exclude
+                .into_iter()
+                .flatten()
+                .any(|excluded| excluded.eq_ignore_ascii_case(name))
+        })
+        .collect();
+
+    if packages.is_empty() {
+        return Ok(());
+    }
+
+    ctx.run_type()
+        .execute(&python3)
+        .args(&["-m", "pip", "install", "--upgrade", "--user"])
+        .args(&packages)
+        .check_run()?;
+    ctx.report_changes(!ctx.run_type().dry());
+
+    Ok(())
 }
 
 pub fn run_stack_update(run_type: RunType) -> Result<()> {
@@ -323,9 +737,7 @@ pub fn run_tlmgr_update(ctx: &ExecutionContext) -> Result<()> {
     let mut command = if directory_writable {
         ctx.run_type().execute(&tlmgr)
     } else {
-        let mut c = ctx
-            .run_type()
-            .execute(ctx.sudo().as_ref().ok_or(TopgradeError::SudoRequired)?);
+        let mut c = ctx.run_type().execute(ctx.require_sudo()?);
         c.arg(&tlmgr);
         c
     };
@@ -363,9 +775,95 @@ pub fn run_myrepos_update(base_dirs: &BaseDirs, run_type: RunType) -> Result<()>
         .check_run()
 }
 
+pub fn run_repo_sync(ctx: &ExecutionContext, workspace: &str) -> Result<()> {
+    let repo = utils::require("repo")?;
+    Path::new(workspace).join(".repo").require()?;
+
+    print_separator(format!("repo ({})", workspace));
+
+    ctx.run_type()
+        .execute(&repo)
+        .current_dir(workspace)
+        .arg("sync")
+        .check_run()
+}
+
+pub fn run_west_update(ctx: &ExecutionContext, workspace: &str) -> Result<()> {
+    let west = utils::require("west")?;
+    Path::new(workspace).join(".west").require()?;
+
+    print_separator(format!("west ({})", workspace));
+
+    ctx.run_type()
+        .execute(&west)
+        .current_dir(workspace)
+        .arg("update")
+        .check_run()
+}
+
+pub fn run_hg_pull(ctx: &ExecutionContext, working_copy: &str) -> Result<()> {
+    let hg = utils::require("hg")?;
+    Path::new(working_copy).join(".hg").require()?;
+
+    print_separator(format!("Mercurial ({})", working_copy));
+
+    ctx.run_type()
+        .execute(&hg)
+        .current_dir(working_copy)
+        .args(&["pull", "--update"])
+        .check_run()
+}
+
+pub fn run_svn_update(ctx: &ExecutionContext, working_copy: &str) -> Result<()> {
+    let svn = utils::require("svn")?;
+    Path::new(working_copy).join(".svn").require()?;
+
+    print_separator(format!("Subversion ({})", working_copy));
+
+    ctx.run_type()
+        .execute(&svn)
+        .current_dir(working_copy)
+        .arg("update")
+        .check_run()
+}
+
+/// The configuration and theme repositories of terminal emulators, and the plugins of WezTerm, which are clones
+/// of their repositories
+pub fn terminal_repositories(base_dirs: &BaseDirs) -> Vec<PathBuf> {
+    let config = base_dirs.home_dir().join(".config");
+    let mut repositories = vec![
+        config.join("alacritty"),
+        // Where the instructions of alacritty-theme clone it
+        config.join("alacritty/themes"),
+        config.join("kitty"),
+        config.join("kitty/kitty-themes"),
+        config.join("wezterm"),
+    ];
+
+    if let Ok(plugins) = fs::read_dir(base_dirs.data_dir().join("wezterm/plugins")) {
+        repositories.extend(plugins.flatten().map(|plugin| plugin.path()));
+    }
+
+    repositories
+}
+
 pub fn run_custom_command(name: &str, command: &str, ctx: &ExecutionContext) -> Result<()> {
     print_separator(name);
-    ctx.run_type().execute(shell()).arg("-c").arg(command).check_run()
+
+    custom_command(command, ctx)?.check_run()
+}
+
+/// The executor of a custom command, whose secrets are passed in its environment
+pub fn custom_command(command: &str, ctx: &ExecutionContext) -> Result<Executor> {
+    let mut executor = ctx.run_type().execute(shell());
+
+    let (command, secrets) = secrets::expand_to_environment(command, ctx.config())?;
+    for (variable, value) in secrets {
+        executor.env(variable, value);
+    }
+
+    executor.arg("-c").arg(command);
+    Ok(executor)
 }
 
 pub fn run_composer_update(ctx: &ExecutionContext) -> Result<()> {
@@ -398,7 +896,7 @@ pub fn run_composer_update(ctx: &ExecutionContext) -> Result<()> {
 
                 if has_update {
                     ctx.run_type()
-                        .execute(ctx.sudo().as_ref().unwrap())
+                        .execute(ctx.require_sudo()?)
                         .arg(&composer)
                         .arg("self-update")
                         .check_run()?;
@@ -496,3 +994,66 @@ pub fn run_ghcli_extensions_upgrade(ctx: &ExecutionContext) -> Result<()> {
         .args(&["extension", "upgrade", "--all"])
         .check_run()
 }
+
+/// Whether one of the directories in PATH contains a link named `name` which doesn't resolve, as left behind by a
+/// removed alternative
+fn broken_link_in_path(name: &str) -> Option<PathBuf> {
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(name))
+        .find(|path| path.symlink_metadata().is_ok() && !path.exists())
+}
+
+/// Check that the default toolchains still run, as upgrades of toolchains or their alternatives can break them
+pub fn check_toolchains(ctx: &ExecutionContext) -> Result<()> {
+    print_separator("Toolchain check");
+
+    let mut broken = Vec::new();
+    for tool in ctx.config().toolchain_check_tools() {
+        let path = match which(&tool) {
+            Some(path) => path,
+            None => {
+                if let Some(link) = broken_link_in_path(&tool) {
+                    broken.push(format!("{} is a broken link", link.display()));
+                } else {
+                    debug!("{} is not installed", tool);
+                }
+                continue;
+            }
+        };
+
+        // Java only accepts a single dash
+        let version_argument = if tool == "java" { "-version" } else { "--version" };
+        match Command::new(&path).arg(version_argument).output() {
+            Ok(output) if output.status.success() => {
+                // Java prints its version to stderr
+                let version = String::from_utf8_lossy(if output.stdout.is_empty() {
+                    &output.stderr
+                } else {
+                    &output.stdout
+                })
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .to_string();
+                println!("{}: {}", tool, version);
+            }
+            Ok(output) => broken.push(format!(
+                "`{} {}` failed: {}",
+                path.display(),
+                version_argument,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )),
+            Err(e) => broken.push(format!("Failed running {}: {}", path.display(), e)),
+        }
+    }
+
+    if broken.is_empty() {
+        return Ok(());
+    }
+
+    for message in broken {
+        print_warning(&message);
+        ctx.add_summary_note(message);
+    }
+    Err(StepFailed.into())
+}
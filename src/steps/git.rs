@@ -1,20 +1,24 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
 use std::io;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Output, Stdio};
+use std::sync::Mutex;
 
 use anyhow::{anyhow, Result};
 use console::style;
 use futures::stream::{iter, FuturesUnordered};
 use futures::StreamExt;
-use glob::{glob_with, MatchOptions};
+use glob::{glob_with, MatchOptions, Pattern};
 use log::{debug, error};
 use tokio::process::Command as AsyncCommand;
 use tokio::runtime;
+use tokio::time;
 
+use crate::config::GitRepo;
 use crate::execution_context::ExecutionContext;
-use crate::executor::{CommandExt, RunType};
-use crate::terminal::print_separator;
+use crate::executor::{self, CommandExt, RunType};
+use crate::terminal::{clear_progress, print_progress, print_separator};
 use crate::utils::{which, PathExt};
 use crate::{error::SkipStep, terminal::print_warning};
 
@@ -29,8 +33,22 @@ pub struct Git {
 pub struct Repositories<'a> {
     git: &'a Git,
     repositories: HashSet<String>,
+    options: HashMap<String, GitRepo>,
     glob_match_options: MatchOptions,
     bad_patterns: Vec<String>,
+    exclude: Vec<Pattern>,
+}
+
+fn compile_patterns(patterns: Option<&Vec<String>>) -> Vec<Pattern> {
+    patterns
+        .into_iter()
+        .flatten()
+        .filter_map(|pattern| {
+            Pattern::new(pattern)
+                .map_err(|e| error!("Bad glob pattern {}: {}", pattern, e))
+                .ok()
+        })
+        .collect()
 }
 
 fn check_output(output: Output) -> Result<()> {
@@ -42,65 +60,227 @@ fn check_output(output: Output) -> Result<()> {
     }
 }
 
-async fn pull_repository(repo: String, git: &Path, ctx: &ExecutionContext<'_>) -> Result<()> {
+enum PullOutcome {
+    UpToDate,
+    Changed,
+}
+
+async fn sync_content(repo: &str, git: &Path, options: Option<&GitRepo>) -> Result<()> {
+    if let Some(options) = options {
+        if options.lfs() {
+            let output = AsyncCommand::new(git)
+                .args(&["lfs", "pull"])
+                .current_dir(repo)
+                .stdin(Stdio::null())
+                .kill_on_drop(true)
+                .output()
+                .await?;
+            check_output(output)?;
+        }
+
+        if options.annex() {
+            let output = AsyncCommand::new(git)
+                .args(&["annex", "sync", "--content"])
+                .current_dir(repo)
+                .stdin(Stdio::null())
+                .kill_on_drop(true)
+                .output()
+                .await?;
+            check_output(output)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Pull the repository, writing the messages into `out` so that the messages of repositories pulled concurrently
+/// don't interleave
+async fn pull_repository(
+    repo: String,
+    options: Option<&GitRepo>,
+    git: &Path,
+    ctx: &ExecutionContext<'_>,
+    out: &mut String,
+) -> Result<PullOutcome> {
     let path = repo.to_string();
     let before_revision = get_head_revision(git, &repo);
 
-    println!("{} {}", style("Pulling").cyan().bold(), path);
+    let stash_dirty = options
+        .and_then(GitRepo::stash_dirty)
+        .unwrap_or_else(|| ctx.config().git_stash_dirty());
+    let push_after_pull = options
+        .and_then(GitRepo::push_after_pull)
+        .unwrap_or_else(|| ctx.config().git_push_after_pull());
 
     let mut command = AsyncCommand::new(git);
 
     command
         .stdin(Stdio::null())
+        .kill_on_drop(true)
         .current_dir(&repo)
-        .args(&["pull", "--ff-only"]);
+        .arg("pull");
+    let repo_arguments = options.and_then(GitRepo::arguments);
+    if stash_dirty && is_dirty(git, &repo).await {
+        writeln!(out, "{} local changes of {}", style("Stashing").cyan().bold(), path)?;
+        command.args(&["--rebase", "--autostash"]);
+    } else if repo_arguments.is_none() {
+        command.arg("--ff-only");
+    }
 
-    if let Some(extra_arguments) = ctx.config().git_arguments() {
+    // The arguments of the repository replace both `--ff-only` and the global arguments
+    if let Some(extra_arguments) = repo_arguments.or_else(|| ctx.config().git_arguments().as_deref()) {
         command.args(extra_arguments.split_whitespace());
     }
 
+    if let Some(remote) = options.and_then(GitRepo::remote) {
+        command.arg(remote);
+        // Pulling from a remote which isn't the upstream of the branch requires naming the branch
+        if let Some(branch) = current_branch(git, &repo).await {
+            command.arg(branch);
+        }
+    }
+
     let pull_output = command.output().await?;
     let submodule_output = AsyncCommand::new(git)
         .args(&["submodule", "update", "--recursive"])
         .current_dir(&repo)
         .stdin(Stdio::null())
+        .kill_on_drop(true)
         .output()
         .await?;
-    let result = check_output(pull_output).and_then(|_| check_output(submodule_output));
+    let mut result = check_output(pull_output).and_then(|_| check_output(submodule_output));
+    if result.is_ok() {
+        result = sync_content(&repo, git, options).await;
+    }
+    if result.is_ok() && push_after_pull && is_ahead(git, &repo).await {
+        writeln!(out, "{} {}", style("Pushing").cyan().bold(), path)?;
+        result = AsyncCommand::new(git)
+            .arg("push")
+            .current_dir(&repo)
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output()
+            .await
+            .map_err(|e| e.into())
+            .and_then(check_output);
+    }
 
-    if let Err(message) = &result {
-        println!("{} pulling {}", style("Failed").red().bold(), &repo);
-        print!("{}", message);
-    } else {
-        let after_revision = get_head_revision(git, &repo);
-
-        match (&before_revision, &after_revision) {
-            (Some(before), Some(after)) if before != after => {
-                println!("{} {}:", style("Changed").yellow().bold(), &repo);
-
-                Command::new(&git)
-                    .stdin(Stdio::null())
-                    .current_dir(&repo)
-                    .args(&[
-                        "--no-pager",
-                        "log",
-                        "--no-decorate",
-                        "--oneline",
-                        &format!("{}..{}", before, after),
-                    ])
-                    .spawn()
-                    .unwrap()
-                    .wait()
-                    .unwrap();
-                println!();
-            }
-            _ => {
-                println!("{} {}", style("Up-to-date").green().bold(), &repo);
+    result?;
+
+    let after_revision = get_head_revision(git, &repo);
+    match (&before_revision, &after_revision) {
+        (Some(before), Some(after)) if before != after => {
+            ctx.report_changes(true);
+            writeln!(out, "{} {}:", style("Changed").yellow().bold(), &repo)?;
+
+            let log = Command::new(&git)
+                .stdin(Stdio::null())
+                .current_dir(&repo)
+                .args(&[
+                    "--no-pager",
+                    "log",
+                    "--no-decorate",
+                    "--oneline",
+                    &format!("{}..{}", before, after),
+                ])
+                .check_output()
+                .unwrap_or_default();
+            writeln!(out, "{}", log)?;
+            Ok(PullOutcome::Changed)
+        }
+        _ => {
+            ctx.report_changes(false);
+            writeln!(out, "{} {}", style("Up-to-date").green().bold(), &repo)?;
+            Ok(PullOutcome::UpToDate)
+        }
+    }
+}
+
+/// Pull the repository, returning its messages along with the result. The repository is listed in `in_progress`
+/// while it's being pulled.
+async fn pull_with_progress(
+    repo: String,
+    options: Option<&GitRepo>,
+    git: &Path,
+    ctx: &ExecutionContext<'_>,
+    in_progress: &Mutex<Vec<String>>,
+) -> (Result<PullOutcome>, String) {
+    in_progress.lock().unwrap().push(repo.clone());
+
+    let mut out = String::new();
+    let pull = pull_repository(repo.clone(), options, git, ctx, &mut out);
+    let result = match ctx.config().git_pull_timeout() {
+        Some(timeout) => match time::timeout(timeout, pull).await {
+            Ok(result) => result,
+            Err(_) => {
+                // Dropping the pull kills the git processes it started
+                ctx.add_summary_note(format!(
+                    "Pulling {} timed out after {} seconds",
+                    repo,
+                    timeout.as_secs()
+                ));
+                Err(anyhow!("Timed out after {} seconds", timeout.as_secs()))
             }
+        },
+        None => pull.await,
+    };
+    if let Err(message) = &result {
+        out.push_str(&format!(
+            "{} pulling {}\n{}",
+            style("Failed").red().bold(),
+            &repo,
+            message
+        ));
+        if !out.ends_with('\n') {
+            out.push('\n');
         }
     }
 
-    result.map(|_| ())
+    in_progress.lock().unwrap().retain(|r| r != &repo);
+    (result, out)
+}
+
+/// The name of the checked out branch, unless the HEAD is detached
+async fn current_branch(git: &Path, repo: &str) -> Option<String> {
+    AsyncCommand::new(git)
+        .args(&["symbolic-ref", "--short", "HEAD"])
+        .current_dir(repo)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Whether the repository has uncommitted changes to tracked files
+async fn is_dirty(git: &Path, repo: &str) -> bool {
+    AsyncCommand::new(git)
+        .args(&["status", "--porcelain", "--untracked-files=no"])
+        .current_dir(repo)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map(|output| output.status.success() && !output.stdout.is_empty())
+        .unwrap_or(false)
+}
+
+/// Whether the current branch has commits which its upstream branch doesn't
+async fn is_ahead(git: &Path, repo: &str) -> bool {
+    AsyncCommand::new(git)
+        .args(&["rev-list", "--count", "@{upstream}..HEAD"])
+        .current_dir(repo)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8_lossy(&output.stdout).trim().parse::<u32>().ok())
+        .map(|count| count > 0)
+        .unwrap_or(false)
 }
 
 fn get_head_revision(git: &Path, repo: &str) -> Option<String> {
@@ -137,6 +317,27 @@ impl Git {
         Self { git: which("git") }
     }
 
+    /// Whether the repository opted out of being pulled, with a `.topgrade-ignore` file or with
+    /// `topgrade.ignore = true` in its git configuration
+    fn is_ignored(&self, repo: &str) -> bool {
+        if Path::new(repo).join(".topgrade-ignore").exists() {
+            return true;
+        }
+
+        let git = match &self.git {
+            Some(git) => git,
+            None => return false,
+        };
+
+        Command::new(git)
+            .stdin(Stdio::null())
+            .current_dir(repo)
+            .args(&["config", "--bool", "topgrade.ignore"])
+            .check_output()
+            .map(|output| output.trim() == "true")
+            .unwrap_or(false)
+    }
+
     pub fn get_repo_root<P: AsRef<Path>>(&self, path: P) -> Option<String> {
         match path.as_ref().canonicalize() {
             Ok(mut path) => {
@@ -180,12 +381,12 @@ impl Git {
 
         None
     }
-    pub fn multi_pull_step(&self, repositories: &Repositories, ctx: &ExecutionContext) -> Result<()> {
+    pub fn multi_pull_step(&self, title: &str, repositories: &Repositories, ctx: &ExecutionContext) -> Result<()> {
         if repositories.repositories.is_empty() {
             return Err(SkipStep(String::from("No repositories to pull")).into());
         }
 
-        print_separator("Git repositories");
+        print_separator(title);
         repositories
             .bad_patterns
             .iter()
@@ -195,6 +396,8 @@ impl Git {
 
     pub fn multi_pull(&self, repositories: &Repositories, ctx: &ExecutionContext) -> Result<()> {
         let git = self.git.as_ref().unwrap();
+        // The pulls don't run through an executor
+        executor::start_step()?;
 
         if let RunType::Dry = ctx.run_type() {
             repositories
@@ -205,7 +408,7 @@ impl Git {
             return Ok(());
         }
 
-        let futures_iterator = repositories
+        let repos = repositories
             .repositories
             .iter()
             .filter(|repo| match has_remotes(git, repo) {
@@ -219,19 +422,50 @@ impl Git {
                 }
                 _ => true, // repo has remotes or command to check for remotes has failed. proceed to pull anyway.
             })
-            .map(|repo| pull_repository(repo.clone(), git, ctx));
-
-        let stream_of_futures = if let Some(limit) = ctx.config().git_concurrency_limit() {
+            .collect::<Vec<_>>();
+        let total = repos.len();
+        let in_progress = Mutex::new(Vec::new());
+        let futures_iterator = repos
+            .into_iter()
+            .map(|repo| pull_with_progress(repo.clone(), repositories.options.get(repo), git, ctx, &in_progress));
+
+        let mut stream_of_futures = if let Some(limit) = ctx.config().git_concurrency_limit() {
             iter(futures_iterator).buffer_unordered(limit).boxed()
         } else {
             futures_iterator.collect::<FuturesUnordered<_>>().boxed()
         };
 
         let basic_rt = runtime::Runtime::new()?;
-        let results = basic_rt.block_on(async { stream_of_futures.collect::<Vec<Result<()>>>().await });
+        let mut results = Vec::new();
+        let (mut up_to_date, mut changed, mut failed) = (0, 0, 0);
+        print_progress(format!("Pulling {} repositories", total));
+        basic_rt.block_on(async {
+            while let Some((result, out)) = stream_of_futures.next().await {
+                clear_progress();
+                print!("{}", out);
+                match &result {
+                    Ok(PullOutcome::UpToDate) => up_to_date += 1,
+                    Ok(PullOutcome::Changed) => changed += 1,
+                    Err(_) => failed += 1,
+                }
+                results.push(result);
+
+                if let Some(current) = in_progress.lock().unwrap().first() {
+                    print_progress(format!("{}/{} pulled. Pulling {}", results.len(), total, current));
+                }
+            }
+        });
+        clear_progress();
+
+        println!(
+            "\n{} up-to-date, {} changed, {} failed",
+            style(up_to_date).green().bold(),
+            style(changed).yellow().bold(),
+            style(failed).red().bold()
+        );
 
         let error = results.into_iter().find(|r| r.is_err());
-        error.unwrap_or(Ok(()))
+        error.map(|e| e.map(|_| ())).unwrap_or(Ok(()))
     }
 }
 
@@ -246,21 +480,44 @@ impl<'a> Repositories<'a> {
         Self {
             git,
             repositories: HashSet::new(),
+            options: HashMap::new(),
             bad_patterns: Vec::new(),
+            exclude: Vec::new(),
             glob_match_options,
         }
     }
 
+    /// Never pull the repositories matching one of the given glob patterns, however they are inserted
+    pub fn set_exclude(&mut self, patterns: Option<&Vec<String>>) {
+        self.exclude = compile_patterns(patterns);
+    }
+
+    fn is_excluded(&self, repo: &str) -> bool {
+        let excluded = self
+            .exclude
+            .iter()
+            .any(|pattern| pattern.matches_with(repo, self.glob_match_options))
+            || self.git.is_ignored(repo);
+        if excluded {
+            debug!("Excluding git repository {}", repo);
+        }
+
+        excluded
+    }
+
     pub fn insert_if_repo<P: AsRef<Path>>(&mut self, path: P) -> bool {
         if let Some(repo) = self.git.get_repo_root(path) {
-            self.repositories.insert(repo);
+            if !self.is_excluded(&repo) {
+                self.repositories.insert(repo);
+            }
             true
         } else {
             false
         }
     }
 
-    pub fn glob_insert(&mut self, pattern: &str) {
+    pub fn glob_insert(&mut self, repo: &GitRepo) {
+        let pattern = repo.path();
         if let Ok(glob) = glob_with(pattern, self.glob_match_options) {
             let mut last_git_repo: Option<PathBuf> = None;
             for entry in glob {
@@ -276,7 +533,11 @@ impl<'a> Repositories<'a> {
                                 continue;
                             }
                         }
-                        if self.insert_if_repo(&path) {
+                        if let Some(root) = self.git.get_repo_root(&path) {
+                            if !self.is_excluded(&root) {
+                                self.repositories.insert(root.clone());
+                                self.options.insert(root, repo.clone());
+                            }
                             last_git_repo = Some(path);
                         }
                     }
@@ -294,6 +555,33 @@ impl<'a> Repositories<'a> {
         }
     }
 
+    /// Insert the repositories managed by ghq, except those matching one of the `exclude` patterns
+    pub fn ghq_insert(&mut self, exclude: Option<&Vec<String>>) -> Result<()> {
+        let ghq = which("ghq").ok_or_else(|| SkipStep(String::from("ghq is not installed")))?;
+        let output = Command::new(ghq).args(&["list", "-p"]).check_output()?;
+
+        let exclude = compile_patterns(exclude);
+
+        for repo in output.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            if exclude
+                .iter()
+                .any(|pattern| pattern.matches_with(repo, self.glob_match_options))
+            {
+                debug!("Excluding ghq repository {}", repo);
+                continue;
+            }
+
+            self.insert_if_repo(repo);
+        }
+
+        Ok(())
+    }
+
+    /// The roots of the repositories
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.repositories.iter().map(|repo| repo.as_str())
+    }
+
     #[cfg(unix)]
     pub fn is_empty(&self) -> bool {
         self.repositories.is_empty()
@@ -0,0 +1,260 @@
+//! Updates of language servers installed outside of package managers: from their releases, or with rustup.
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::Result;
+use serde::Deserialize;
+use tempfile::TempDir;
+
+use crate::config::Config;
+use crate::error::SkipStep;
+use crate::execution_context::ExecutionContext;
+use crate::executor::{CommandExt, Executor};
+use crate::terminal::print_separator;
+use crate::utils::{self, which};
+
+struct Server {
+    name: &'static str,
+    binary: &'static str,
+    enabled: fn(&Config) -> bool,
+    update: fn(&ExecutionContext, &Path) -> Result<()>,
+}
+
+const SERVERS: [Server; 3] = [
+    Server {
+        name: "rust-analyzer",
+        binary: "rust-analyzer",
+        enabled: Config::update_rust_analyzer,
+        update: update_rust_analyzer,
+    },
+    Server {
+        name: "clangd",
+        binary: "clangd",
+        enabled: Config::update_clangd,
+        update: update_clangd,
+    },
+    Server {
+        name: "lua-language-server",
+        binary: "lua-language-server",
+        enabled: Config::update_lua_language_server,
+        update: update_lua_language_server,
+    },
+];
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+}
+
+/// The tag of the latest release of a GitHub repository
+fn latest_release(repository: &str) -> Result<String> {
+    let curl = utils::require("curl")?;
+    let release = Command::new(curl)
+        .args(&[
+            "-fsSL",
+            &format!("https://api.github.com/repos/{}/releases/latest", repository),
+        ])
+        .check_output()?;
+
+    Ok(serde_json::from_str::<Release>(&release)?.tag_name)
+}
+
+/// Whether the output of `--version` of the server mentions the version
+fn is_installed(binary: &Path, version: &str) -> bool {
+    Command::new(binary)
+        .arg("--version")
+        .check_output()
+        .map(|output| output.contains(version.trim_start_matches('v')))
+        .unwrap_or(false)
+}
+
+/// Download the file into a temporary directory, which is removed when it's dropped
+fn download(ctx: &ExecutionContext, url: &str) -> Result<(TempDir, PathBuf)> {
+    let curl = utils::require("curl")?;
+    let tempdir = tempfile::tempdir()?;
+    let download = tempdir.path().join(url.rsplit('/').next().unwrap());
+
+    ctx.run_type()
+        .execute(&curl)
+        .args(&["-fsSL", "-o"])
+        .arg(&download)
+        .arg(url)
+        .check_run()?;
+
+    Ok((tempdir, download))
+}
+
+/// Run the command as the current user if it writes inside the home directory, or elevated otherwise
+fn execute_for(ctx: &ExecutionContext, command: &Path, target: &Path) -> Result<Executor> {
+    if target.starts_with(ctx.base_dirs().home_dir()) {
+        Ok(ctx.run_type().execute(command))
+    } else {
+        ctx.execute_elevated(command, false)
+    }
+}
+
+fn update_rust_analyzer(ctx: &ExecutionContext, binary: &Path) -> Result<()> {
+    let canonical = binary.canonicalize().unwrap_or_else(|_| binary.to_owned());
+    // The rust-analyzer of rustup is a link to the rustup proxy, which fails if the component is missing
+    if canonical.file_stem() != Some(OsStr::new("rustup")) {
+        return Err(SkipStep(format!(
+            "{} wasn't installed by rustup. Update it with the tool which installed it",
+            binary.display()
+        ))
+        .into());
+    }
+
+    let rustup = utils::require("rustup")?;
+    ctx.run_type()
+        .execute(&rustup)
+        .args(&["component", "add", "rust-analyzer"])
+        .check_run()
+}
+
+fn update_clangd(ctx: &ExecutionContext, binary: &Path) -> Result<()> {
+    // The releases of clangd extract to a clangd_<version> directory
+    let canonical = binary.canonicalize().unwrap_or_else(|_| binary.to_owned());
+    let install_dir = canonical
+        .ancestors()
+        .find(|dir| {
+            dir.file_name()
+                .map(|name| name.to_string_lossy().starts_with("clangd_"))
+                .unwrap_or(false)
+        })
+        .ok_or_else(|| {
+            SkipStep(format!(
+                "{} isn't a release of clangd. Update it with the package manager which owns it",
+                binary.display()
+            ))
+        })?;
+    let releases_dir = install_dir.parent().unwrap();
+
+    let platform = if cfg!(target_os = "linux") {
+        "linux"
+    } else if cfg!(target_os = "macos") {
+        "mac"
+    } else {
+        "windows"
+    };
+
+    let version = latest_release("clangd/clangd")?;
+    if is_installed(binary, &version) {
+        println!("clangd {} is up to date", version);
+        return Ok(());
+    }
+
+    let (_tempdir, archive) = download(
+        ctx,
+        &format!(
+            "https://github.com/clangd/clangd/releases/download/{0}/clangd-{1}-{0}.zip",
+            version, platform
+        ),
+    )?;
+    let unzip = utils::require("unzip")?;
+    execute_for(ctx, &unzip, releases_dir)?
+        .args(&["-q", "-o"])
+        .arg(&archive)
+        .arg("-d")
+        .arg(releases_dir)
+        .check_run()?;
+
+    let new_binary = releases_dir
+        .join(format!("clangd_{}", version))
+        .join(canonical.strip_prefix(install_dir).unwrap());
+    if cfg!(unix) && binary.symlink_metadata()?.file_type().is_symlink() {
+        execute_for(ctx, Path::new("ln"), binary)?
+            .arg("-sfn")
+            .arg(&new_binary)
+            .arg(binary)
+            .check_run()
+    } else {
+        println!(
+            "clangd {} was installed to {}. Add it to PATH instead of {}",
+            version,
+            new_binary.parent().unwrap().display(),
+            canonical.parent().unwrap().display()
+        );
+        Ok(())
+    }
+}
+
+fn update_lua_language_server(ctx: &ExecutionContext, binary: &Path) -> Result<()> {
+    // Releases hold the server in bin/, next to the Lua scripts it runs
+    let canonical = binary.canonicalize().unwrap_or_else(|_| binary.to_owned());
+    let install_dir = canonical
+        .parent()
+        .and_then(Path::parent)
+        .filter(|dir| dir.join("main.lua").exists())
+        .ok_or_else(|| {
+            SkipStep(format!(
+                "{} isn't a release of lua-language-server. Update it with the package manager which owns it",
+                binary.display()
+            ))
+        })?;
+
+    let platform = if cfg!(target_os = "linux") {
+        "linux"
+    } else if cfg!(target_os = "macos") {
+        "darwin"
+    } else {
+        return Err(SkipStep(String::from(
+            "Topgrade can't install releases of lua-language-server on Windows",
+        ))
+        .into());
+    };
+    let arch = match std::env::consts::ARCH {
+        "aarch64" => "arm64",
+        _ => "x64",
+    };
+
+    let version = latest_release("LuaLS/lua-language-server")?;
+    if is_installed(binary, &version) {
+        println!("lua-language-server {} is up to date", version);
+        return Ok(());
+    }
+
+    let (_tempdir, archive) = download(
+        ctx,
+        &format!(
+            "https://github.com/LuaLS/lua-language-server/releases/download/{0}/lua-language-server-{0}-{1}-{2}.tar.gz",
+            version, platform, arch
+        ),
+    )?;
+    let tar = utils::require("tar")?;
+    execute_for(ctx, &tar, install_dir)?
+        .arg("-xzf")
+        .arg(&archive)
+        .arg("-C")
+        .arg(install_dir)
+        .check_run()
+}
+
+pub fn run_language_servers(ctx: &ExecutionContext) -> Result<()> {
+    let servers: Vec<(&Server, PathBuf)> = SERVERS
+        .iter()
+        .filter(|server| (server.enabled)(ctx.config()))
+        .filter_map(|server| which(server.binary).map(|binary| (server, binary)))
+        .collect();
+    if servers.is_empty() {
+        return Err(SkipStep(String::from("No language server is installed")).into());
+    }
+
+    print_separator("Language servers");
+
+    let mut updated = false;
+    for (server, binary) in servers {
+        println!("{}", server.name);
+        match (server.update)(ctx, &binary) {
+            Ok(()) => updated = true,
+            Err(e) if e.is::<SkipStep>() => println!("{}", e),
+            Err(e) => return Err(e),
+        }
+    }
+
+    if updated {
+        Ok(())
+    } else {
+        Err(SkipStep(String::from("No language server can be updated by Topgrade")).into())
+    }
+}
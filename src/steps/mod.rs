@@ -1,8 +1,14 @@
+pub mod aws;
+pub mod cleanup;
 pub mod containers;
 pub mod emacs;
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+pub mod firmware;
 pub mod generic;
 pub mod git;
 pub mod kakoune;
+pub mod language_servers;
+pub mod network_devices;
 pub mod node;
 pub mod os;
 pub mod powershell;
@@ -0,0 +1,291 @@
+//! Checks for updates of routers, firewalls and homelab services through their HTTP APIs.
+//!
+//! Updates are reported in the summary. Only Home Assistant updates can be installed, and only when asked to.
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use anyhow::{anyhow, Context, Result};
+use serde_json::{json, Value};
+
+use crate::config::{NetworkDevice, NetworkDeviceKind};
+use crate::execution_context::ExecutionContext;
+use crate::terminal::print_separator;
+use crate::utils;
+
+/// Pending update of a component of a device
+struct PendingUpdate {
+    component: String,
+    installed: String,
+    available: String,
+    /// The Home Assistant entity tracking the update
+    entity_id: Option<String>,
+}
+
+/// How requests to the API are authenticated
+enum Auth {
+    None,
+    Basic(String),
+    Bearer(String),
+    Header(String),
+}
+
+fn credentials(device: &NetworkDevice) -> Result<String> {
+    if let Some(variable) = device.credentials_env() {
+        return env::var(variable).with_context(|| format!("Environment variable {} is not set", variable));
+    }
+
+    if let Some(file) = device.credentials_file() {
+        let credentials =
+            fs::read_to_string(file).with_context(|| format!("Failed reading credentials from {}", file))?;
+        return Ok(credentials.trim().to_string());
+    }
+
+    Err(anyhow!("No credentials are configured for {}", device.url()))
+}
+
+/// Query the API of the device. The credentials are passed to curl through its standard input so that they don't
+/// show up in the process list.
+fn query(device: &NetworkDevice, method: &str, path: &str, auth: &Auth, body: Option<&Value>) -> Result<Value> {
+    let curl = utils::require("curl")?;
+    let url = format!("{}{}", device.url().trim_end_matches('/'), path);
+
+    let mut options = Vec::new();
+    match auth {
+        Auth::None => (),
+        Auth::Basic(user) => options.push(utils::curl_option("user", user)),
+        Auth::Bearer(token) => options.push(utils::curl_option(
+            "header",
+            &format!("Authorization: Bearer {}", token),
+        )),
+        Auth::Header(header) => options.push(utils::curl_option("header", header)),
+    }
+    if let Some(body) = body {
+        options.push(utils::curl_option("header", "Content-Type: application/json"));
+        options.push(utils::curl_option("data", &body.to_string()));
+    }
+
+    let mut command = Command::new(curl);
+    command.args(&[
+        "--silent",
+        "--show-error",
+        "--fail",
+        "--config",
+        "-",
+        "--request",
+        method,
+    ]);
+    if device.insecure() {
+        command.arg("--insecure");
+    }
+    let mut child = command
+        .arg(&url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    writeln!(child.stdin.take().unwrap(), "{}", options.join("\n"))?;
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Querying {} failed: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+fn string_at<'a>(value: &'a Value, pointer: &str) -> &'a str {
+    value.pointer(pointer).and_then(Value::as_str).unwrap_or("unknown")
+}
+
+fn check_opnsense(device: &NetworkDevice) -> Result<Vec<PendingUpdate>> {
+    let status = query(
+        device,
+        "GET",
+        "/api/core/firmware/status",
+        &Auth::Basic(credentials(device)?),
+        None,
+    )?;
+
+    if status.get("status").and_then(Value::as_str) != Some("update") {
+        return Ok(Vec::new());
+    }
+
+    Ok(vec![PendingUpdate {
+        component: String::from("OPNsense"),
+        installed: string_at(&status, "/product_version").to_string(),
+        available: string_at(&status, "/product_target").to_string(),
+        entity_id: None,
+    }])
+}
+
+fn check_pfsense(device: &NetworkDevice) -> Result<Vec<PendingUpdate>> {
+    // Requires the pfSense REST API package
+    let upgrade = query(
+        device,
+        "GET",
+        "/api/v1/system/version/upgrade",
+        &Auth::Basic(credentials(device)?),
+        None,
+    )?;
+
+    if string_at(&upgrade, "/data/pkg_version_compare") != "<" {
+        return Ok(Vec::new());
+    }
+
+    Ok(vec![PendingUpdate {
+        component: String::from("pfSense"),
+        installed: string_at(&upgrade, "/data/installed_version").to_string(),
+        available: string_at(&upgrade, "/data/version").to_string(),
+        entity_id: None,
+    }])
+}
+
+fn check_routeros(device: &NetworkDevice) -> Result<Vec<PendingUpdate>> {
+    let check = query(
+        device,
+        "POST",
+        "/rest/system/package/update/check-for-updates",
+        &Auth::Basic(credentials(device)?),
+        None,
+    )?;
+    // The command reports its progress as a list, of which the last entry holds the result
+    let result = match &check {
+        Value::Array(entries) => entries.last().cloned().unwrap_or(Value::Null),
+        other => other.clone(),
+    };
+
+    let installed = string_at(&result, "/installed-version");
+    let latest = string_at(&result, "/latest-version");
+    if latest == "unknown" || installed == latest {
+        return Ok(Vec::new());
+    }
+
+    Ok(vec![PendingUpdate {
+        component: String::from("RouterOS"),
+        installed: installed.to_string(),
+        available: latest.to_string(),
+        entity_id: None,
+    }])
+}
+
+/// Home Assistant tracks the updates of the core, the OS, the add-ons and some integrations as `update` entities
+fn check_home_assistant(device: &NetworkDevice) -> Result<Vec<PendingUpdate>> {
+    let states = query(device, "GET", "/api/states", &Auth::Bearer(credentials(device)?), None)?;
+
+    Ok(states
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|state| string_at(state, "/entity_id").starts_with("update."))
+        .filter(|state| string_at(state, "/state") == "on")
+        .map(|state| PendingUpdate {
+            component: string_at(state, "/attributes/title").to_string(),
+            installed: string_at(state, "/attributes/installed_version").to_string(),
+            available: string_at(state, "/attributes/latest_version").to_string(),
+            entity_id: Some(string_at(state, "/entity_id").to_string()),
+        })
+        .collect())
+}
+
+fn install_home_assistant_update(ctx: &ExecutionContext, device: &NetworkDevice, update: &PendingUpdate) -> Result<()> {
+    let entity_id = update.entity_id.as_deref().unwrap();
+
+    if ctx.run_type().dry() {
+        println!("Would install {} on {}", update.component, device.url());
+        return Ok(());
+    }
+
+    println!("Installing {} {}", update.component, update.available);
+    query(
+        device,
+        "POST",
+        "/api/services/update/install",
+        &Auth::Bearer(credentials(device)?),
+        Some(&json!({ "entity_id": entity_id })),
+    )?;
+
+    Ok(())
+}
+
+/// Pi-hole v6 authenticates with the application password for a session
+fn check_pihole(device: &NetworkDevice) -> Result<Vec<PendingUpdate>> {
+    let auth = query(
+        device,
+        "POST",
+        "/api/auth",
+        &Auth::None,
+        Some(&json!({ "password": credentials(device)? })),
+    )?;
+    let sid = auth
+        .pointer("/session/sid")
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("Pi-hole at {} did not grant a session", device.url()))?;
+    let session = Auth::Header(format!("X-FTL-SID: {}", sid));
+
+    let versions = query(device, "GET", "/api/info/version", &session, None);
+    // Sessions are limited, so the session is closed even if querying the versions failed
+    query(device, "DELETE", "/api/auth", &session, None).ok();
+    let versions = versions?;
+
+    Ok(["core", "web", "ftl"]
+        .iter()
+        .filter_map(|component| {
+            let installed = string_at(&versions, &format!("/version/{}/local/version", component));
+            let available = string_at(&versions, &format!("/version/{}/remote/version", component));
+            if available == "unknown" || installed == available {
+                return None;
+            }
+
+            Some(PendingUpdate {
+                component: format!("Pi-hole {}", component),
+                installed: installed.to_string(),
+                available: available.to_string(),
+                entity_id: None,
+            })
+        })
+        .collect())
+}
+
+pub fn check_network_device(ctx: &ExecutionContext, device: &NetworkDevice) -> Result<()> {
+    print_separator(format!("Updates of {}", device.url()));
+
+    let updates = match device.kind() {
+        NetworkDeviceKind::Opnsense => check_opnsense(device),
+        NetworkDeviceKind::Pfsense => check_pfsense(device),
+        NetworkDeviceKind::Routeros => check_routeros(device),
+        NetworkDeviceKind::Homeassistant => check_home_assistant(device),
+        NetworkDeviceKind::Pihole => check_pihole(device),
+    }?;
+
+    if updates.is_empty() {
+        println!("{} is up to date", device.url());
+        return Ok(());
+    }
+
+    for update in &updates {
+        if device.install() && update.entity_id.is_some() {
+            install_home_assistant_update(ctx, device, update)?;
+            ctx.report_changes(!ctx.run_type().dry());
+            continue;
+        }
+
+        let message = format!(
+            "{} has an update of {} available: {} -> {}",
+            device.url(),
+            update.component,
+            update.installed,
+            update.available
+        );
+        println!("{}", message);
+        ctx.add_summary_note(message);
+    }
+
+    Ok(())
+}
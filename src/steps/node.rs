@@ -49,7 +49,7 @@ impl NPM {
         Version::parse(&version_str?).map_err(|err| err.into())
     }
 
-    fn upgrade(&self, run_type: RunType, use_sudo: bool) -> Result<()> {
+    fn upgrade(&self, ctx: &ExecutionContext, use_sudo: bool) -> Result<()> {
         print_separator("Node Package Manager");
         let version = self.version()?;
         let args = if version < Version::new(8, 11, 0) {
@@ -58,9 +58,9 @@ impl NPM {
             ["update", "--location=global"]
         };
         if use_sudo {
-            run_type.execute("sudo").args(args).check_run()?;
+            ctx.execute_elevated(&self.command, false)?.args(args).check_run()?;
         } else {
-            run_type.execute(&self.command).args(args).check_run()?;
+            ctx.run_type().execute(&self.command).args(args).check_run()?;
         }
 
         Ok(())
@@ -99,12 +99,12 @@ pub fn run_npm_upgrade(ctx: &ExecutionContext) -> Result<()> {
 
     #[cfg(target_os = "linux")]
     {
-        npm.upgrade(ctx.run_type(), should_use_sudo(&npm, ctx)?)
+        npm.upgrade(ctx, should_use_sudo(&npm, ctx)?)
     }
 
     #[cfg(not(target_os = "linux"))]
     {
-        npm.upgrade(ctx.run_type(), false)
+        npm.upgrade(ctx, false)
     }
 }
 
@@ -8,22 +8,41 @@ use walkdir::WalkDir;
 
 use crate::error::TopgradeError;
 use crate::execution_context::ExecutionContext;
+use crate::executor::{CommandExt, Executor};
+use crate::steps::os::locks::{wait_for_package_manager, PackageManager};
+use crate::terminal::print_warning;
 use crate::utils::which;
 use crate::{config, Step};
 
+// Printed by pacman and the AUR helpers wrapping it when the system is up to date
+const NOTHING_TO_DO: &str = "there is nothing to do";
+// Printed by Aura when packages of the AUR are up to date
+const NO_AUR_UPGRADES: &str = "No AUR package upgrades necessary";
+
 fn get_execution_path() -> OsString {
     let mut path = OsString::from("/usr/bin:");
     path.push(var_os("PATH").unwrap());
     path
 }
 
+/// Ask the helper to rebuild development packages if they're enabled
+fn add_devel(command: &mut Executor, devel: bool) {
+    if devel {
+        command.arg("--devel");
+    }
+}
+
 pub trait ArchPackageManager {
     fn upgrade(&self, ctx: &ExecutionContext) -> Result<()>;
+
+    /// Clean the package cache
+    fn cleanup(&self, ctx: &ExecutionContext) -> Result<()>;
 }
 
 pub struct YayParu {
     executable: PathBuf,
     pacman: PathBuf,
+    paru: bool,
 }
 
 impl ArchPackageManager for YayParu {
@@ -42,17 +61,30 @@ impl ArchPackageManager for YayParu {
             .arg("--pacman")
             .arg(&self.pacman)
             .arg("-Syu")
-            .args(ctx.config().yay_arguments().split_whitespace())
             .env("PATH", get_execution_path());
+        add_devel(&mut command, ctx.config().yay_devel(self.paru));
+        if self.paru {
+            command.args(ctx.config().paru_arguments().split_whitespace());
+        } else {
+            command.args(ctx.config().yay_arguments().split_whitespace());
+        }
 
         if ctx.config().yes(Step::System) {
             command.arg("--noconfirm");
         }
-        command.check_run()?;
+        let changed = command.check_run_detect_changes(&[NOTHING_TO_DO])?;
+        ctx.report_changes(changed);
 
-        if ctx.config().cleanup() {
+        Ok(())
+    }
+
+    fn cleanup(&self, ctx: &ExecutionContext) -> Result<()> {
+        // Both clean the package cache along with their clones of the AUR, and remove the dependencies which
+        // aren't needed anymore, with different flags
+        let remove_unneeded = if self.paru { "-c" } else { "-Yc" };
+        for operation in ["-Scc", remove_unneeded] {
             let mut command = ctx.run_type().execute(&self.executable);
-            command.arg("--pacman").arg(&self.pacman).arg("-Scc");
+            command.arg("--pacman").arg(&self.pacman).arg(operation);
             if ctx.config().yes(Step::System) {
                 command.arg("--noconfirm");
             }
@@ -68,6 +100,7 @@ impl YayParu {
         Some(Self {
             executable: which(exec_name)?,
             pacman: pacman.to_owned(),
+            paru: exec_name == "paru",
         })
     }
 }
@@ -80,27 +113,27 @@ impl ArchPackageManager for Trizen {
     fn upgrade(&self, ctx: &ExecutionContext) -> Result<()> {
         let mut command = ctx.run_type().execute(&self.executable);
 
-        command
-            .arg("-Syu")
-            .args(ctx.config().trizen_arguments().split_whitespace())
-            .env("PATH", get_execution_path());
+        command.arg("-Syu").env("PATH", get_execution_path());
+        add_devel(&mut command, ctx.config().aur_devel());
+        command.args(ctx.config().trizen_arguments().split_whitespace());
 
         if ctx.config().yes(Step::System) {
             command.arg("--noconfirm");
         }
-        command.check_run()?;
-
-        if ctx.config().cleanup() {
-            let mut command = ctx.run_type().execute(&self.executable);
-            command.arg("-Sc");
-            if ctx.config().yes(Step::System) {
-                command.arg("--noconfirm");
-            }
-            command.check_run()?;
-        }
+        let changed = command.check_run_detect_changes(&[NOTHING_TO_DO])?;
+        ctx.report_changes(changed);
 
         Ok(())
     }
+
+    fn cleanup(&self, ctx: &ExecutionContext) -> Result<()> {
+        let mut command = ctx.run_type().execute(&self.executable);
+        command.arg("-Sc");
+        if ctx.config().yes(Step::System) {
+            command.arg("--noconfirm");
+        }
+        command.check_run()
+    }
 }
 
 impl Trizen {
@@ -112,13 +145,12 @@ impl Trizen {
 }
 
 pub struct Pacman {
-    sudo: PathBuf,
     executable: PathBuf,
 }
 
 impl ArchPackageManager for Pacman {
     fn upgrade(&self, ctx: &ExecutionContext) -> Result<()> {
-        let mut command = ctx.run_type().execute(&self.sudo);
+        let mut command = ctx.run_type().execute(require_sudo(ctx)?);
         command
             .arg(&self.executable)
             .arg("-Syu")
@@ -126,27 +158,39 @@ impl ArchPackageManager for Pacman {
         if ctx.config().yes(Step::System) {
             command.arg("--noconfirm");
         }
-        command.check_run()?;
-
-        if ctx.config().cleanup() {
-            let mut command = ctx.run_type().execute(&self.sudo);
-            command.arg(&self.executable).arg("-Scc");
-            if ctx.config().yes(Step::System) {
-                command.arg("--noconfirm");
-            }
-            command.check_run()?;
-        }
+        let changed = command.check_run_detect_changes(&[NOTHING_TO_DO])?;
+        ctx.report_changes(changed);
 
         Ok(())
     }
+
+    fn cleanup(&self, ctx: &ExecutionContext) -> Result<()> {
+        let mut command = ctx.run_type().execute(require_sudo(ctx)?);
+        command.arg(&self.executable).arg("-Scc");
+        if ctx.config().yes(Step::System) {
+            command.arg("--noconfirm");
+        }
+        command.check_run()
+    }
 }
 
 impl Pacman {
-    pub fn get(ctx: &ExecutionContext) -> Option<Self> {
-        Some(Self {
+    pub fn get() -> Self {
+        Self {
             executable: which("powerpill").unwrap_or_else(|| PathBuf::from("pacman")),
-            sudo: ctx.sudo().to_owned()?,
-        })
+        }
+    }
+}
+
+/// Pacman, which Aura runs as well, needs sudo unlike the AUR helpers which elevate by themselves. The step is
+/// skipped without it, with a warning since the system isn't upgraded then.
+fn require_sudo<'a>(ctx: &'a ExecutionContext) -> Result<&'a PathBuf> {
+    match ctx.require_sudo() {
+        Ok(sudo) => Ok(sudo),
+        Err(e) => {
+            print_warning(format!("{}. Pacman can't run without it", e));
+            Err(e)
+        }
     }
 }
 
@@ -166,28 +210,103 @@ impl ArchPackageManager for Pikaur {
     fn upgrade(&self, ctx: &ExecutionContext) -> Result<()> {
         let mut command = ctx.run_type().execute(&self.executable);
 
+        command.arg("-Syu").env("PATH", get_execution_path());
+        add_devel(&mut command, ctx.config().aur_devel());
+        command.args(ctx.config().pikaur_arguments().split_whitespace());
+
+        if ctx.config().yes(Step::System) {
+            command.arg("--noconfirm");
+        }
+
+        let changed = command.check_run_detect_changes(&[NOTHING_TO_DO])?;
+        ctx.report_changes(changed);
+
+        Ok(())
+    }
+
+    fn cleanup(&self, ctx: &ExecutionContext) -> Result<()> {
+        let mut command = ctx.run_type().execute(&self.executable);
+        command.arg("-Sc");
+        if ctx.config().yes(Step::System) {
+            command.arg("--noconfirm");
+        }
+        command.check_run()
+    }
+}
+
+pub struct Aura {
+    sudo: PathBuf,
+    executable: PathBuf,
+}
+
+impl Aura {
+    fn get(ctx: &ExecutionContext) -> Option<Self> {
+        Some(Self {
+            executable: which("aura")?,
+            sudo: ctx.sudo().to_owned()?,
+        })
+    }
+
+    /// Aura elevates by itself since version 4, and refuses to build packages of the AUR as root
+    fn elevates_itself(&self) -> bool {
+        Command::new(&self.executable)
+            .arg("--version")
+            .check_output()
+            .ok()
+            .and_then(|output| {
+                output
+                    .split(|c: char| !c.is_ascii_digit())
+                    .find(|part| !part.is_empty())
+                    .and_then(|major| major.parse::<u32>().ok())
+            })
+            .map(|major| major >= 4)
+            .unwrap_or(true)
+    }
+
+    fn execute(&self, ctx: &ExecutionContext) -> Executor {
+        if self.elevates_itself() {
+            ctx.run_type().execute(&self.executable)
+        } else {
+            let mut command = ctx.run_type().execute(&self.sudo);
+            command.arg(&self.executable);
+            command
+        }
+    }
+}
+
+impl ArchPackageManager for Aura {
+    fn upgrade(&self, ctx: &ExecutionContext) -> Result<()> {
+        // Packages of the repositories and of the AUR are upgraded separately
+        let mut command = ctx.run_type().execute(require_sudo(ctx)?);
         command
+            .arg(&self.executable)
             .arg("-Syu")
-            .args(ctx.config().pikaur_arguments().split_whitespace())
             .env("PATH", get_execution_path());
-
         if ctx.config().yes(Step::System) {
             command.arg("--noconfirm");
         }
+        let mut changed = command.check_run_detect_changes(&[NOTHING_TO_DO])?;
 
-        command.check_run()?;
-
-        if ctx.config().cleanup() {
-            let mut command = ctx.run_type().execute(&self.executable);
-            command.arg("-Sc");
-            if ctx.config().yes(Step::System) {
-                command.arg("--noconfirm");
-            }
-            command.check_run()?;
+        let mut command = self.execute(ctx);
+        command.arg("-Au").env("PATH", get_execution_path());
+        command.args(ctx.config().aura_arguments().split_whitespace());
+        if ctx.config().yes(Step::System) {
+            command.arg("--noconfirm");
         }
+        changed |= command.check_run_detect_changes(&[NO_AUR_UPGRADES])?;
+        ctx.report_changes(changed);
 
         Ok(())
     }
+
+    fn cleanup(&self, ctx: &ExecutionContext) -> Result<()> {
+        let mut command = ctx.run_type().execute(require_sudo(ctx)?);
+        command.arg(&self.executable).arg("-Sc");
+        if ctx.config().yes(Step::System) {
+            command.arg("--noconfirm");
+        }
+        command.check_run()
+    }
 }
 
 pub struct Pamac {
@@ -205,10 +324,12 @@ impl ArchPackageManager for Pamac {
     fn upgrade(&self, ctx: &ExecutionContext) -> Result<()> {
         let mut command = ctx.run_type().execute(&self.executable);
 
-        command
-            .arg("upgrade")
-            .args(ctx.config().pamac_arguments().split_whitespace())
-            .env("PATH", get_execution_path());
+        command.arg("upgrade").env("PATH", get_execution_path());
+        if ctx.config().pamac_aur() {
+            command.arg("--aur");
+        }
+        add_devel(&mut command, ctx.config().aur_devel());
+        command.args(ctx.config().pamac_arguments().split_whitespace());
 
         if ctx.config().yes(Step::System) {
             command.arg("--no-confirm");
@@ -216,17 +337,17 @@ impl ArchPackageManager for Pamac {
 
         command.check_run()?;
 
-        if ctx.config().cleanup() {
-            let mut command = ctx.run_type().execute(&self.executable);
-            command.arg("clean");
-            if ctx.config().yes(Step::System) {
-                command.arg("--no-confirm");
-            }
-            command.check_run()?;
-        }
-
         Ok(())
     }
+
+    fn cleanup(&self, ctx: &ExecutionContext) -> Result<()> {
+        let mut command = ctx.run_type().execute(&self.executable);
+        command.arg("clean");
+        if ctx.config().yes(Step::System) {
+            command.arg("--no-confirm");
+        }
+        command.check_run()
+    }
 }
 
 fn box_package_manager<P: 'static + ArchPackageManager>(package_manager: P) -> Box<dyn ArchPackageManager> {
@@ -242,13 +363,15 @@ pub fn get_arch_package_manager(ctx: &ExecutionContext) -> Option<Box<dyn ArchPa
             .or_else(|| YayParu::get("yay", &pacman).map(box_package_manager))
             .or_else(|| Trizen::get().map(box_package_manager))
             .or_else(|| Pikaur::get().map(box_package_manager))
+            .or_else(|| Aura::get(ctx).map(box_package_manager))
             .or_else(|| Pamac::get().map(box_package_manager))
-            .or_else(|| Pacman::get(ctx).map(box_package_manager)),
+            .or_else(|| Some(box_package_manager(Pacman::get()))),
         config::ArchPackageManager::Trizen => Trizen::get().map(box_package_manager),
         config::ArchPackageManager::Paru => YayParu::get("paru", &pacman).map(box_package_manager),
         config::ArchPackageManager::Yay => YayParu::get("yay", &pacman).map(box_package_manager),
-        config::ArchPackageManager::Pacman => Pacman::get(ctx).map(box_package_manager),
+        config::ArchPackageManager::Pacman => Some(box_package_manager(Pacman::get())),
         config::ArchPackageManager::Pikaur => Pikaur::get().map(box_package_manager),
+        config::ArchPackageManager::Aura => Aura::get(ctx).map(box_package_manager),
         config::ArchPackageManager::Pamac => Pamac::get().map(box_package_manager),
     }
 }
@@ -256,9 +379,16 @@ pub fn get_arch_package_manager(ctx: &ExecutionContext) -> Option<Box<dyn ArchPa
 pub fn upgrade_arch_linux(ctx: &ExecutionContext) -> Result<()> {
     let package_manager =
         get_arch_package_manager(ctx).ok_or_else(|| anyhow::Error::from(TopgradeError::FailedGettingPackageManager))?;
+    wait_for_package_manager(ctx, &PackageManager::Pacman)?;
     package_manager.upgrade(ctx)
 }
 
+pub fn cleanup_arch_linux(ctx: &ExecutionContext) -> Result<()> {
+    let package_manager =
+        get_arch_package_manager(ctx).ok_or_else(|| anyhow::Error::from(TopgradeError::FailedGettingPackageManager))?;
+    package_manager.cleanup(ctx)
+}
+
 pub fn show_pacnew() {
     let mut iter = WalkDir::new("/etc")
         .into_iter()
@@ -1,19 +1,27 @@
+use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use ini::Ini;
 use log::{debug, warn};
+use walkdir::WalkDir;
 
+use crate::bandwidth;
+use crate::config::AptTool;
 use crate::error::{SkipStep, TopgradeError};
 use crate::execution_context::ExecutionContext;
 use crate::executor::{CommandExt, RunType};
-use crate::steps::os::archlinux;
+use crate::steps::os::locks::{wait_for_package_manager, PackageManager};
+use crate::steps::os::{archlinux, unix};
 use crate::terminal::{print_separator, print_warning};
 use crate::utils::{require, require_option, which, PathExt};
 use crate::Step;
 
 static OS_RELEASE_PATH: &str = "/etc/os-release";
+static FLATPAK_NOTHING_TO_DO: &str = "Nothing to do.";
+static NIXOS_BIN: &str = "/run/current-system/sw/bin";
+static NIXOS_SYSTEM_PROFILE: &str = "/nix/var/nix/profiles/system";
 
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -108,6 +116,19 @@ impl Distribution {
         }
     }
 
+    /// Clean the caches of the package manager and remove unneeded packages.
+    ///
+    /// Distributions missing here clean up as part of their upgrade.
+    pub fn cleanup(self, ctx: &ExecutionContext) -> Result<()> {
+        match self {
+            Distribution::Arch => archlinux::cleanup_arch_linux(ctx),
+            Distribution::Debian => cleanup_debian(ctx),
+            Distribution::Gentoo => cleanup_gentoo(ctx),
+            Distribution::Void => cleanup_void(ctx),
+            _ => Err(SkipStep(format!("{:?} cleans up during the system update", self)).into()),
+        }
+    }
+
     pub fn show_summary(self) {
         if let Distribution::Arch = self {
             archlinux::show_pacnew();
@@ -120,7 +141,7 @@ impl Distribution {
 }
 
 fn update_bedrock(ctx: &ExecutionContext) -> Result<()> {
-    let sudo = require_option(ctx.sudo().as_ref(), String::from("Sudo required"))?;
+    let sudo = ctx.require_sudo()?;
 
     ctx.run_type().execute(sudo).args(&["brl", "update"]);
 
@@ -144,7 +165,7 @@ fn update_bedrock(ctx: &ExecutionContext) -> Result<()> {
     Ok(())
 }
 
-fn is_wsl() -> Result<bool> {
+pub fn is_wsl() -> Result<bool> {
     let output = Command::new("uname").arg("-r").check_output()?;
     debug!("Uname output: {}", output);
     Ok(output.contains("microsoft"))
@@ -152,7 +173,7 @@ fn is_wsl() -> Result<bool> {
 
 fn upgrade_alpine_linux(ctx: &ExecutionContext) -> Result<()> {
     let apk = require("apk")?;
-    let sudo = ctx.sudo().as_ref().unwrap();
+    let sudo = ctx.require_sudo()?;
 
     ctx.run_type().execute(sudo).arg(&apk).arg("update").check_run()?;
     ctx.run_type().execute(sudo).arg(&apk).arg("upgrade").check_run()
@@ -167,163 +188,401 @@ fn upgrade_redhat(ctx: &ExecutionContext) -> Result<()> {
         }
     };
 
-    if let Some(sudo) = &ctx.sudo() {
-        let mut command = ctx.run_type().execute(&sudo);
-        command
-            .arg(which("dnf").unwrap_or_else(|| Path::new("yum").to_path_buf()))
-            .arg(if ctx.config().redhat_distro_sync() {
-                "distro-sync"
-            } else {
-                "upgrade"
-            });
+    let sudo = ctx.require_sudo()?;
+    wait_for_package_manager(ctx, &PackageManager::Dnf)?;
+
+    // Newer Fedora releases link dnf to dnf5, which dropped modularity
+    let dnf = which("dnf5").or_else(|| which("dnf"));
+    let is_dnf5 = dnf
+        .as_ref()
+        .and_then(|dnf| dnf.canonicalize().ok())
+        .map(|dnf| dnf.file_name() == Some(OsStr::new("dnf5")))
+        .unwrap_or(false);
+
+    let mut command = ctx.run_type().execute(&sudo);
+    command
+        .arg(dnf.as_deref().unwrap_or_else(|| Path::new("yum")))
+        .arg(if ctx.config().redhat_distro_sync() {
+            "distro-sync"
+        } else {
+            "upgrade"
+        });
 
-        if let Some(args) = ctx.config().dnf_arguments() {
-            command.args(args.split_whitespace());
-        }
+    if ctx.config().dnf_refresh() {
+        command.arg("--refresh");
+    }
 
-        if ctx.config().yes(Step::System) {
-            command.arg("-y");
-        }
+    if let Some(args) = ctx.config().dnf_arguments() {
+        command.args(args.split_whitespace());
+    }
 
-        command.check_run()?;
-    } else {
-        print_warning("No sudo detected. Skipping system upgrade");
+    if ctx.config().yes(Step::System) {
+        command.arg("-y");
+    }
+
+    let changed = command.check_run_detect_changes(&["Nothing to do.", "No packages marked for update"])?;
+    ctx.report_changes(changed);
+
+    if let Some(dnf) = dnf.filter(|_| !is_dnf5) {
+        report_module_streams(ctx, &dnf);
     }
 
     Ok(())
 }
 
-fn upgrade_bedrock_strata(ctx: &ExecutionContext) -> Result<()> {
-    if let Some(sudo) = ctx.sudo() {
-        ctx.run_type().execute(&sudo).args(&["brl", "update"]).check_run()?;
-    } else {
-        print_warning("No sudo detected. Skipping system upgrade");
+/// Mention the modules whose enabled stream isn't the default one anymore in the summary. Upgrades never switch
+/// the stream of a module.
+fn report_module_streams(ctx: &ExecutionContext, dnf: &Path) {
+    // The metadata is fresh after the upgrade, so the cache is enough
+    let modules = match Command::new(dnf).args(&["-C", "module", "list"]).check_output() {
+        Ok(modules) => modules,
+        Err(e) => {
+            debug!("Failed to list the modules: {}", e);
+            return;
+        }
+    };
+
+    for (name, stream, default) in outdated_module_streams(&modules) {
+        ctx.add_summary_note(format!(
+            "Module {0} is on stream {1}, but the default is {2} now. Switch with `dnf module switch-to {0}:{2}`",
+            name, stream, default
+        ));
+    }
+}
+
+/// The enabled modules of the output of `dnf module list` whose stream isn't the default one, with their stream and
+/// the default one
+fn outdated_module_streams(modules: &str) -> Vec<(&str, &str, &str)> {
+    let mut enabled = Vec::new();
+    let mut defaults = Vec::new();
+    for line in modules.lines() {
+        // Modules are listed as `name stream [d][e] profiles summary`
+        let columns: Vec<&str> = line.split_whitespace().collect();
+        let (name, stream, flags) = match columns[..] {
+            [name, stream, flags, ..] if flags.starts_with('[') && flags.ends_with(']') => (name, stream, flags),
+            _ => continue,
+        };
+        if flags.contains("[e]") {
+            enabled.push((name, stream));
+        }
+        if flags.contains("[d]") {
+            defaults.push((name, stream));
+        }
     }
 
+    enabled
+        .into_iter()
+        .filter_map(|(name, stream)| {
+            defaults
+                .iter()
+                .find(|(module, default)| *module == name && *default != stream)
+                .map(|(_, default)| (name, stream, *default))
+        })
+        .collect()
+}
+
+fn upgrade_bedrock_strata(ctx: &ExecutionContext) -> Result<()> {
+    let sudo = ctx.require_sudo()?;
+
+    ctx.run_type().execute(&sudo).args(&["brl", "update"]).check_run()?;
+
     Ok(())
 }
 
 fn upgrade_suse(ctx: &ExecutionContext) -> Result<()> {
-    if let Some(sudo) = ctx.sudo() {
-        ctx.run_type().execute(&sudo).args(&["zypper", "refresh"]).check_run()?;
+    let sudo = ctx.require_sudo()?;
 
-        ctx.run_type()
-            .execute(&sudo)
-            .args(&["zypper", "dist-upgrade"])
-            .check_run()?;
-    } else {
-        print_warning("No sudo detected. Skipping system upgrade");
+    // The root of transactional systems is read-only, so they're upgraded in a new snapshot which boots next time
+    if let Some(transactional_update) = which("transactional-update").filter(|_| ctx.config().transactional_update()) {
+        let mut command = ctx.run_type().execute(&sudo);
+        command.arg(transactional_update);
+        if ctx.config().yes(Step::System) {
+            command.arg("--non-interactive");
+        }
+        command.arg("dup").check_run()?;
+
+        if Path::new("/run/reboot-needed").exists() {
+            ctx.report_reboot_required(String::from("The upgraded snapshot is used after a reboot"));
+        }
+        return Ok(());
     }
 
+    ctx.run_type().execute(&sudo).args(&["zypper", "refresh"]).check_run()?;
+
+    ctx.run_type()
+        .execute(&sudo)
+        .args(&["zypper", "dist-upgrade"])
+        .check_run()?;
+
     Ok(())
 }
 
 fn upgrade_void(ctx: &ExecutionContext) -> Result<()> {
-    if let Some(sudo) = ctx.sudo() {
-        let mut command = ctx.run_type().execute(&sudo);
-        command.args(&["xbps-install", "-Su", "xbps"]);
-        if ctx.config().yes(Step::System) {
-            command.arg("-y");
-        }
-        command.check_run()?;
+    let sudo = ctx.require_sudo()?;
 
+    let arguments: Vec<&str> = ctx
+        .config()
+        .xbps_arguments()
+        .map(|arguments| arguments.split_whitespace().collect())
+        .unwrap_or_default();
+
+    // xbps has to be updated by itself before the other packages
+    for packages in [&["-Su", "xbps"][..], &["-u"][..]] {
         let mut command = ctx.run_type().execute(&sudo);
-        command.args(&["xbps-install", "-u"]);
+        command.arg("xbps-install").args(packages).args(&arguments);
         if ctx.config().yes(Step::System) {
             command.arg("-y");
         }
         command.check_run()?;
-    } else {
-        print_warning("No sudo detected. Skipping system upgrade");
     }
 
     Ok(())
 }
 
+fn cleanup_void(ctx: &ExecutionContext) -> Result<()> {
+    let sudo = ctx.require_sudo()?;
+
+    // Remove the orphaned packages and the obsolete packages in the cache
+    let mut command = ctx.run_type().execute(&sudo);
+    command.args(&["xbps-remove", "-Oo"]);
+    if ctx.config().yes(Step::System) {
+        command.arg("-y");
+    }
+    command.check_run()
+}
+
 fn upgrade_gentoo(ctx: &ExecutionContext) -> Result<()> {
     let run_type = ctx.run_type();
 
-    if let Some(sudo) = &ctx.sudo() {
-        if let Some(layman) = which("layman") {
-            run_type.execute(&sudo).arg(layman).args(&["-s", "ALL"]).check_run()?;
-        }
+    let sudo = ctx.require_sudo()?;
+
+    if let Some(layman) = which("layman") {
+        run_type.execute(&sudo).arg(layman).args(&["-s", "ALL"]).check_run()?;
+    }
+
+    println!("Syncing portage");
+    run_type
+        .execute(&sudo)
+        .args(&["emerge", "--sync"])
+        .args(
+            ctx.config()
+                .emerge_sync_flags()
+                .map(|s| s.split_whitespace().collect())
+                .unwrap_or_else(|| vec!["-q"]),
+        )
+        .check_run()?;
 
-        println!("Syncing portage");
+    if let Some(eix_update) = which("eix-update") {
+        run_type.execute(&sudo).arg(eix_update).check_run()?;
+    }
+
+    run_type
+        .execute(&sudo)
+        .arg("emerge")
+        .args(
+            ctx.config()
+                .emerge_update_flags()
+                .map(|s| s.split_whitespace().collect())
+                .unwrap_or_else(|| vec!["-uDNa", "--with-bdeps=y", "world"]),
+        )
+        .check_run()?;
+
+    if ctx.config().emerge_preserved_rebuild() {
         run_type
             .execute(&sudo)
-            .args(&["emerge", "--sync"])
-            .args(
-                ctx.config()
-                    .emerge_sync_flags()
-                    .map(|s| s.split_whitespace().collect())
-                    .unwrap_or_else(|| vec!["-q"]),
-            )
+            .args(&["emerge", "-q", "@preserved-rebuild"])
             .check_run()?;
+    }
+
+    Ok(())
+}
 
-        if let Some(eix_update) = which("eix-update") {
-            run_type.execute(&sudo).arg(eix_update).check_run()?;
+fn cleanup_gentoo(ctx: &ExecutionContext) -> Result<()> {
+    let config = ctx.config();
+    if !config.emerge_depclean() && !config.eclean() {
+        return Err(SkipStep(String::from("Neither emerge_depclean nor eclean is enabled")).into());
+    }
+
+    let sudo = ctx.require_sudo()?;
+
+    if config.emerge_depclean() {
+        let mut command = ctx.run_type().execute(&sudo);
+        command.args(&["emerge", "--depclean"]);
+        // depclean removes packages after a delay unless it's asked to confirm
+        if !config.yes(Step::System) {
+            command.arg("--ask");
         }
+        command.check_run()?;
+    }
 
-        run_type
-            .execute(&sudo)
-            .arg("emerge")
-            .args(
-                ctx.config()
-                    .emerge_update_flags()
-                    .map(|s| s.split_whitespace().collect())
-                    .unwrap_or_else(|| vec!["-uDNa", "--with-bdeps=y", "world"]),
-            )
-            .check_run()?;
-    } else {
-        print_warning("No sudo detected. Skipping system upgrade");
+    if config.eclean() {
+        match (which("eclean-dist"), which("eclean-pkg")) {
+            (Some(eclean_dist), Some(eclean_pkg)) => {
+                // --deep keeps only the files of the installed versions
+                for eclean in [eclean_dist, eclean_pkg] {
+                    ctx.run_type().execute(&sudo).arg(eclean).arg("--deep").check_run()?;
+                }
+            }
+            _ => println!("eclean is part of gentoolkit, which isn't installed"),
+        }
     }
 
     Ok(())
 }
 
-fn upgrade_debian(ctx: &ExecutionContext) -> Result<()> {
-    if let Some(sudo) = &ctx.sudo() {
-        let apt = which("apt-fast")
-            .or_else(|| which("nala"))
-            .unwrap_or_else(|| PathBuf::from("apt-get"));
-
-        let is_nala = apt.ends_with("nala");
-        if !is_nala {
-            ctx.run_type().execute(&sudo).arg(&apt).arg("update").check_run()?;
+fn debian_package_manager(ctx: &ExecutionContext) -> Result<PathBuf> {
+    let tool = match ctx.config().apt_tool() {
+        AptTool::Autodetect => {
+            return Ok(which("apt-fast")
+                .or_else(|| which("nala"))
+                .unwrap_or_else(|| PathBuf::from("apt-get")))
         }
+        AptTool::Apt => "apt",
+        AptTool::AptGet => "apt-get",
+        AptTool::AptFast => "apt-fast",
+        AptTool::Nala => "nala",
+    };
 
-        let mut command = ctx.run_type().execute(&sudo);
-        command.arg(&apt);
-        if is_nala {
-            command.arg("upgrade");
-        } else {
-            command.arg("dist-upgrade");
-        };
-        if ctx.config().yes(Step::System) {
-            command.arg("-y");
+    // A missing binary would skip the step, which is easy to miss when the tool was chosen explicitly
+    which(tool).ok_or_else(|| {
+        let message = format!("apt_tool is set to {}, which isn't installed", tool);
+        print_warning(&message);
+        anyhow!(message)
+    })
+}
+
+fn upgrade_debian(ctx: &ExecutionContext) -> Result<()> {
+    let sudo = ctx.require_sudo()?;
+    let apt = debian_package_manager(ctx)?;
+    wait_for_package_manager(ctx, &PackageManager::Apt)?;
+
+    let is_nala = apt.ends_with("nala");
+    if is_nala && ctx.config().apt_phased_updates() {
+        print_warning("nala doesn't support apt_phased_updates. Phased updates are installed as they're rolled out");
+    }
+
+    // nala doesn't take options before its commands
+    let mut options: Vec<String> = Vec::new();
+    if !is_nala {
+        if let Some(limit) = ctx.config().network_limit(Step::System) {
+            let kilobytes = bandwidth::kilobytes(limit);
+            options.extend([
+                String::from("-o"),
+                format!("Acquire::http::Dl-Limit={}", kilobytes),
+                String::from("-o"),
+                format!("Acquire::https::Dl-Limit={}", kilobytes),
+            ]);
         }
-        if let Some(args) = ctx.config().apt_arguments() {
-            command.args(args.split_whitespace());
+        if ctx.config().apt_phased_updates() {
+            options.extend([
+                String::from("-o"),
+                String::from("APT::Get::Always-Include-Phased-Updates=true"),
+            ]);
         }
-        command.check_run()?;
+    }
 
-        if ctx.config().cleanup() {
-            ctx.run_type().execute(&sudo).arg(&apt).arg("clean").check_run()?;
+    if !is_nala {
+        ctx.run_type()
+            .execute(&sudo)
+            .arg(&apt)
+            .args(&options)
+            .arg("update")
+            .check_run()?;
+    }
 
-            let mut command = ctx.run_type().execute(&sudo);
-            command.arg(&apt).arg("autoremove");
-            if ctx.config().yes(Step::System) {
-                command.arg("-y");
-            }
-            command.check_run()?;
+    let dist_upgrade = ctx.config().apt_dist_upgrade();
+    let mut command = ctx.run_type().execute(&sudo);
+    command.arg(&apt).args(&options);
+    if is_nala {
+        command.arg("upgrade");
+        if !dist_upgrade {
+            command.arg("--no-full");
         }
+    } else if !dist_upgrade {
+        command.arg("upgrade");
+    } else if apt.ends_with("apt") {
+        command.arg("full-upgrade");
     } else {
-        print_warning("No sudo detected. Skipping system upgrade");
+        command.arg("dist-upgrade");
+    };
+    if ctx.config().yes(Step::System) {
+        command.arg("-y");
+    }
+    if let Some(args) = ctx.config().apt_arguments() {
+        command.args(args.split_whitespace());
     }
+    let changed = command.check_run_detect_changes(&[
+        "0 upgraded, 0 newly installed, 0 to remove",
+        "All packages are up to date.",
+    ])?;
+    ctx.report_changes(changed);
+
+    report_held_packages(ctx);
 
     Ok(())
 }
 
+/// Mention the packages held with `apt-mark hold` in the summary, since they're never upgraded
+fn report_held_packages(ctx: &ExecutionContext) {
+    let held = match which("apt-mark").map(|apt_mark| Command::new(apt_mark).arg("showhold").check_output()) {
+        Some(Ok(output)) => output,
+        _ => return,
+    };
+    let held: Vec<&str> = held.split_whitespace().collect();
+    if held.is_empty() {
+        return;
+    }
+
+    println!("Held packages: {}", held.join(", "));
+    ctx.add_summary_note(format!(
+        "{} package(s) are held and weren't upgraded: {}. Release them with `apt-mark unhold`",
+        held.len(),
+        held.join(", ")
+    ));
+}
+
+fn cleanup_debian(ctx: &ExecutionContext) -> Result<()> {
+    let sudo = ctx.require_sudo()?;
+    let apt = debian_package_manager(ctx)?;
+
+    ctx.run_type().execute(&sudo).arg(&apt).arg("clean").check_run()?;
+
+    let mut command = ctx.run_type().execute(&sudo);
+    command.arg(&apt).arg("autoremove");
+    if ctx.config().yes(Step::System) {
+        command.arg("-y");
+    }
+    command.check_run()
+}
+
+/// Restart the running systemd user units, so that they run the upgraded versions
+pub fn restart_user_units(ctx: &ExecutionContext, units: &[String]) -> Result<()> {
+    let systemctl = require("systemctl")?;
+
+    println!("Restarting {}", units.join(", "));
+    ctx.run_type()
+        .execute(&systemctl)
+        .args(&["--user", "try-restart"])
+        .args(units)
+        .check_run()
+}
+
+/// Remove old entries of the systemd journal according to `journalctl_vacuum_time`
+pub fn vacuum_journal(ctx: &ExecutionContext) -> Result<()> {
+    let journalctl = require("journalctl")?;
+    let time = require_option(
+        ctx.config().journalctl_vacuum_time(),
+        String::from("journalctl_vacuum_time isn't set"),
+    )?;
+    let sudo = ctx.require_sudo()?;
+
+    ctx.run_type()
+        .execute(&sudo)
+        .arg(&journalctl)
+        .arg(format!("--vacuum-time={}", time))
+        .check_run()
+}
+
 pub fn run_deb_get(ctx: &ExecutionContext) -> Result<()> {
     let deb_get = require("deb-get")?;
 
@@ -339,11 +598,9 @@ pub fn run_deb_get(ctx: &ExecutionContext) -> Result<()> {
 }
 
 fn upgrade_solus(ctx: &ExecutionContext) -> Result<()> {
-    if let Some(sudo) = ctx.sudo() {
-        ctx.run_type().execute(&sudo).args(&["eopkg", "upgrade"]).check_run()?;
-    } else {
-        print_warning("No sudo detected. Skipping system upgrade");
-    }
+    let sudo = ctx.require_sudo()?;
+
+    ctx.run_type().execute(&sudo).args(&["eopkg", "upgrade"]).check_run()?;
 
     Ok(())
 }
@@ -358,62 +615,94 @@ pub fn run_pacstall(ctx: &ExecutionContext) -> Result<()> {
 }
 
 fn upgrade_clearlinux(ctx: &ExecutionContext) -> Result<()> {
-    if let Some(sudo) = &ctx.sudo() {
-        ctx.run_type().execute(&sudo).args(&["swupd", "update"]).check_run()?;
-    } else {
-        print_warning("No sudo detected. Skipping system upgrade");
-    }
+    let sudo = ctx.require_sudo()?;
+
+    ctx.run_type().execute(&sudo).args(&["swupd", "update"]).check_run()?;
 
     Ok(())
 }
 
 fn upgrade_exherbo(ctx: &ExecutionContext) -> Result<()> {
-    if let Some(sudo) = ctx.sudo() {
-        ctx.run_type().execute(&sudo).args(&["cave", "sync"]).check_run()?;
+    let sudo = ctx.require_sudo()?;
 
-        ctx.run_type()
-            .execute(&sudo)
-            .args(&["cave", "resolve", "world", "-c1", "-Cs", "-km", "-Km", "-x"])
-            .check_run()?;
+    ctx.run_type().execute(&sudo).args(&["cave", "sync"]).check_run()?;
 
-        if ctx.config().cleanup() {
-            ctx.run_type()
-                .execute(&sudo)
-                .args(&["cave", "purge", "-x"])
-                .check_run()?;
-        }
-
-        ctx.run_type()
-            .execute(&sudo)
-            .args(&["cave", "fix-linkage", "-x", "--", "-Cs"])
-            .check_run()?;
+    ctx.run_type()
+        .execute(&sudo)
+        .args(&["cave", "resolve", "world", "-c1", "-Cs", "-km", "-Km", "-x"])
+        .check_run()?;
 
+    if ctx.config().cleanup() {
         ctx.run_type()
             .execute(&sudo)
-            .args(&["eclectic", "config", "interactive"])
+            .args(&["cave", "purge", "-x"])
             .check_run()?;
-    } else {
-        print_warning("No sudo detected. Skipping system upgrade");
     }
 
+    ctx.run_type()
+        .execute(&sudo)
+        .args(&["cave", "fix-linkage", "-x", "--", "-Cs"])
+        .check_run()?;
+
+    ctx.run_type()
+        .execute(&sudo)
+        .args(&["eclectic", "config", "interactive"])
+        .check_run()?;
+
     Ok(())
 }
 
 fn upgrade_nixos(ctx: &ExecutionContext) -> Result<()> {
-    if let Some(sudo) = ctx.sudo() {
-        ctx.run_type()
-            .execute(&sudo)
-            .args(&["/run/current-system/sw/bin/nixos-rebuild", "switch", "--upgrade"])
-            .check_run()?;
+    let sudo = ctx.require_sudo()?;
+
+    if let Some(flake) = ctx.config().nixos_flake() {
+        return upgrade_nixos_flake(ctx, sudo, flake);
+    }
+
+    ctx.run_type()
+        .execute(&sudo)
+        .args(&["/run/current-system/sw/bin/nixos-rebuild", "switch", "--upgrade"])
+        .check_run()?;
 
-        if ctx.config().cleanup() {
+    if ctx.config().cleanup() {
+        if unix::nix_gc_policy_configured(ctx) {
+            unix::nix_collect_garbage(ctx, Some(sudo), Path::new(NIXOS_BIN), Some(NIXOS_SYSTEM_PROFILE))?;
+        } else {
             ctx.run_type()
                 .execute(&sudo)
                 .args(&["/run/current-system/sw/bin/nix-collect-garbage", "-d"])
                 .check_run()?;
         }
+    }
+
+    Ok(())
+}
+
+fn upgrade_nixos_flake(ctx: &ExecutionContext, sudo: &Path, flake: &str) -> Result<()> {
+    let nixos_rebuild = "/run/current-system/sw/bin/nixos-rebuild";
+
+    let mut command = if ctx.config().nixos_use_remote_sudo() {
+        let mut command = ctx.run_type().execute(nixos_rebuild);
+        command.arg("--use-remote-sudo");
+        command
     } else {
-        print_warning("No sudo detected. Skipping system upgrade");
+        let mut command = ctx.run_type().execute(sudo);
+        command.arg(nixos_rebuild);
+        command
+    };
+    command.args(&["switch", "--flake", flake]).check_run()?;
+
+    if ctx.config().cleanup() {
+        let nix = "/run/current-system/sw/bin/nix";
+        if unix::nix_gc_policy_configured(ctx) {
+            unix::nix_collect_garbage(ctx, Some(sudo), Path::new(NIXOS_BIN), Some(NIXOS_SYSTEM_PROFILE))?;
+        } else {
+            ctx.run_type().execute(sudo).args(&[nix, "store", "gc"]).check_run()?;
+        }
+        ctx.run_type()
+            .execute(sudo)
+            .args(&[nix, "store", "optimise"])
+            .check_run()?;
     }
 
     Ok(())
@@ -425,21 +714,21 @@ fn upgrade_neon(ctx: &ExecutionContext) -> Result<()> {
     // in theory rpm based distributions use pkcon as well, though that
     // seems rare
     // if that comes up we need to create a Distribution::PackageKit or some such
-    if let Some(sudo) = &ctx.sudo() {
-        let pkcon = which("pkcon").unwrap();
-        // pkcon ignores update with update and refresh provided together
-        ctx.run_type().execute(&sudo).arg(&pkcon).arg("refresh").check_run()?;
-        let mut exe = ctx.run_type().execute(&sudo);
-        let cmd = exe.arg(&pkcon).arg("update");
-        if ctx.config().yes(Step::System) {
-            cmd.arg("-y");
-        }
-        if ctx.config().cleanup() {
-            cmd.arg("--autoremove");
-        }
-        // from pkcon man, exit code 5 is 'Nothing useful was done.'
-        cmd.check_run_with_codes(&[5])?;
+    let sudo = ctx.require_sudo()?;
+
+    let pkcon = which("pkcon").unwrap();
+    // pkcon ignores update with update and refresh provided together
+    ctx.run_type().execute(&sudo).arg(&pkcon).arg("refresh").check_run()?;
+    let mut exe = ctx.run_type().execute(&sudo);
+    let cmd = exe.arg(&pkcon).arg("update");
+    if ctx.config().yes(Step::System) {
+        cmd.arg("-y");
     }
+    if ctx.config().cleanup() {
+        cmd.arg("--autoremove");
+    }
+    // from pkcon man, exit code 5 is 'Nothing useful was done.'
+    cmd.check_run_with_codes(&[5])?;
 
     Ok(())
 }
@@ -460,74 +749,58 @@ pub fn run_needrestart(sudo: Option<&PathBuf>, run_type: RunType) -> Result<()>
     Ok(())
 }
 
-pub fn run_fwupdmgr(ctx: &ExecutionContext) -> Result<()> {
-    let fwupdmgr = require("fwupdmgr")?;
-
-    if is_wsl()? {
-        return Err(SkipStep(String::from("Should not run in WSL")).into());
-    }
-
-    print_separator("Firmware upgrades");
-
-    ctx.run_type()
-        .execute(&fwupdmgr)
-        .arg("refresh")
-        .check_run_with_codes(&[2])?;
-
-    let mut updmgr = ctx.run_type().execute(&fwupdmgr);
-
-    if ctx.config().firmware_upgrade() {
-        updmgr.arg("update");
-        if ctx.config().yes(Step::System) {
-            updmgr.arg("-y");
-        }
-    } else {
-        updmgr.arg("get-updates");
-    }
-    updmgr.check_run_with_codes(&[2])
-}
-
 pub fn flatpak_update(ctx: &ExecutionContext) -> Result<()> {
     let flatpak = require("flatpak")?;
-    let sudo = require_option(ctx.sudo().as_ref(), String::from("sudo is not installed"))?;
+    let sudo = ctx.require_sudo()?;
     let cleanup = ctx.config().cleanup();
     let run_type = ctx.run_type();
-    print_separator("Flatpak User Packages");
-
-    run_type
-        .execute(&flatpak)
-        .args(&["update", "--user", "-y"])
-        .check_run()?;
-    if cleanup {
-        run_type
-            .execute(&flatpak)
-            .args(&["uninstall", "--user", "--unused"])
-            .check_run()?;
-    }
+    let system_use_sudo = ctx.config().flatpak_use_sudo() || std::env::var("SSH_CLIENT").is_ok();
+
+    let mut installations = vec![
+        (String::from("Flatpak User Packages"), String::from("--user"), false),
+        (
+            String::from("Flatpak System Packages"),
+            String::from("--system"),
+            system_use_sudo,
+        ),
+    ];
+    installations.extend(flatpak_custom_installations().into_iter().map(|name| {
+        (
+            format!("Flatpak Packages ({})", name),
+            format!("--installation={}", name),
+            system_use_sudo,
+        )
+    }));
+
+    for (title, installation, use_sudo) in installations {
+        print_separator(title);
+
+        let flatpak_command = || {
+            if use_sudo {
+                let mut command = run_type.execute(&sudo);
+                command.arg(&flatpak);
+                command
+            } else {
+                run_type.execute(&flatpak)
+            }
+        };
 
-    print_separator("Flatpak System Packages");
-    if ctx.config().flatpak_use_sudo() || std::env::var("SSH_CLIENT").is_ok() {
-        run_type
-            .execute(&sudo)
-            .arg(&flatpak)
-            .args(&["update", "--system", "-y"])
-            .check_run()?;
-        if cleanup {
-            run_type
-                .execute(sudo)
-                .arg(flatpak)
-                .args(&["uninstall", "--system", "--unused"])
-                .check_run()?;
+        let mut command = flatpak_command();
+        command.args(&["update", &installation, "-y"]);
+        if let Some(exclude) = ctx.config().flatpak_exclude() {
+            let refs = flatpak_updatable_refs(&flatpak, &installation, exclude)?;
+            if refs.is_empty() {
+                println!("{}", FLATPAK_NOTHING_TO_DO);
+                continue;
+            }
+            command.args(refs);
         }
-    } else {
-        run_type
-            .execute(&flatpak)
-            .args(&["update", "--system", "-y"])
-            .check_run()?;
+        let changed = command.check_run_detect_changes(&[FLATPAK_NOTHING_TO_DO])?;
+        ctx.report_changes(changed);
+
         if cleanup {
-            run_type
-                .execute(flatpak)
-                .args(&["uninstall", "--system", "--unused"])
+            flatpak_command()
+                .args(&["uninstall", &installation, "--unused"])
                 .check_run()?;
         }
     }
@@ -535,8 +808,60 @@ pub fn flatpak_update(ctx: &ExecutionContext) -> Result<()> {
     Ok(())
 }
 
-pub fn run_snap(sudo: Option<&PathBuf>, run_type: RunType) -> Result<()> {
-    let sudo = require_option(sudo, String::from("sudo is not installed"))?;
+/// Names of the system-wide installations configured besides the default one
+fn flatpak_custom_installations() -> Vec<String> {
+    let entries = match std::fs::read_dir("/etc/flatpak/installations.d") {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut installations = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().map(|ext| ext != "conf").unwrap_or(true) {
+            continue;
+        }
+
+        match Ini::load_from_file(&path) {
+            // Sections look like [Installation "name"]
+            Ok(ini) => installations.extend(
+                ini.sections()
+                    .flatten()
+                    .filter_map(|section| section.strip_prefix("Installation "))
+                    .map(|name| name.trim_matches('"').to_string()),
+            ),
+            Err(e) => warn!("Failed parsing {}: {}", path.display(), e),
+        }
+    }
+
+    debug!("Custom flatpak installations: {:?}", installations);
+    installations
+}
+
+/// The refs of an installation which have updates and aren't excluded
+fn flatpak_updatable_refs(flatpak: &Path, installation: &str, exclude: &[String]) -> Result<Vec<String>> {
+    let output = Command::new(flatpak)
+        .args(&["remote-ls", "--updates", installation, "--columns=ref"])
+        .check_output()?;
+
+    Ok(output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .filter(|flatpak_ref| {
+            // Refs look like app/org.example.App/x86_64/stable, the kind may be omitted
+            let id = flatpak_ref
+                .split('/')
+                .find(|part| *part != "app" && *part != "runtime")
+                .unwrap_or(flatpak_ref);
+            !exclude.iter().any(|excluded| excluded == id)
+        })
+        .map(String::from)
+        .collect())
+}
+
+pub fn run_snap(ctx: &ExecutionContext) -> Result<()> {
+    let sudo = ctx.require_sudo()?;
     let snap = require("snap")?;
 
     if !PathBuf::from("/var/snapd.socket").exists() && !PathBuf::from("/run/snapd.socket").exists() {
@@ -544,7 +869,63 @@ pub fn run_snap(sudo: Option<&PathBuf>, run_type: RunType) -> Result<()> {
     }
     print_separator("snap");
 
-    run_type.execute(sudo).arg(snap).arg("refresh").check_run()
+    // Prints "All snaps up to date." to stderr and nothing to stdout when there's nothing to refresh
+    let pending = Command::new(&snap).args(&["refresh", "--list"]).check_output()?;
+    let pending_snaps: Vec<&str> = pending
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().next())
+        .collect();
+
+    if pending_snaps.is_empty() {
+        println!("All snaps up to date.");
+        ctx.report_changes(false);
+        return Ok(());
+    }
+    println!("{}", pending.trim_end());
+
+    let held = held_snaps(&snap)?;
+    let (skipped, snaps): (Vec<&str>, Vec<&str>) = pending_snaps
+        .into_iter()
+        .partition(|name| held.iter().any(|h| h == name));
+
+    if !skipped.is_empty() {
+        println!("\nSkipping held snaps: {}", skipped.join(", "));
+    }
+    if snaps.is_empty() {
+        return Ok(());
+    }
+
+    let mut command = ctx.run_type().execute(&sudo);
+    command.arg(&snap).arg("refresh").args(&snaps);
+    if let Some(args) = ctx.config().snap_arguments() {
+        command.args(args.split_whitespace());
+    }
+    command.check_run()?;
+
+    if let RunType::Wet = ctx.run_type() {
+        ctx.report_changes(true);
+    }
+
+    Ok(())
+}
+
+/// Names of the snaps held with `snap refresh --hold`, marked as "held" in the notes column of `snap list`
+fn held_snaps(snap: &Path) -> Result<Vec<String>> {
+    let output = Command::new(snap).arg("list").check_output()?;
+
+    Ok(output
+        .lines()
+        .skip(1)
+        .filter(|line| {
+            line.split_whitespace()
+                .last()
+                .map(|notes| notes.split(',').any(|note| note == "held"))
+                .unwrap_or(false)
+        })
+        .filter_map(|line| line.split_whitespace().next())
+        .map(String::from)
+        .collect())
 }
 
 pub fn run_pihole_update(sudo: Option<&PathBuf>, run_type: RunType) -> Result<()> {
@@ -557,11 +938,51 @@ pub fn run_pihole_update(sudo: Option<&PathBuf>, run_type: RunType) -> Result<()
     run_type.execute(sudo).arg(pihole).arg("-up").check_run()
 }
 
+/// List the configuration files waiting to be merged, for runs which can't launch the interactive mergers
+fn report_pending_config_files(ctx: &ExecutionContext) -> Result<()> {
+    let pending: Vec<String> = if which("etc-update").is_some() {
+        // Portage installs the new versions of protected files next to them as ._cfg0000_<name>
+        WalkDir::new("/etc")
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with("._cfg"))
+            .map(|entry| entry.path().display().to_string())
+            .collect()
+    } else if let Some(pacdiff) = which("pacdiff") {
+        Command::new(pacdiff)
+            .arg("--output")
+            .check_output()?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect()
+    } else {
+        return Err(SkipStep(String::from("Neither etc-update nor pacdiff is installed")).into());
+    };
+
+    print_separator("Configuration update");
+    if pending.is_empty() {
+        println!("No configuration files need merging");
+        return Ok(());
+    }
+
+    for path in &pending {
+        println!("{}", path);
+    }
+    ctx.add_summary_note(format!(
+        "{} configuration files need merging. Run `topgrade run config_update` to merge them",
+        pending.len()
+    ));
+
+    Ok(())
+}
+
 pub fn run_config_update(ctx: &ExecutionContext) -> Result<()> {
-    let sudo = require_option(ctx.sudo().as_ref(), String::from("sudo is not installed"))?;
     if ctx.config().yes(Step::ConfigUpdate) {
-        return Err(SkipStep("Skipped in --yes".to_string()).into());
+        return report_pending_config_files(ctx);
     }
+    let sudo = ctx.require_sudo()?;
 
     if let Ok(etc_update) = require("etc-update") {
         print_separator("Configuration update");
@@ -631,6 +1052,11 @@ mod tests {
         test_template(include_str!("os_release/opensuse"), Distribution::Suse);
     }
 
+    #[test]
+    fn test_opensuse_microos() {
+        test_template(include_str!("os_release/opensuse-microos"), Distribution::Suse);
+    }
+
     #[test]
     fn test_oraclelinux() {
         test_template(include_str!("os_release/oracle"), Distribution::CentOS);
@@ -705,4 +1131,21 @@ mod tests {
     fn test_pureos() {
         test_template(include_str!("os_release/pureos"), Distribution::Debian);
     }
+
+    #[test]
+    fn test_outdated_module_streams() {
+        let modules = "\
+Fedora Modular 36 - x86_64
+Name      Stream         Profiles                       Summary
+nodejs    16 [e]         common [d], development        Javascript runtime
+nodejs    18 [d]         common [d], development        Javascript runtime
+postgresql 13            client, server [d]             PostgreSQL server and client module
+postgresql 14 [d][e]     client, server [d]             PostgreSQL server and client module
+ripgrep   latest [d][x]  default [d]                    Line oriented search tool
+
+Hint: [d]efault, [e]nabled, [x]disabled, [i]nstalled
+";
+        assert_eq!(outdated_module_streams(modules), vec![("nodejs", "16", "18")]);
+        assert!(outdated_module_streams("").is_empty());
+    }
 }
@@ -0,0 +1,142 @@
+//! Detection of package managers which are already running, so that steps wait for them instead of failing.
+use std::fs::{self, File};
+use std::io::{self, Write};
+#[cfg(target_os = "linux")]
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use nix::errno::Errno;
+use nix::fcntl::{flock, FlockArg};
+#[cfg(target_os = "linux")]
+use nix::sys::stat::{major, minor};
+
+use crate::execution_context::ExecutionContext;
+
+pub enum PackageManager {
+    #[cfg(target_os = "linux")]
+    Apt,
+    #[cfg(target_os = "linux")]
+    Dnf,
+    #[cfg(target_os = "linux")]
+    Pacman,
+    /// Homebrew installed in the given prefix
+    Brew(PathBuf),
+}
+
+impl PackageManager {
+    fn name(&self) -> &'static str {
+        match self {
+            #[cfg(target_os = "linux")]
+            PackageManager::Apt => "dpkg",
+            #[cfg(target_os = "linux")]
+            PackageManager::Dnf => "dnf",
+            #[cfg(target_os = "linux")]
+            PackageManager::Pacman => "pacman",
+            PackageManager::Brew(_) => "Homebrew",
+        }
+    }
+
+    fn is_locked(&self) -> bool {
+        match self {
+            #[cfg(target_os = "linux")]
+            PackageManager::Apt => file_lock_held(Path::new("/var/lib/dpkg/lock-frontend")),
+            // The rpm lock can only be inspected by root, so the processes holding it are looked for
+            #[cfg(target_os = "linux")]
+            PackageManager::Dnf => process_running(&["dnf", "dnf5", "yum", "microdnf"]),
+            #[cfg(target_os = "linux")]
+            PackageManager::Pacman => Path::new("/var/lib/pacman/db.lck").exists(),
+            PackageManager::Brew(prefix) => brew_locked(prefix),
+        }
+    }
+}
+
+/// Whether a process with one of the given names is running. The kernel truncates the names to 15 characters.
+#[cfg(target_os = "linux")]
+fn process_running(names: &[&str]) -> bool {
+    let own_pid = std::process::id().to_string();
+    let entries = match fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(_) => return false,
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name() != own_pid.as_str())
+        .filter_map(|entry| fs::read_to_string(entry.path().join("comm")).ok())
+        .any(|comm| names.contains(&comm.trim()))
+}
+
+/// Whether a process holds an fcntl lock on the file, such as the lock of the dpkg frontends. The file itself may
+/// only be readable by root, so the locks are looked up in /proc/locks, which lists them by device and inode.
+#[cfg(target_os = "linux")]
+fn file_lock_held(path: &Path) -> bool {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return false,
+    };
+    let id = format!(
+        "{:02x}:{:02x}:{}",
+        major(metadata.dev()),
+        minor(metadata.dev()),
+        metadata.ino()
+    );
+
+    fs::read_to_string("/proc/locks")
+        .map(|locks| {
+            locks
+                .lines()
+                .any(|lock| lock.split_whitespace().any(|field| field == id))
+        })
+        .unwrap_or(false)
+}
+
+/// Homebrew holds an exclusive flock on the files in its locks directory while it works
+fn brew_locked(prefix: &Path) -> bool {
+    let entries = match fs::read_dir(prefix.join("var/homebrew/locks")) {
+        Ok(entries) => entries,
+        Err(_) => return false,
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| File::open(entry.path()).ok())
+        // Our own lock is released when the file is closed
+        .any(|file| flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock) == Err(Errno::EWOULDBLOCK))
+}
+
+/// Wait until the package manager isn't used by another process, for up to the configured timeout
+pub fn wait_for_package_manager(ctx: &ExecutionContext, package_manager: &PackageManager) -> Result<()> {
+    if ctx.run_type().dry() || !package_manager.is_locked() {
+        return Ok(());
+    }
+
+    let timeout = ctx.config().package_lock_timeout();
+    let start = Instant::now();
+
+    while package_manager.is_locked() {
+        let remaining = timeout.checked_sub(start.elapsed()).unwrap_or_default();
+        if remaining.is_zero() {
+            println!();
+            return Err(anyhow!(
+                "{} is still used by another process after {} seconds",
+                package_manager.name(),
+                timeout.as_secs()
+            ));
+        }
+
+        print!(
+            "\r{} is used by another process. Waiting up to {} seconds ",
+            package_manager.name(),
+            remaining.as_secs()
+        );
+        io::stdout().flush().ok();
+        sleep(Duration::from_secs(1));
+    }
+
+    println!();
+    Ok(())
+}
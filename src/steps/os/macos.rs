@@ -1,15 +1,17 @@
 use crate::execution_context::ExecutionContext;
 use crate::executor::{CommandExt, RunType};
 use crate::terminal::{print_separator, prompt_yesno};
+use crate::utils::PathExt;
 use crate::{error::TopgradeError, utils::require, Step};
 use anyhow::Result;
 use log::debug;
 use std::fs;
+use std::path::Path;
 use std::process::Command;
 
 pub fn run_macports(ctx: &ExecutionContext) -> Result<()> {
     require("port")?;
-    let sudo = ctx.sudo().as_ref().unwrap();
+    let sudo = ctx.require_sudo()?;
     print_separator("MacPorts");
     ctx.run_type().execute(sudo).args(&["port", "selfupdate"]).check_run()?;
     ctx.run_type()
@@ -37,31 +39,41 @@ pub fn upgrade_macos(ctx: &ExecutionContext) -> Result<()> {
     print_separator("macOS system update");
 
     let should_ask = !(ctx.config().yes(Step::System)) || (ctx.config().dry_run());
-    if should_ask {
-        println!("Finding available software");
-        if system_update_available()? {
-            let answer = prompt_yesno("A system update is available. Do you wish to install it?")?;
-            if !answer {
-                return Ok(());
-            }
-            println!();
-        } else {
+
+    println!("Finding available software");
+    let updates = match available_updates()? {
+        Some(updates) => updates,
+        None => {
             println!("No new software available.");
             return Ok(());
         }
+    };
+
+    if should_ask {
+        let answer = prompt_yesno("A system update is available. Do you wish to install it?")?;
+        if !answer {
+            return Ok(());
+        }
+        println!();
     }
 
-    let mut command = ctx.run_type().execute("softwareupdate");
-    command.args(&["--install", "--all"]);
+    ctx.run_type()
+        .execute("softwareupdate")
+        .args(&["--install", "--all", "--no-scan"])
+        .check_run()?;
 
-    if should_ask {
-        command.arg("--no-scan");
+    // Updates which require a restart are marked with "restart" in the listing
+    if let RunType::Wet = ctx.run_type() {
+        if updates.contains("restart") {
+            ctx.report_reboot_required(String::from("macOS updates require a restart"));
+        }
     }
 
-    command.check_run()
+    Ok(())
 }
 
-fn system_update_available() -> Result<bool> {
+/// Returns the listing of the available system updates, or `None` if there are none
+fn available_updates() -> Result<Option<String>> {
     let output = Command::new("softwareupdate").arg("--list").output()?;
     debug!("{:?}", output);
 
@@ -71,7 +83,22 @@ fn system_update_available() -> Result<bool> {
     }
     let string_output = String::from_utf8(output.stderr)?;
     debug!("{:?}", string_output);
-    Ok(!string_output.contains("No new software available"))
+    if string_output.contains("No new software available") {
+        return Ok(None);
+    }
+
+    Ok(Some(String::from_utf8(output.stdout)?))
+}
+
+/// Verify the EFI firmware against the versions Apple knows. Only available on Intel Macs.
+pub fn run_eficheck(ctx: &ExecutionContext) -> Result<()> {
+    let eficheck = Path::new("/usr/libexec/firmwarecheckers/eficheck/eficheck").require()?;
+
+    print_separator("Firmware check");
+
+    ctx.execute_elevated(&eficheck, false)?
+        .arg("--integrity-check")
+        .check_run()
 }
 
 pub fn run_sparkle(ctx: &ExecutionContext) -> Result<()> {
@@ -8,6 +8,8 @@ pub mod dragonfly;
 pub mod freebsd;
 #[cfg(target_os = "linux")]
 pub mod linux;
+#[cfg(unix)]
+pub mod locks;
 #[cfg(target_os = "macos")]
 pub mod macos;
 #[cfg(unix)]
@@ -1,15 +1,19 @@
 use crate::error::{SkipStep, TopgradeError};
 use crate::execution_context::ExecutionContext;
 use crate::executor::{CommandExt, Executor, ExecutorExitStatus, RunType};
-use crate::terminal::print_separator;
+use crate::report::Upgrade;
+use crate::steps::os::locks::{wait_for_package_manager, PackageManager};
+use crate::terminal::{print_separator, print_warning};
 #[cfg(not(target_os = "macos"))]
 use crate::utils::require_option;
 use crate::utils::{require, PathExt};
 use crate::Step;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use directories::BaseDirs;
 use ini::Ini;
 use log::debug;
+use semver::Version;
+use serde_json::Value;
 use std::fs;
 use std::os::unix::fs::MetadataExt;
 use std::path::PathBuf;
@@ -72,7 +76,95 @@ impl BrewVariant {
 
     #[cfg(target_os = "macos")]
     fn is_macos_custom(binary_name: PathBuf) -> bool {
-        !(binary_name.as_os_str() == INTEL_BREW || binary_name.as_os_str() == ARM_BREW)
+        // A brew in the PATH may link to one of the standard installations, which are upgraded by their own variants
+        let binary_name = binary_name.canonicalize().unwrap_or(binary_name);
+        ![INTEL_BREW, ARM_BREW]
+            .iter()
+            .any(|standard| Path::new(standard).canonicalize().ok().as_ref() == Some(&binary_name))
+    }
+
+    /// Whether Homebrew updated itself within the configured window, making `brew update` redundant.
+    ///
+    /// Homebrew fetches its repository and, since it uses the JSON API, downloads the formula list when updating.
+    fn updated_recently(self, ctx: &ExecutionContext) -> bool {
+        let window = match ctx.config().brew_recent_update_window() {
+            Some(window) => window,
+            None => return false,
+        };
+
+        let query = |arg: &str| {
+            self.execute(RunType::Wet)
+                .arg(arg)
+                .check_output()
+                .map(|output| PathBuf::from(output.trim()))
+        };
+        let mut markers = Vec::new();
+        if let Ok(repository) = query("--repository") {
+            markers.push(repository.join(".git/FETCH_HEAD"));
+        }
+        if let Ok(cache) = query("--cache") {
+            markers.push(cache.join("api/formula.jws.json"));
+        }
+
+        markers
+            .iter()
+            .filter_map(|marker| fs::metadata(marker).and_then(|m| m.modified()).ok())
+            .filter_map(|modified| modified.elapsed().ok())
+            .any(|elapsed| elapsed < window)
+    }
+
+    /// The formulas which have newer versions, with their newest versions
+    fn outdated_formulas(self) -> Result<Vec<Upgrade>> {
+        let output = self
+            .execute(RunType::Wet)
+            .args(&["outdated", "--formula", "--json=v2"])
+            .env("HOMEBREW_NO_AUTO_UPDATE", "1")
+            .check_output()?;
+        let outdated: Value = serde_json::from_str(&output)?;
+
+        // Formulas of taps are prefixed with the tap, which services aren't
+        Ok(outdated["formulae"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            // Pinned formulas are listed as well, but they aren't upgraded
+            .filter(|formula| formula["pinned"] != true)
+            .filter_map(|formula| {
+                Some(Upgrade {
+                    name: formula["name"].as_str()?.rsplit('/').next()?.to_string(),
+                    version: formula["current_version"].as_str()?.to_string(),
+                })
+            })
+            .collect())
+    }
+
+    /// Restart the started services of the upgraded formulas, so that they run the new versions
+    fn restart_services(self, ctx: &ExecutionContext, upgraded: &[String]) -> Result<()> {
+        let output = self
+            .execute(RunType::Wet)
+            .args(&["services", "list", "--json"])
+            .check_output()?;
+        let services: Vec<Value> = serde_json::from_str(&output)?;
+
+        for service in services
+            .iter()
+            .filter(|service| service["status"] == "started")
+            .filter_map(|service| service["name"].as_str())
+            .filter(|name| upgraded.iter().any(|upgraded| upgraded == name))
+        {
+            println!("Restarting the service of {}", service);
+            self.execute(ctx.run_type())
+                .args(&["services", "restart", service])
+                .check_run()?;
+        }
+
+        Ok(())
+    }
+
+    /// Wait for other brew processes of this installation to finish
+    fn wait_for_lock(self, ctx: &ExecutionContext) -> Result<()> {
+        let prefix = self.execute(RunType::Wet).arg("--prefix").check_output()?;
+        wait_for_package_manager(ctx, &PackageManager::Brew(PathBuf::from(prefix.trim())))
     }
 }
 
@@ -117,14 +209,16 @@ pub fn run_oh_my_fish(ctx: &ExecutionContext) -> Result<()> {
 pub fn run_pkgin(ctx: &ExecutionContext) -> Result<()> {
     let pkgin = require("pkgin")?;
 
-    let mut command = ctx.run_type().execute(ctx.sudo().as_ref().unwrap());
+    let sudo = ctx.require_sudo()?;
+
+    let mut command = ctx.run_type().execute(sudo);
     command.arg(&pkgin).arg("update");
     if ctx.config().yes(Step::Pkgin) {
         command.arg("-y");
     }
     command.check_run()?;
 
-    let mut command = ctx.run_type().execute(ctx.sudo().as_ref().unwrap());
+    let mut command = ctx.run_type().execute(sudo);
     command.arg(&pkgin).arg("upgrade");
     if ctx.config().yes(Step::Pkgin) {
         command.arg("-y");
@@ -199,20 +293,54 @@ pub fn run_brew_formula(ctx: &ExecutionContext, variant: BrewVariant) -> Result<
 
     print_separator(variant.step_title());
     let run_type = ctx.run_type();
+    variant.wait_for_lock(ctx)?;
 
-    variant.execute(run_type).arg("update").check_run()?;
+    if variant.updated_recently(ctx) {
+        println!("Homebrew was updated recently. Skipping brew update");
+    } else {
+        variant.execute(run_type).arg("update").check_run()?;
+    }
+    let restart_services = ctx.config().brew_restart_services();
+    let report_upgrades = !run_type.dry() && ctx.config().changelog_url(Step::BrewFormula.as_ref()).is_some();
+    let outdated = if restart_services || report_upgrades {
+        variant.outdated_formulas()?
+    } else {
+        Vec::new()
+    };
+
+    // Homebrew would otherwise check whether it should update itself again
     variant
         .execute(run_type)
         .args(&["upgrade", "--ignore-pinned", "--formula"])
+        .args(ctx.config().step_arguments(Step::BrewFormula))
+        .env("HOMEBREW_NO_AUTO_UPDATE", "1")
         .check_run()?;
 
-    if ctx.config().cleanup() {
-        variant.execute(run_type).arg("cleanup").check_run()?;
+    if restart_services && !outdated.is_empty() {
+        let upgraded: Vec<String> = outdated.iter().map(|formula| formula.name.clone()).collect();
+        variant.restart_services(ctx, &upgraded)?;
+    }
+    if report_upgrades {
+        ctx.report_upgrades(outdated);
     }
 
     Ok(())
 }
 
+pub fn run_brew_cleanup(ctx: &ExecutionContext, variant: BrewVariant) -> Result<()> {
+    #[allow(unused_variables)]
+    let binary_name = require(variant.binary_name())?;
+
+    #[cfg(target_os = "macos")]
+    {
+        if variant.is_path() && !BrewVariant::is_macos_custom(binary_name) {
+            return Err(SkipStep("Not a custom brew for macOS".to_string()).into());
+        }
+    }
+
+    variant.execute(ctx.run_type()).arg("cleanup").check_run()
+}
+
 #[cfg(target_os = "macos")]
 pub fn run_brew_cask(ctx: &ExecutionContext, variant: BrewVariant) -> Result<()> {
     let binary_name = require(variant.binary_name())?;
@@ -221,6 +349,7 @@ pub fn run_brew_cask(ctx: &ExecutionContext, variant: BrewVariant) -> Result<()>
     }
     print_separator(format!("{} - Cask", variant.step_title()));
     let run_type = ctx.run_type();
+    variant.wait_for_lock(ctx)?;
 
     let cask_upgrade_exists = variant
         .execute(RunType::Wet)
@@ -242,13 +371,7 @@ pub fn run_brew_cask(ctx: &ExecutionContext, variant: BrewVariant) -> Result<()>
         }
     }
 
-    variant.execute(run_type).args(&brew_args).check_run()?;
-
-    if ctx.config().cleanup() {
-        variant.execute(run_type).arg("cleanup").check_run()?;
-    }
-
-    Ok(())
+    variant.execute(run_type).args(&brew_args).check_run()
 }
 
 pub fn run_nix(ctx: &ExecutionContext) -> Result<()> {
@@ -285,7 +408,92 @@ pub fn run_nix(ctx: &ExecutionContext) -> Result<()> {
     }
 
     run_type.execute(&nix_channel).arg("--update").check_run()?;
-    run_type.execute(&nix_env).arg("--upgrade").check_run()
+    run_type.execute(&nix_env).arg("--upgrade").check_run()?;
+
+    if ctx.config().cleanup() && nix_gc_policy_configured(ctx) {
+        let bin_dir = nix_env.parent().unwrap();
+        nix_collect_garbage(ctx, None, bin_dir, None)?;
+    }
+
+    Ok(())
+}
+
+/// Tells whether a policy for deleting old Nix generations is configured
+pub fn nix_gc_policy_configured(ctx: &ExecutionContext) -> bool {
+    ctx.config().nix_keep_generations().is_some() || ctx.config().nix_keep_days().is_some()
+}
+
+/// Delete the Nix generations of a profile which fall outside the configured policy and collect garbage.
+///
+/// `bin_dir` is the directory holding the Nix binaries and `profile` defaults to the profile of the user.
+pub fn nix_collect_garbage(
+    ctx: &ExecutionContext,
+    sudo: Option<&Path>,
+    bin_dir: &Path,
+    profile: Option<&str>,
+) -> Result<()> {
+    let command = |program: &str| match sudo {
+        Some(sudo) => {
+            let mut command = ctx.run_type().execute(sudo);
+            command.arg(bin_dir.join(program));
+            command
+        }
+        None => ctx.run_type().execute(bin_dir.join(program)),
+    };
+
+    if let Some(generations) = ctx.config().nix_keep_generations() {
+        let mut nix_env = command("nix-env");
+        if let Some(profile) = profile {
+            nix_env.args(&["--profile", profile]);
+        }
+        nix_env
+            .arg("--delete-generations")
+            .arg(format!("+{}", generations))
+            .check_run()?;
+    }
+
+    let mut collect_garbage = command("nix-collect-garbage");
+    if let Some(days) = ctx.config().nix_keep_days() {
+        collect_garbage.arg("--delete-older-than").arg(format!("{}d", days));
+    }
+    collect_garbage.check_run()
+}
+
+pub fn run_devbox(ctx: &ExecutionContext) -> Result<()> {
+    let devbox = require("devbox")?;
+
+    print_separator("devbox");
+
+    ctx.run_type().execute(&devbox).args(&["global", "update"]).check_run()
+}
+
+pub fn run_flox(ctx: &ExecutionContext) -> Result<()> {
+    let flox = require("flox")?;
+    // The default environment lives in the home directory
+    let home = ctx.base_dirs().home_dir();
+    home.join(".flox").require()?;
+
+    print_separator("flox");
+
+    ctx.run_type()
+        .execute(&flox)
+        .arg("upgrade")
+        .arg("--dir")
+        .arg(home)
+        .check_run()
+}
+
+pub fn run_devenv(ctx: &ExecutionContext, project: &str) -> Result<()> {
+    let devenv = require("devenv")?;
+    Path::new(project).join("devenv.nix").require()?;
+
+    print_separator(format!("devenv ({})", project));
+
+    ctx.run_type()
+        .execute(&devenv)
+        .current_dir(project)
+        .arg("update")
+        .check_run()
 }
 
 pub fn run_yadm(ctx: &ExecutionContext) -> Result<()> {
@@ -296,8 +504,9 @@ pub fn run_yadm(ctx: &ExecutionContext) -> Result<()> {
     ctx.run_type().execute(&yadm).arg("pull").check_run()
 }
 
-pub fn run_asdf(run_type: RunType) -> Result<()> {
+pub fn run_asdf(ctx: &ExecutionContext) -> Result<()> {
     let asdf = require("asdf")?;
+    let run_type = ctx.run_type();
 
     print_separator("asdf");
     let exit_status = run_type.execute(&asdf).arg("update").spawn()?.wait()?;
@@ -307,14 +516,140 @@ pub fn run_asdf(run_type: RunType) -> Result<()> {
             return Err(TopgradeError::ProcessFailed(e).into());
         }
     }
-    run_type.execute(&asdf).args(&["plugin", "update", "--all"]).check_run()
+    run_type
+        .execute(&asdf)
+        .args(&["plugin", "update", "--all"])
+        .check_run()?;
+
+    if ctx.config().asdf_bump_global_versions() {
+        bump_asdf_global_versions(ctx, &asdf)?;
+    }
+
+    Ok(())
+}
+
+/// The version of asdf, which prints it like `v0.14.0-ccdd47d` or `asdf version 0.16.0`
+fn asdf_version(asdf: &Path) -> Result<Version> {
+    let output = Command::new(asdf).arg("version").check_output()?;
+    let version = output
+        .split_whitespace()
+        .last()
+        .and_then(|version| version.trim_start_matches('v').split('-').next())
+        .unwrap_or_default();
+    Version::parse(version).with_context(|| format!("Failed parsing the asdf version {:?}", output.trim()))
 }
 
-pub fn run_home_manager(run_type: RunType) -> Result<()> {
+/// Install the latest version of every tool in ~/.tool-versions and make it the global version
+fn bump_asdf_global_versions(ctx: &ExecutionContext, asdf: &Path) -> Result<()> {
+    let tool_versions = ctx.base_dirs().home_dir().join(".tool-versions");
+    let contents = match fs::read_to_string(&tool_versions) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("Failed reading {}", tool_versions.display())),
+    };
+    // asdf 0.16 replaced `asdf global` with `asdf set -u`
+    let set_global: &[&str] = if asdf_version(asdf)? >= Version::new(0, 16, 0) {
+        &["set", "-u"]
+    } else {
+        &["global"]
+    };
+
+    for line in contents.lines() {
+        let mut fields = line.split('#').next().unwrap_or_default().split_whitespace();
+        let (plugin, current) = match (fields.next(), fields.next()) {
+            (Some(plugin), Some(current)) => (plugin, current),
+            _ => continue,
+        };
+
+        // System installations and versions built from a ref or a path are managed by hand
+        if current == "system" || current.starts_with("ref:") || current.starts_with("path:") {
+            continue;
+        }
+
+        let latest = match Command::new(asdf).args(&["latest", plugin]).check_output() {
+            Ok(latest) => latest.trim().to_string(),
+            Err(e) => {
+                print_warning(format!("Failed getting the latest version of {}: {}", plugin, e));
+                continue;
+            }
+        };
+
+        if latest.is_empty() || latest == current {
+            continue;
+        }
+
+        println!("Bumping {} from {} to {}", plugin, current, latest);
+        ctx.run_type()
+            .execute(asdf)
+            .args(&["install", plugin, &latest])
+            .check_run()?;
+        ctx.run_type()
+            .execute(asdf)
+            .args(set_global)
+            .args(&[plugin, &latest])
+            .check_run()?;
+        ctx.report_changes(!ctx.run_type().dry());
+    }
+
+    Ok(())
+}
+
+pub fn run_home_manager(ctx: &ExecutionContext) -> Result<()> {
     let home_manager = require("home-manager")?;
+    let flake = ctx.config().home_manager_flake();
 
     print_separator("home-manager");
-    run_type.execute(&home_manager).arg("switch").check_run()
+
+    let mut command = ctx.run_type().execute(&home_manager);
+    command.arg("switch");
+    if let Some(flake) = flake {
+        command.args(&["--flake", flake]);
+    }
+
+    let unread_news = command
+        .check_run_capture()?
+        .and_then(|output| unread_home_manager_news(&output))
+        .unwrap_or(0);
+
+    if unread_news > 0 {
+        ctx.add_summary_note(format!(
+            "home-manager has {} unread news item(s). Read them by running `home-manager news`",
+            unread_news
+        ));
+
+        if ctx.config().home_manager_show_news() {
+            let mut command = ctx.run_type().execute(&home_manager);
+            command.arg("news");
+            if let Some(flake) = flake {
+                command.args(&["--flake", flake]);
+            }
+            command.check_run()?;
+        }
+    }
+
+    if let Some(timestamp) = ctx
+        .config()
+        .home_manager_expire_generations()
+        .filter(|_| ctx.config().cleanup())
+    {
+        ctx.run_type()
+            .execute(&home_manager)
+            .arg("expire-generations")
+            .arg(timestamp)
+            .check_run()?;
+    }
+
+    Ok(())
+}
+
+/// Parses the number of unread news from the output of `home-manager switch`, which reads
+/// "There are 3 unread and relevant news items."
+fn unread_home_manager_news(output: &str) -> Option<u32> {
+    output
+        .lines()
+        .find(|line| line.contains("unread and relevant news"))?
+        .split_whitespace()
+        .find_map(|word| word.parse().ok())
 }
 
 pub fn run_tldr(run_type: RunType) -> Result<()> {
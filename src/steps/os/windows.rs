@@ -5,11 +5,12 @@ use std::{ffi::OsStr, process::Command};
 use anyhow::Result;
 use log::debug;
 
+use crate::error::{SkipStep, StepFailed};
 use crate::execution_context::ExecutionContext;
 use crate::executor::{CommandExt, RunType};
+use crate::steps::git::Repositories;
 use crate::terminal::{print_separator, print_warning};
 use crate::utils::require;
-use crate::{error::SkipStep, steps::git::Repositories};
 use crate::{powershell, Step};
 
 pub fn run_chocolatey(ctx: &ExecutionContext) -> Result<()> {
@@ -18,17 +19,20 @@ pub fn run_chocolatey(ctx: &ExecutionContext) -> Result<()> {
 
     print_separator("Chocolatey");
 
-    let mut cmd = &choco;
-    let mut args = vec!["upgrade", "all"];
-
-    if let Some(sudo) = ctx.sudo() {
-        cmd = sudo;
-        args.insert(0, "choco");
-    }
+    let mut command = match ctx.sudo() {
+        Some(sudo) if ctx.config().chocolatey_use_sudo() && !is_elevated() => {
+            let mut command = ctx.run_type().execute(&sudo);
+            command.arg(&choco);
+            command
+        }
+        _ => ctx.run_type().execute(&choco),
+    };
 
-    let mut command = ctx.run_type().execute(&cmd);
+    command.args(&["upgrade", "all"]);
 
-    command.args(&args);
+    if let Some(args) = ctx.config().chocolatey_arguments() {
+        command.args(args.split_whitespace());
+    }
 
     if yes {
         command.arg("--yes");
@@ -37,6 +41,17 @@ pub fn run_chocolatey(ctx: &ExecutionContext) -> Result<()> {
     command.check_run()
 }
 
+/// Tells whether the current process runs with administrator privileges.
+///
+/// `net session` requires elevation, so its exit status is used as a cheap check.
+fn is_elevated() -> bool {
+    Command::new("net")
+        .arg("session")
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
 pub fn run_winget(ctx: &ExecutionContext) -> Result<()> {
     let winget = require("winget")?;
 
@@ -50,6 +65,14 @@ pub fn run_winget(ctx: &ExecutionContext) -> Result<()> {
     ctx.run_type().execute(&winget).args(&["upgrade", "--all"]).check_run()
 }
 
+pub fn microsoft_store(ctx: &ExecutionContext) -> Result<()> {
+    let powershell = powershell::Powershell::windows_powershell();
+
+    print_separator("Microsoft Store");
+
+    powershell.microsoft_store(ctx)
+}
+
 pub fn run_scoop(cleanup: bool, run_type: RunType) -> Result<()> {
     let scoop = require("scoop")?;
 
@@ -96,21 +119,35 @@ pub fn run_wsl_topgrade(ctx: &ExecutionContext) -> Result<()> {
     let wsl = require("wsl")?;
     let wsl_distributions = get_wsl_distributions(&wsl)?;
     let mut ran = false;
+    let mut failed = false;
 
     debug!("WSL distributions: {:?}", wsl_distributions);
 
+    let allowed_distributions = ctx.config().wsl_distributions();
+
     for distribution in wsl_distributions {
+        if let Some(allowed) = allowed_distributions {
+            if !allowed.iter().any(|d| d.eq_ignore_ascii_case(&distribution)) {
+                debug!("Skipping WSL distribution {}", distribution);
+                continue;
+            }
+        }
+
         let result = upgrade_wsl_distribution(&wsl, &distribution, ctx);
         debug!("Upgrading {:?}: {:?}", distribution, result);
         if let Err(e) = result {
             if e.is::<SkipStep>() {
                 continue;
             }
+            print_warning(format!("Upgrading WSL distribution {} failed: {}", distribution, e));
+            failed = true;
         }
         ran = true
     }
 
-    if ran {
+    if failed {
+        Err(StepFailed.into())
+    } else if ran {
         Ok(())
     } else {
         Err(SkipStep(String::from("Could not find Topgrade in any WSL disribution")).into())
@@ -120,4 +120,29 @@ impl Powershell {
             ])
             .check_run()
     }
+
+    #[cfg(windows)]
+    pub fn microsoft_store(&self, ctx: &ExecutionContext) -> Result<()> {
+        let powershell = require_option(self.path.as_ref(), String::from("Powershell is not installed"))?;
+
+        let mut command = if let Some(sudo) = ctx.sudo() {
+            let mut command = ctx.run_type().execute(sudo);
+            command.arg(&powershell);
+            command
+        } else {
+            ctx.run_type().execute(&powershell)
+        };
+
+        println!("Scanning for updates...");
+        command
+            .args(&[
+                "-NoProfile",
+                "-Command",
+                "Get-CimInstance -Namespace \"Root\\cimv2\\mdm\\dmmap\" -ClassName \"MDM_EnterpriseModernAppManagement_AppManagement01\" | Invoke-CimMethod -MethodName UpdateScanMethod",
+            ])
+            .check_run()?;
+
+        println!("Updates are installed in the background. Check the Microsoft Store for progress.");
+        Ok(())
+    }
 }
@@ -1,5 +1,12 @@
+use std::process::Command;
+
 use anyhow::Result;
+use console::style;
 
+use crate::config::{Config, Remote, RemotePreset};
+use crate::report::parse_json_report;
+use crate::secrets;
+use crate::utils::Check;
 use crate::{error::SkipStep, execution_context::ExecutionContext, terminal::print_separator, utils};
 
 fn prepare_async_ssh_command(args: &mut Vec<&str>) {
@@ -7,20 +14,51 @@ fn prepare_async_ssh_command(args: &mut Vec<&str>) {
     args.push("--keep");
 }
 
-pub fn ssh_step(ctx: &ExecutionContext, hostname: &str) -> Result<()> {
+pub fn ssh_step(ctx: &ExecutionContext, remote: &Remote) -> Result<()> {
+    match remote.preset() {
+        Some(RemotePreset::K3sNode) => upgrade_kubernetes_node(ctx, remote),
+        None => remote_topgrade(ctx, remote.host(), true),
+    }
+}
+
+/// Drain the node so that its workloads move to other nodes during the upgrade, and uncordon it afterwards
+fn upgrade_kubernetes_node(ctx: &ExecutionContext, remote: &Remote) -> Result<()> {
+    let kubectl = utils::require("kubectl")?;
+    let node = remote.kubernetes_node();
+
+    print_separator(format!("Draining {}", node));
+    // Draining cordons the node before evicting its pods, so it's cordoned even if the eviction fails
+    let result = ctx
+        .run_type()
+        .execute(&kubectl)
+        .args(&["drain", node, "--ignore-daemonsets", "--delete-emptydir-data"])
+        .check_run()
+        // Running in the background would uncordon the node while it's still being upgraded
+        .and_then(|()| remote_topgrade(ctx, remote.host(), false));
+
+    // The node is uncordoned even if the drain or the upgrade failed, so that it doesn't stay unschedulable
+    println!("Uncordoning {}", node);
+    let uncordon = ctx.run_type().execute(&kubectl).args(&["uncordon", node]).check_run();
+
+    result.and(uncordon)
+}
+
+fn remote_topgrade(ctx: &ExecutionContext, hostname: &str, allow_background: bool) -> Result<()> {
     let ssh = utils::require("ssh")?;
 
-    let topgrade = ctx.config().remote_topgrade_path();
+    let topgrade = secrets::expand(ctx.config().remote_topgrade_path(), ctx.config())?;
+    let topgrade = topgrade.as_str();
+    let ssh_arguments = match ctx.config().ssh_arguments() {
+        Some(ssh_arguments) => secrets::expand(ssh_arguments, ctx.config())?,
+        None => String::new(),
+    };
     let mut args = vec!["-t", hostname];
-
-    if let Some(ssh_arguments) = ctx.config().ssh_arguments() {
-        args.extend(ssh_arguments.split_whitespace());
-    }
+    args.extend(ssh_arguments.split_whitespace());
 
     let env = format!("TOPGRADE_PREFIX={}", hostname);
     args.extend(&["env", &env, "$SHELL", "-lc", topgrade]);
 
-    if ctx.config().run_in_tmux() && !ctx.run_type().dry() {
+    if allow_background && ctx.config().run_in_tmux() && !ctx.run_type().dry() {
         #[cfg(unix)]
         {
             prepare_async_ssh_command(&mut args);
@@ -30,23 +68,78 @@ pub fn ssh_step(ctx: &ExecutionContext, hostname: &str) -> Result<()> {
 
         #[cfg(not(unix))]
         unreachable!("Tmux execution is only implemented in Unix");
-    } else if ctx.config().open_remotes_in_new_terminal() && !ctx.run_type().dry() && cfg!(windows) {
+    } else if allow_background && ctx.config().open_remotes_in_new_terminal() && !ctx.run_type().dry() && cfg!(windows)
+    {
         prepare_async_ssh_command(&mut args);
         ctx.run_type().execute("wt").args(&args).spawn()?;
         Err(SkipStep(String::from("Remote Topgrade launched in an external terminal")).into())
     } else {
         let mut args = vec!["-t", hostname];
+        args.extend(ssh_arguments.split_whitespace());
 
-        if let Some(ssh_arguments) = ctx.config().ssh_arguments() {
-            args.extend(ssh_arguments.split_whitespace());
-        }
-
+        let remote_command = if ctx.config().remote_summary() {
+            format!("{} --report-format json", topgrade)
+        } else {
+            topgrade.to_string()
+        };
         let env = format!("TOPGRADE_PREFIX={}", hostname);
-        args.extend(&["env", &env, "$SHELL", "-lc", topgrade]);
+        args.extend(&["env", &env, "$SHELL", "-lc", &remote_command]);
 
         print_separator(format!("Remote ({})", hostname));
         println!("Connecting to {}...", hostname);
 
-        ctx.run_type().execute(&ssh).args(&args).check_run()
+        let mut command = ctx.run_type().execute(&ssh);
+        command.args(&args);
+        if !ctx.config().remote_summary() {
+            return command.check_run();
+        }
+
+        match command.run_capture()? {
+            Some((status, output)) => {
+                if let Some(results) = parse_json_report(&output) {
+                    ctx.report_sub_results(results);
+                }
+                status.check()
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// Print the configured remote hosts, their tags and whether they accept SSH connections
+pub fn list_remotes(config: &Config) -> Result<()> {
+    let ssh = utils::require("ssh")?;
+
+    for remote in config.remotes().iter().filter(|r| config.remote_matches_filters(r)) {
+        let mut command = Command::new(&ssh);
+        if let Some(ssh_arguments) = config.ssh_arguments() {
+            command.args(ssh_arguments.split_whitespace());
+        }
+
+        // Batch mode fails instead of prompting for passwords, which would stall the listing
+        let reachable = command
+            .args(&["-o", "BatchMode=yes", "-o", "ConnectTimeout=5", remote.host(), "true"])
+            .output()
+            .map(|o| o.status.success())
+            .unwrap_or(false);
+
+        let tags = if remote.tags().is_empty() {
+            String::new()
+        } else {
+            format!(" [{}]", remote.tags().join(", "))
+        };
+
+        println!(
+            "{}{}: {}",
+            remote.host(),
+            tags,
+            if reachable {
+                style("reachable").green()
+            } else {
+                style("unreachable").red()
+            }
+        );
     }
+
+    Ok(())
 }
@@ -8,7 +8,9 @@ use crate::{
     utils::{require, PathExt},
 };
 use directories::BaseDirs;
+use glob::glob;
 use log::debug;
+use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 use std::{
     io::{self, Write},
@@ -17,6 +19,9 @@ use std::{
 
 const UPGRADE_VIM: &str = include_str!("upgrade.vim");
 
+/// Directories of native packages which are managed by a plugin manager, and are updated by it
+const MANAGED_PACKAGES: &[&str] = &["packer", "paqs", "minpac"];
+
 pub fn vimrc(base_dirs: &BaseDirs) -> Result<PathBuf> {
     base_dirs
         .home_dir()
@@ -25,7 +30,8 @@ pub fn vimrc(base_dirs: &BaseDirs) -> Result<PathBuf> {
         .or_else(|_| base_dirs.home_dir().join(".vim/vimrc").require())
 }
 
-fn nvimrc(base_dirs: &BaseDirs) -> Result<PathBuf> {
+/// The directories of Neovim are named after `NVIM_APPNAME`, which is `nvim` by default
+fn nvim_config_dir(base_dirs: &BaseDirs, appname: &str) -> PathBuf {
     #[cfg(unix)]
         let base_dir =
         // Bypass directories crate as nvim doesn't use the macOS-specific directories.
@@ -34,13 +40,72 @@ fn nvimrc(base_dirs: &BaseDirs) -> Result<PathBuf> {
     #[cfg(windows)]
     let base_dir = base_dirs.cache_dir();
 
-    base_dir
-        .join("nvim/init.vim")
+    base_dir.join(appname)
+}
+
+fn nvim_data_dir(base_dirs: &BaseDirs, appname: &str) -> PathBuf {
+    #[cfg(unix)]
+    return std::env::var_os("XDG_DATA_HOME")
+        .map_or_else(|| base_dirs.home_dir().join(".local/share"), PathBuf::from)
+        .join(appname);
+
+    #[cfg(windows)]
+    return base_dirs.cache_dir().join(format!("{}-data", appname));
+}
+
+fn nvimrc(base_dirs: &BaseDirs, appname: &str) -> Result<PathBuf> {
+    let config_dir = nvim_config_dir(base_dirs, appname);
+
+    config_dir
+        .join("init.vim")
         .require()
-        .or_else(|_| base_dir.join("nvim/init.lua").require())
+        .or_else(|_| config_dir.join("init.lua").require())
+}
+
+/// Pull the plugins installed as native packages (`pack/*/start/*`) which are git repositories
+fn upgrade_native_packages(ctx: &ExecutionContext, pack_roots: &[PathBuf]) -> Result<()> {
+    let plugins: Vec<PathBuf> = pack_roots
+        .iter()
+        .filter_map(|root| glob(&format!("{}/pack/*/start/*", root.display())).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|plugin| plugin.join(".git").exists())
+        .filter(|plugin| {
+            let package = plugin.parent().and_then(Path::parent).and_then(Path::file_name);
+            !MANAGED_PACKAGES
+                .iter()
+                .any(|managed| package == Some(OsStr::new(managed)))
+        })
+        .collect();
+
+    if plugins.is_empty() {
+        return Ok(());
+    }
+
+    let git = require("git")?;
+    println!("Pulling native packages");
+    for plugin in plugins {
+        ctx.run_type()
+            .execute(&git)
+            .arg("-C")
+            .arg(&plugin)
+            .args(&["pull", "--ff-only"])
+            .check_run()?;
+    }
+
+    Ok(())
 }
 
-fn upgrade(vim: &Path, vimrc: &Path, ctx: &ExecutionContext) -> Result<()> {
+/// The title of a step of Neovim, with the app name unless it's the default one
+pub fn nvim_title(title: &str, appname: &str) -> String {
+    if appname == "nvim" {
+        title.to_string()
+    } else {
+        format!("{} ({})", title, appname)
+    }
+}
+
+fn upgrade(vim: &Path, vimrc: &Path, ctx: &ExecutionContext, appname: Option<&str>) -> Result<()> {
     let mut tempfile = tempfile::NamedTempFile::new()?;
     tempfile.write_all(UPGRADE_VIM.replace('\r', "").as_bytes())?;
     debug!("Wrote vim script to {:?}", tempfile.path());
@@ -56,6 +121,9 @@ fn upgrade(vim: &Path, vimrc: &Path, ctx: &ExecutionContext) -> Result<()> {
     if ctx.config().force_vim_plug_update() {
         command.env("TOPGRADE_FORCE_PLUGUPDATE", "true");
     }
+    if let Some(appname) = appname {
+        command.env("NVIM_APPNAME", appname);
+    }
 
     let output = command.output()?;
 
@@ -120,15 +188,50 @@ pub fn upgrade_vim(base_dirs: &BaseDirs, ctx: &ExecutionContext) -> Result<()> {
     let vimrc = vimrc(base_dirs)?;
 
     print_separator("Vim");
-    upgrade(&vim, &vimrc, ctx)
+    upgrade(&vim, &vimrc, ctx, None)?;
+    upgrade_native_packages(ctx, &[base_dirs.home_dir().join(".vim")])
 }
 
-pub fn upgrade_neovim(base_dirs: &BaseDirs, ctx: &ExecutionContext) -> Result<()> {
+pub fn upgrade_neovim(base_dirs: &BaseDirs, ctx: &ExecutionContext, appname: &str) -> Result<()> {
     let nvim = require("nvim")?;
-    let nvimrc = nvimrc(base_dirs)?;
+    let nvimrc = nvimrc(base_dirs, appname)?;
+
+    print_separator(nvim_title("Neovim", appname));
+    let data_dir = nvim_data_dir(base_dirs, appname);
+    if data_dir.join("lazy/lazy.nvim").exists() {
+        // lazy.nvim loads the plugins asynchronously, so it has to be run headless rather than in Ex mode
+        ctx.run_type()
+            .execute(&nvim)
+            .args(&["--headless", "+Lazy! sync", "+qa"])
+            .env("NVIM_APPNAME", appname)
+            .check_run()?;
+    } else {
+        upgrade(&nvim, &nvimrc, ctx, Some(appname))?;
+    }
+    upgrade_native_packages(ctx, &[nvim_config_dir(base_dirs, appname), data_dir.join("site")])
+}
 
-    print_separator("Neovim");
-    upgrade(&nvim, &nvimrc, ctx)
+pub fn upgrade_mason(base_dirs: &BaseDirs, ctx: &ExecutionContext, appname: &str) -> Result<()> {
+    let nvim = require("nvim")?;
+    let data_dir = nvim_data_dir(base_dirs, appname);
+    // mason.nvim installs its registries and tools there whichever plugin manager installed it, be it lazy.nvim,
+    // vim-plug or a native package
+    if !data_dir.join("mason").is_dir() {
+        return Err(SkipStep(String::from("mason.nvim has no data directory")).into());
+    }
+
+    print_separator(nvim_title("Mason", appname));
+
+    ctx.run_type()
+        .execute(&nvim)
+        .args(&[
+            "--headless",
+            "+MasonUpdate",
+            "+if exists(':MasonToolsUpdateSync') | MasonToolsUpdateSync | endif",
+            "+qa",
+        ])
+        .env("NVIM_APPNAME", appname)
+        .check_run()
 }
 
 pub fn run_voom(_base_dirs: &BaseDirs, run_type: RunType) -> Result<()> {
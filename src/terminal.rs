@@ -16,6 +16,7 @@ use notify_rust::{Notification, Timeout};
 #[cfg(windows)]
 use which_crate::which;
 
+use crate::config::NotifyOn;
 use crate::report::StepResult;
 #[cfg(target_os = "linux")]
 use crate::utils::which;
@@ -43,6 +44,24 @@ pub fn run_shell() {
         .unwrap();
 }
 
+/// Format a duration like `1h 2m 3s`, leaving out the leading zero units
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {}s", m, s),
+        (h, m, s) => format!("{}h {}m {}s", h, m, s),
+    }
+}
+
+/// The answer to whether to run a step
+pub enum StepAnswer {
+    Yes,
+    No,
+    /// Run this step and all the remaining ones without asking
+    All,
+}
+
 struct Terminal {
     width: Option<u16>,
     prefix: String,
@@ -50,6 +69,8 @@ struct Terminal {
     set_title: bool,
     display_time: bool,
     desktop_notification: bool,
+    notify_failures: bool,
+    notification_command: Option<String>,
     #[cfg(target_os = "linux")]
     notify_send: Option<PathBuf>,
 }
@@ -66,6 +87,8 @@ impl Terminal {
             set_title: true,
             display_time: true,
             desktop_notification: false,
+            notify_failures: true,
+            notification_command: None,
             #[cfg(target_os = "linux")]
             notify_send: which("notify-send"),
         }
@@ -75,6 +98,11 @@ impl Terminal {
         self.desktop_notification = desktop_notifications
     }
 
+    fn set_notifications(&mut self, notify_on: NotifyOn, notification_command: Option<&str>) {
+        self.notify_failures = notify_on != NotifyOn::Never;
+        self.notification_command = notification_command.map(String::from);
+    }
+
     fn set_title(&mut self, set_title: bool) {
         self.set_title = set_title
     }
@@ -86,6 +114,18 @@ impl Terminal {
     #[allow(unused_variables)]
     fn notify_desktop<P: AsRef<str>>(&self, message: P, timeout: Option<Duration>) {
         debug!("Desktop notification: {}", message.as_ref());
+        if let Some(command) = &self.notification_command {
+            let mut arguments = command.split_whitespace();
+            if let Some(program) = arguments.next() {
+                Command::new(program)
+                    .args(arguments)
+                    .arg(message.as_ref())
+                    .output()
+                    .ok();
+            }
+            return;
+        }
+
         cfg_if::cfg_if! {
             if #[cfg(target_os = "macos")] {
                 let mut notification = Notification::new();
@@ -188,6 +228,7 @@ impl Terminal {
                 key,
                 match result {
                     StepResult::Success => format!("{}", style("OK").bold().green()),
+                    StepResult::Changed => format!("{}", style("CHANGED").bold().cyan()),
                     StepResult::Failure => format!("{}", style("FAILED").bold().red()),
                     StepResult::Ignored => format!("{}", style("IGNORED").bold().yellow()),
                     StepResult::Skipped(reason) => format!("{}: {}", style("SKIPPED").bold().blue(), reason),
@@ -196,6 +237,50 @@ impl Terminal {
             .ok();
     }
 
+    fn print_excerpt(&mut self, excerpt: &str) {
+        for line in excerpt.lines() {
+            self.term
+                .write_fmt(format_args!("    {} {}\n", style("│").dim(), style(line).dim()))
+                .ok();
+        }
+    }
+
+    /// Print links under the summary entry, as hyperlinks (OSC 8) in terminals and with their URLs otherwise
+    fn print_links(&mut self, links: &[(String, String)]) {
+        let hyperlinks = self.term.is_term() && self.width.is_some();
+        for (text, url) in links {
+            if hyperlinks {
+                self.term
+                    .write_fmt(format_args!(
+                        "    {} \x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\\n",
+                        style("↗").dim(),
+                        url,
+                        text
+                    ))
+                    .ok();
+            } else {
+                self.term.write_fmt(format_args!("    {}: {}\n", text, url)).ok();
+            }
+        }
+    }
+
+    fn print_reboot_required(&mut self, reasons: &[String]) {
+        self.term
+            .write_fmt(format_args!(
+                "\nReboot required: {}\n",
+                if reasons.is_empty() {
+                    style("no").bold().green()
+                } else {
+                    style("yes").bold().yellow()
+                }
+            ))
+            .ok();
+
+        for reason in reasons {
+            self.term.write_fmt(format_args!("  {}\n", reason)).ok();
+        }
+    }
+
     #[allow(dead_code)]
     fn prompt_yesno(&mut self, question: &str) -> Result<bool, io::Error> {
         self.term
@@ -223,7 +308,9 @@ impl Terminal {
             self.term.set_title("Topgrade - Awaiting user");
         }
 
-        self.notify_desktop(&format!("{} failed", step_name), None);
+        if self.notify_failures {
+            self.notify_desktop(&format!("{} failed", step_name), None);
+        }
 
         self.term
             .write_fmt(format_args!(
@@ -257,6 +344,118 @@ impl Terminal {
         answer
     }
 
+    /// Replace the current line with the message, unless the terminal is dumb
+    fn print_progress(&mut self, message: &str) {
+        if let Some(width) = self.width {
+            let message: String = message.chars().take(width.saturating_sub(1) as usize).collect();
+            self.term.clear_line().ok();
+            self.term.write_str(&message).ok();
+        }
+    }
+
+    fn clear_progress(&mut self) {
+        if self.width.is_some() {
+            self.term.clear_line().ok();
+        }
+    }
+
+    fn ask_step(&mut self, step_name: &str) -> Result<StepAnswer, io::Error> {
+        if self.width.is_none() {
+            return Ok(StepAnswer::Yes);
+        }
+
+        self.term
+            .write_fmt(format_args!(
+                "{}",
+                style(format!("{}Run {}? (Y)es/(n)o/(a)ll/(q)uit", self.prefix, step_name))
+                    .yellow()
+                    .bold()
+            ))
+            .ok();
+
+        let answer = loop {
+            match self.term.read_key()? {
+                Key::Char('y') | Key::Char('Y') | Key::Enter => break StepAnswer::Yes,
+                Key::Char('n') | Key::Char('N') => break StepAnswer::No,
+                Key::Char('a') | Key::Char('A') => break StepAnswer::All,
+                Key::Char('q') | Key::Char('Q') => return Err(io::Error::from(io::ErrorKind::Interrupted)),
+                _ => (),
+            }
+        };
+
+        self.term.write_str("\n").ok();
+
+        Ok(answer)
+    }
+
+    /// Show a checkbox list of the given items and return which of them were selected.
+    ///
+    /// All items are selected initially. Dumb terminals get the initial selection without prompting.
+    fn select_items<S: AsRef<str>>(&mut self, title: &str, items: &[S]) -> Result<Vec<bool>, io::Error> {
+        let mut selected = vec![true; items.len()];
+        if self.width.is_none() || items.is_empty() {
+            return Ok(selected);
+        }
+
+        let page_size = self
+            .term
+            .size_checked()
+            .map(|(rows, _)| (rows as usize).saturating_sub(3).max(1))
+            .unwrap_or(20)
+            .min(items.len());
+        let mut cursor = 0;
+        let mut first = 0;
+
+        self.term
+            .write_fmt(format_args!(
+                "{}\n{}\n",
+                style(title).yellow().bold(),
+                style("(space) toggle, (a) toggle all, (enter) confirm, (q) quit").dim()
+            ))
+            .ok();
+
+        loop {
+            if cursor < first {
+                first = cursor;
+            } else if cursor >= first + page_size {
+                first = cursor + 1 - page_size;
+            }
+
+            for (index, item) in items.iter().enumerate().skip(first).take(page_size) {
+                let line = format!(
+                    "{} [{}] {}",
+                    if index == cursor { ">" } else { " " },
+                    if selected[index] { "x" } else { " " },
+                    item.as_ref()
+                );
+                if index == cursor {
+                    self.term.write_line(&format!("{}", style(line).bold()))?;
+                } else {
+                    self.term.write_line(&line)?;
+                }
+            }
+
+            match self.term.read_key()? {
+                Key::ArrowUp | Key::Char('k') => cursor = cursor.checked_sub(1).unwrap_or(items.len() - 1),
+                Key::ArrowDown | Key::Char('j') => cursor = (cursor + 1) % items.len(),
+                Key::Char(' ') => selected[cursor] = !selected[cursor],
+                Key::Char('a') | Key::Char('A') => {
+                    let all_selected = selected.iter().all(|s| *s);
+                    selected.iter_mut().for_each(|s| *s = !all_selected);
+                }
+                Key::Enter => break,
+                Key::Escape | Key::Char('q') | Key::Char('Q') => {
+                    return Err(io::Error::from(io::ErrorKind::Interrupted))
+                }
+                _ => (),
+            }
+
+            self.term.clear_last_lines(page_size)?;
+        }
+
+        Ok(selected)
+    }
+
     fn get_char(&self) -> Result<Key, io::Error> {
         self.term.read_key()
     }
@@ -272,6 +471,18 @@ pub fn should_retry(interrupted: bool, step_name: &str) -> Result<bool, io::Erro
     TERMINAL.lock().unwrap().should_retry(interrupted, step_name)
 }
 
+pub fn ask_step(step_name: &str) -> Result<StepAnswer, io::Error> {
+    TERMINAL.lock().unwrap().ask_step(step_name)
+}
+
+pub fn print_progress<P: AsRef<str>>(message: P) {
+    TERMINAL.lock().unwrap().print_progress(message.as_ref())
+}
+
+pub fn clear_progress() {
+    TERMINAL.lock().unwrap().clear_progress()
+}
+
 pub fn print_separator<P: AsRef<str>>(message: P) {
     TERMINAL.lock().unwrap().print_separator(message)
 }
@@ -290,11 +501,28 @@ pub fn print_result<P: AsRef<str>>(key: P, result: &StepResult) {
     TERMINAL.lock().unwrap().print_result(key, result)
 }
 
+/// Print the lines of output indented under a result of the summary
+pub fn print_excerpt(excerpt: &str) {
+    TERMINAL.lock().unwrap().print_excerpt(excerpt)
+}
+
+pub fn print_links(links: &[(String, String)]) {
+    TERMINAL.lock().unwrap().print_links(links)
+}
+
+pub fn print_reboot_required(reasons: &[String]) {
+    TERMINAL.lock().unwrap().print_reboot_required(reasons)
+}
+
 /// Tells whether the terminal is dumb.
 pub fn is_dumb() -> bool {
     TERMINAL.lock().unwrap().width.is_none()
 }
 
+pub fn select_items<S: AsRef<str>>(title: &str, items: &[S]) -> Result<Vec<bool>, io::Error> {
+    TERMINAL.lock().unwrap().select_items(title, items)
+}
+
 pub fn get_key() -> Result<Key, io::Error> {
     TERMINAL.lock().unwrap().get_char()
 }
@@ -303,6 +531,13 @@ pub fn set_title(set_title: bool) {
     TERMINAL.lock().unwrap().set_title(set_title);
 }
 
+pub fn set_notifications(notify_on: NotifyOn, notification_command: Option<&str>) {
+    TERMINAL
+        .lock()
+        .unwrap()
+        .set_notifications(notify_on, notification_command);
+}
+
 pub fn set_desktop_notifications(desktop_notifications: bool) {
     TERMINAL
         .lock()
@@ -0,0 +1,140 @@
+//! A status icon showing the progress of the run, for runs which aren't watched in a terminal.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use ksni::{Status, ToolTip, Tray, TrayService};
+use lazy_static::lazy_static;
+
+use crate::ctrlc;
+
+/// How long the outcome is shown for if it isn't clicked away
+const DISMISS_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+lazy_static! {
+    static ref HANDLE: Mutex<Option<ksni::Handle<TopgradeTray>>> = Mutex::new(None);
+}
+
+#[derive(Default)]
+struct TopgradeTray {
+    current_step: String,
+    completed: usize,
+    failed: usize,
+    /// Whether the run failed, once it's over
+    outcome: Option<bool>,
+    /// Set when the final state was clicked away
+    dismissed: Arc<(Mutex<bool>, Condvar)>,
+    /// Whether a StatusNotifier host, which shows the icon, is running
+    shown: Arc<AtomicBool>,
+}
+
+impl Tray for TopgradeTray {
+    fn id(&self) -> String {
+        String::from("topgrade")
+    }
+
+    fn title(&self) -> String {
+        String::from("Topgrade")
+    }
+
+    fn icon_name(&self) -> String {
+        String::from(match self.outcome {
+            None => "system-software-update",
+            Some(false) => "emblem-ok-symbolic",
+            Some(true) => "dialog-error",
+        })
+    }
+
+    fn status(&self) -> Status {
+        match self.outcome {
+            Some(true) => Status::NeedsAttention,
+            _ => Status::Active,
+        }
+    }
+
+    fn tool_tip(&self) -> ToolTip {
+        let description = match self.outcome {
+            None => format!(
+                "{}\n{} steps done, {} failed",
+                self.current_step, self.completed, self.failed
+            ),
+            Some(false) => String::from("Finished successfully. Click to dismiss"),
+            Some(true) => format!("Finished with {} failed steps. Click to dismiss", self.failed),
+        };
+
+        ToolTip {
+            title: String::from("Topgrade"),
+            description,
+            ..Default::default()
+        }
+    }
+
+    fn watcher_online(&self) {
+        self.shown.store(true, Ordering::SeqCst);
+    }
+
+    fn watcher_offine(&self) -> bool {
+        self.shown.store(false, Ordering::SeqCst);
+        true
+    }
+
+    fn activate(&mut self, _x: i32, _y: i32) {
+        if self.outcome.is_some() {
+            let (dismissed, condvar) = &*self.dismissed;
+            *dismissed.lock().unwrap() = true;
+            condvar.notify_all();
+        }
+    }
+}
+
+/// Show the icon for the rest of the run
+pub fn show() {
+    let service = TrayService::new(TopgradeTray::default());
+    *HANDLE.lock().unwrap() = Some(service.handle());
+    service.spawn();
+}
+
+fn update<F: FnOnce(&mut TopgradeTray)>(f: F) {
+    if let Some(handle) = HANDLE.lock().unwrap().as_ref() {
+        handle.update(f);
+    }
+}
+
+pub fn step_started(name: &str) {
+    update(|tray| tray.current_step = name.to_string());
+}
+
+pub fn step_finished(failed: bool) {
+    update(|tray| {
+        tray.completed += 1;
+        if failed {
+            tray.failed += 1;
+        }
+    });
+}
+
+/// Show the outcome of the run and wait until it's clicked away, Topgrade is interrupted, or the timeout passes.
+/// Nothing is waited for if no icon can be shown, as in sessions without a StatusNotifier host.
+pub fn finish(failed: bool) {
+    let handle = match HANDLE.lock().unwrap().take() {
+        Some(handle) => handle,
+        None => return,
+    };
+
+    let (dismissed, shown) = handle.update(|tray| {
+        tray.outcome = Some(failed);
+        (tray.dismissed.clone(), tray.shown.clone())
+    });
+
+    if shown.load(Ordering::SeqCst) {
+        let (dismissed, condvar) = &*dismissed;
+        let start = Instant::now();
+        let mut guard = dismissed.lock().unwrap();
+        while !*guard && !ctrlc::interrupted() && start.elapsed() < DISMISS_TIMEOUT {
+            // Interruptions don't notify the condition variable, so it's checked every second
+            guard = condvar.wait_timeout(guard, Duration::from_secs(1)).unwrap().0;
+        }
+    }
+
+    handle.shutdown();
+}
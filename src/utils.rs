@@ -1,4 +1,4 @@
-use crate::error::{SkipStep, TopgradeError};
+use crate::error::{MissingBinary, SkipStep, TopgradeError};
 use anyhow::Result;
 
 use log::{debug, error};
@@ -102,7 +102,16 @@ pub fn which<T: AsRef<OsStr> + Debug>(binary_name: T) -> Option<PathBuf> {
     }
 }
 
-pub fn sudo() -> Option<PathBuf> {
+/// Find the program used for elevation, preferring the configured `elevation_command`
+pub fn sudo(elevation_command: Option<&str>) -> Option<PathBuf> {
+    if let Some(command) = elevation_command {
+        let path = which(command);
+        if path.is_none() {
+            error!("The configured elevation command {} was not found", command);
+        }
+        return path;
+    }
+
     which("doas")
         .or_else(|| which("sudo"))
         .or_else(|| which("gsudo"))
@@ -125,7 +134,9 @@ pub fn require<T: AsRef<OsStr> + Debug>(binary_name: T) -> Result<PathBuf> {
         }
         Err(e) => match e {
             which_crate::Error::CannotFindBinaryPath => {
-                Err(SkipStep(format!("Cannot find {:?} in PATH", &binary_name)).into())
+                let missing = MissingBinary(binary_name.as_ref().to_string_lossy().into_owned());
+                let reason = missing.to_string();
+                Err(anyhow::Error::new(missing).context(SkipStep(reason)))
             }
             _ => {
                 panic!("Detecting {:?} failed: {}", &binary_name, e);
@@ -134,6 +145,12 @@ pub fn require<T: AsRef<OsStr> + Debug>(binary_name: T) -> Result<PathBuf> {
     }
 }
 
+/// Format an option of the curl configuration file. Options fed to curl with `--config -` don't show up in the list
+/// of processes, unlike its arguments.
+pub fn curl_option(name: &str, value: &str) -> String {
+    format!("{} = \"{}\"", name, value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
 #[allow(dead_code)]
 pub fn require_option<T>(option: Option<T>, cause: String) -> Result<T> {
     if let Some(value) = option {
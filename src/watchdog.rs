@@ -0,0 +1,120 @@
+//! Detection of commands which stopped producing output, which usually means that they hang on the network. The
+//! output is only seen when it's piped through Topgrade, otherwise commands are timed from their start.
+use std::process::Child;
+#[cfg(unix)]
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+use log::debug;
+
+use crate::terminal::{format_duration, print_warning};
+
+/// How often the watchdog checks the output
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Settings {
+    timeout: Duration,
+    /// Kill stalled commands instead of warning about them
+    unattended: bool,
+}
+
+lazy_static! {
+    static ref SETTINGS: Mutex<Option<Settings>> = Mutex::new(None);
+    static ref LAST_OUTPUT: Mutex<Instant> = Mutex::new(Instant::now());
+}
+
+/// Watch commands from now on
+pub fn enable(timeout: Duration, unattended: bool) {
+    *SETTINGS.lock().unwrap() = Some(Settings { timeout, unattended });
+}
+
+/// Whether commands are watched
+pub fn enabled() -> bool {
+    SETTINGS.lock().unwrap().is_some()
+}
+
+/// Record that the running command produced output
+pub fn output_seen() {
+    *LAST_OUTPUT.lock().unwrap() = Instant::now();
+}
+
+/// Watches a running command until it's dropped
+pub struct Watchdog {
+    done: Arc<AtomicBool>,
+    killed: Arc<AtomicBool>,
+    timeout: Duration,
+}
+
+impl Watchdog {
+    /// Start watching the command. Returns `None` if commands aren't watched.
+    pub fn start(name: String, child: Arc<Mutex<Child>>) -> Option<Self> {
+        let (timeout, unattended) = SETTINGS
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|settings| (settings.timeout, settings.unattended))?;
+
+        output_seen();
+        let done = Arc::new(AtomicBool::new(false));
+        let killed = Arc::new(AtomicBool::new(false));
+
+        let watchdog = Watchdog {
+            done: done.clone(),
+            killed: killed.clone(),
+            timeout,
+        };
+
+        thread::spawn(move || {
+            while !done.load(Ordering::Relaxed) {
+                thread::sleep(POLL_INTERVAL);
+                if LAST_OUTPUT.lock().unwrap().elapsed() < timeout || done.load(Ordering::Relaxed) {
+                    continue;
+                }
+
+                let message = format!("{} produced no output for {}", name, format_duration(timeout));
+                // The command may be waiting for an answer, so the terminal is left to it and to the user
+                if !unattended {
+                    print_warning(format!("\n{}. Press Ctrl+C to stop it", message));
+                    // Wait for another period before warning again
+                    output_seen();
+                    continue;
+                }
+
+                print_warning(format!("{}. Killing it", message));
+                let mut child = child.lock().unwrap();
+                // Shells running custom commands would leave the stalled command running
+                #[cfg(unix)]
+                Command::new("pkill")
+                    .args(&["-P", &child.id().to_string()])
+                    .status()
+                    .ok();
+                if let Err(e) = child.kill() {
+                    debug!("Failed killing {}: {}", name, e);
+                }
+                killed.store(true, Ordering::Relaxed);
+                break;
+            }
+        });
+
+        Some(watchdog)
+    }
+
+    /// The duration without output after which the command was killed, if it was
+    pub fn killed_after(&self) -> Option<Duration> {
+        if self.killed.load(Ordering::Relaxed) {
+            Some(self.timeout)
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.done.store(true, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,147 @@
+//! The setup wizard writing the configuration file on the first run.
+use std::io;
+
+use crate::config::{Step, EXAMPLE_CONFIG};
+use crate::terminal::{print_info, prompt_yesno, select_items};
+use crate::utils::which;
+
+/// A step with the programs telling that it applies to this system. Steps without programs are always offered.
+type Probe = (Step, &'static [&'static str]);
+
+/// The steps offered by the wizard, by category
+const CATEGORIES: &[(&str, &[Probe])] = &[
+    (
+        "System",
+        &[
+            (Step::System, &[]),
+            (Step::Firmware, &["fwupdmgr"]),
+            (Step::Flatpak, &["flatpak"]),
+            (Step::Snap, &["snap"]),
+            (Step::BrewFormula, &["brew"]),
+            (Step::Nix, &["nix"]),
+            (Step::HomeManager, &["home-manager"]),
+            (Step::Chocolatey, &["choco"]),
+            (Step::Scoop, &["scoop"]),
+            (Step::Winget, &["winget"]),
+            (Step::Macports, &["port"]),
+            (Step::Mas, &["mas"]),
+        ],
+    ),
+    (
+        "Languages",
+        &[
+            (Step::Rustup, &["rustup"]),
+            (Step::Cargo, &["cargo-install-update"]),
+            (Step::Pip3, &["pip3"]),
+            (Step::Pipx, &["pipx"]),
+            (Step::Conda, &["conda"]),
+            (Step::Node, &["npm"]),
+            (Step::Deno, &["deno"]),
+            (Step::Gem, &["gem"]),
+            (Step::Go, &["go"]),
+            (Step::Composer, &["composer"]),
+            (Step::Dotnet, &["dotnet"]),
+            (Step::Opam, &["opam"]),
+            (Step::Stack, &["stack"]),
+            (Step::Flutter, &["flutter"]),
+            (Step::Asdf, &["asdf"]),
+        ],
+    ),
+    (
+        "Editors",
+        &[
+            (Step::Vim, &["vim", "nvim"]),
+            (Step::Emacs, &["emacs"]),
+            (Step::Vscode, &["code", "codium"]),
+            (Step::Kakoune, &["kak"]),
+            (Step::Micro, &["micro"]),
+            (
+                Step::LanguageServers,
+                &["rust-analyzer", "clangd", "lua-language-server"],
+            ),
+        ],
+    ),
+    (
+        "Version control",
+        &[
+            (Step::GitRepos, &["git"]),
+            (Step::GithubCliExtensions, &["gh"]),
+            (Step::PreCommit, &["pre-commit"]),
+            (Step::Hg, &["hg"]),
+            (Step::Svn, &["svn"]),
+            (Step::Fossil, &["fossil"]),
+        ],
+    ),
+    (
+        "Shell and dotfiles",
+        &[
+            (Step::Tmux, &["tmux"]),
+            (Step::Terminals, &["alacritty", "kitty", "wezterm"]),
+            (Step::Chezmoi, &["chezmoi"]),
+            (Step::Yadm, &["yadm"]),
+            (Step::Myrepos, &["mr"]),
+            (Step::Tldr, &["tldr"]),
+        ],
+    ),
+    (
+        "Containers and cloud",
+        &[
+            (Step::Containers, &["docker", "podman"]),
+            (Step::Toolbx, &["toolbox"]),
+            (Step::Vagrant, &["vagrant"]),
+            (Step::Gcloud, &["gcloud"]),
+            (Step::AzureCli, &["az"]),
+            (Step::Aws, &["aws"]),
+        ],
+    ),
+];
+
+/// Write a boolean option with its explanation
+fn push_option(contents: &mut String, comment: &str, name: &str, value: bool) {
+    contents.push_str(&format!("# {}\n{} = {}\n\n", comment, name, value));
+}
+
+/// Probe the system for the steps which apply to it, let the user disable the unwanted ones and ask about the
+/// common options. Returns the contents of the configuration file.
+pub fn run() -> Result<String, io::Error> {
+    print_info("Topgrade can create a configuration for this system. Deselect the steps which shouldn't run.\n");
+
+    let mut disabled: Vec<String> = Vec::new();
+    for (category, steps) in CATEGORIES {
+        let detected: Vec<Step> = steps
+            .iter()
+            .filter(|(_, programs)| programs.is_empty() || programs.iter().any(|program| which(program).is_some()))
+            .map(|(step, _)| *step)
+            .collect();
+        let names: Vec<&str> = detected.iter().map(|step| step.as_ref()).collect();
+
+        let selection = select_items(&format!("Detected steps: {}", category), &names)?;
+        disabled.extend(names.iter().zip(selection).filter_map(|(name, selected)| {
+            if selected {
+                None
+            } else {
+                Some(format!("\"{}\"", name))
+            }
+        }));
+    }
+
+    let assume_yes = prompt_yesno("Run upgrades without asking for confirmations?")?;
+    println!();
+    let cleanup = prompt_yesno("Clean up old versions and caches after upgrading?")?;
+    println!();
+
+    let mut contents = String::from(
+        "# Created by the Topgrade setup wizard. The options below the wizard's ones are the defaults.\n\n",
+    );
+    push_option(&mut contents, "Don't ask for confirmations", "assume_yes", assume_yes);
+    push_option(&mut contents, "Cleanup temporary or old files", "cleanup", cleanup);
+    if !disabled.is_empty() {
+        contents.push_str(&format!(
+            "# Steps deselected in the setup wizard\ndisable = [{}]\n\n",
+            disabled.join(", ")
+        ));
+    }
+    contents.push_str(EXAMPLE_CONFIG);
+
+    Ok(contents)
+}